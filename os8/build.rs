@@ -1,6 +1,20 @@
+use std::{env, fs, path::Path};
+
 static TARGET_PATH: &str = "../user/target/riscv64gc-unknown-none-elf/release/";
 
+/// kernel symbol table dumped by the Makefile after the previous build
+static KSYMS_PATH: &str = "os.sym";
+
 fn main() {
     println!("cargo:rerun-if-changed=../user/src/");
     println!("cargo:rerun-if-changed={}", TARGET_PATH);
+    println!("cargo:rerun-if-changed={}", KSYMS_PATH);
+    embed_kernel_symbols();
+}
+
+/// copy the symbol table into OUT_DIR for `ksyms.rs`, an empty table if there is none yet
+fn embed_kernel_symbols() {
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("kernel.sym");
+    let symbols = fs::read(KSYMS_PATH).unwrap_or_default();
+    fs::write(out, symbols).unwrap();
 }
@@ -10,5 +10,10 @@ pub const MAX_SYSCALL_NUM: usize = 500;
 
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
 pub const TRAP_CONTEXT: usize = TRAMPOLINE - PAGE_SIZE;
+/// page holding the instructions displaced by uprobes, far below the trap contexts of all threads
+pub const UPROBE_XOL: usize = TRAP_CONTEXT - 0x1000_0000;
+/// end of the lower half of Sv39 addresses, areas mapped by `sys_mmap` lie below it,
+/// far from `UPROBE_XOL`, `TRAP_CONTEXT` and `TRAMPOLINE` in the upper half
+pub const USER_SPACE_END: usize = 1 << 38;
 pub const CLOCK_FREQ: usize = 12500000;
 pub const MMIO: &[(usize, usize)] = &[(0x10001000, 0x1000)];
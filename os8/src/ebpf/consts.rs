@@ -5,6 +5,7 @@
 //! 1. BPF commands 
 //! 2. BPF map types
 //! 3. eBPF LLVM relocations
+//! 4. eBPF program types and load flags
 //! 
 //! refer to <https://www.kernel.org/doc/html/latest/bpf/llvm_reloc.html>
 //! and <https://github.com/libbpf/libbpf> for details
@@ -25,9 +26,22 @@ numeric_enum! {
         BPF_MAP_DELETE_ELEM = 3,
         BPF_MAP_GET_NEXT_KEY = 4,
         BPF_PROG_LOAD = 5,
+        BPF_OBJ_PIN = 6,
+        BPF_OBJ_GET = 7,
         BPF_PROG_ATTACH = 8,
         BPF_PROG_DETACH = 9,
+        BPF_PROG_GET_NEXT_ID = 11,
+        BPF_MAP_GET_NEXT_ID = 12,
+        BPF_PROG_GET_FD_BY_ID = 13,
+        BPF_MAP_GET_FD_BY_ID = 14,
+        BPF_OBJ_GET_INFO_BY_FD = 15,
+        BPF_MAP_LOOKUP_AND_DELETE_ELEM = 21,
+        BPF_MAP_FREEZE = 22,
+        BPF_MAP_LOOKUP_BATCH = 24,
+        BPF_MAP_UPDATE_BATCH = 26,
+        BPF_MAP_DELETE_BATCH = 27,
         BPF_PROG_LOAD_EX = 1000,
+        BPF_RINGBUF_READ = 1001,
     }
 }
 
@@ -40,6 +54,18 @@ pub const BPF_MAP_TYPE_HASH: u32 = 1;
 pub const BPF_MAP_TYPE_ARRAY: u32 = 2;
 /// eBPF map types
 pub const BPF_MAP_TYPE_PROG_ARRAY: u32 = 3;
+/// eBPF map types
+pub const BPF_MAP_TYPE_STACK_TRACE: u32 = 7;
+/// eBPF map types
+pub const BPF_MAP_TYPE_LRU_HASH: u32 = 9;
+/// eBPF map types
+pub const BPF_MAP_TYPE_LPM_TRIE: u32 = 11;
+/// eBPF map types
+pub const BPF_MAP_TYPE_QUEUE: u32 = 22;
+/// eBPF map types
+pub const BPF_MAP_TYPE_STACK: u32 = 23;
+/// eBPF map types
+pub const BPF_MAP_TYPE_RINGBUF: u32 = 27;
 
 /// eBPF LLVM relocations
 pub const R_BPF_NONE: u32 = 0;
@@ -63,3 +89,51 @@ pub const BPF_EXIST: u64 = 2;
 /// eBPF map operation flags
 pub const BPF_F_LOCK: u64 = 4;
 
+/// eBPF map creation flags, programs can only read the map, like the one of `.rodata`
+pub const BPF_F_RDONLY_PROG: u32 = 1 << 7;
+/// eBPF map creation flags, back an array with whole frames that user space can mmap
+pub const BPF_F_MMAPABLE: u32 = 1 << 10;
+
+/// bpf_get_stackid flags, number of frames to skip
+pub const BPF_F_SKIP_FIELD_MASK: u64 = 0xff;
+/// bpf_get_stackid flags, walk the user stack instead of the kernel stack
+pub const BPF_F_USER_STACK: u64 = 1 << 8;
+/// bpf_get_stackid flags, replace a different stack with the same hash
+pub const BPF_F_REUSE_STACKID: u64 = 1 << 10;
+/// maximum depth of a stack trace, kernel stacks are only 8KiB
+pub const BPF_MAX_STACK_DEPTH: usize = 64;
+
+/// eBPF program types, loaded as `BPF_PROG_TYPE_KPROBE`
+pub const BPF_PROG_TYPE_UNSPEC: u32 = 0;
+/// eBPF program types, attached to kprobes, kretprobes and uprobes
+pub const BPF_PROG_TYPE_KPROBE: u32 = 2;
+/// eBPF program types, attached to syscall tracepoints
+pub const BPF_PROG_TYPE_TRACEPOINT: u32 = 5;
+/// eBPF program types, attached to perf events
+pub const BPF_PROG_TYPE_PERF_EVENT: u32 = 7;
+/// eBPF program types, custom, installed with `seccomp` to filter the syscalls of a process
+pub const BPF_PROG_TYPE_SYSCALL_FILTER: u32 = 1000;
+
+/// seccomp operations, the only one supported
+pub const SECCOMP_SET_MODE_FILTER: usize = 1;
+/// syscall filter return values, follows the linux convection.
+/// the action is in the high 16 bits, its data in the low 16 bits
+pub const SECCOMP_RET_KILL: u32 = 0x0000_0000;
+/// syscall filter return values, fail the syscall with the errno in the data
+pub const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+/// syscall filter return values
+pub const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+/// syscall filter return values, masks
+pub const SECCOMP_RET_ACTION_FULL: u32 = 0xffff_0000;
+/// syscall filter return values, masks
+pub const SECCOMP_RET_DATA: u32 = 0x0000_ffff;
+
+/// `src` of `LD_IMM64`, the immediate is a map fd
+pub const BPF_PSEUDO_MAP_FD: u32 = 1;
+/// `src` of `LD_IMM64`, the immediate is a map fd, the next immediate an offset into its value
+pub const BPF_PSEUDO_MAP_VALUE: u32 = 2;
+/// `src` of `BPF_CALL`, a BPF-to-BPF call, the immediate is relative to the next instruction
+pub const BPF_PSEUDO_CALL: u32 = 1;
+
+/// eBPF program load flags, skip the JIT and run the program in the interpreter
+pub const BPF_F_INTERPRETER: u32 = 1 << 16;
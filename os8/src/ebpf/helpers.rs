@@ -12,42 +12,184 @@ use alloc::string::ToString;
 
 use super::{
     retcode::*,
-    osutil::*, map::{bpf_map_lookup_elem, bpf_map_update_elem, bpf_map_delete_elem},
+    osutil::*, map::{bpf_map_lookup_helper, bpf_map_update_elem, bpf_map_delete_elem},
+    program::bpf_tail_call_prepare,
+    map::{bpf_ringbuf_output, bpf_ringbuf_reserve, bpf_ringbuf_commit, bpf_ringbuf_query},
+    map::bpf_stack_map_get_stackid,
+    map::{bpf_map_push_helper, bpf_map_pop_helper},
+    tracepoints::bpf_ctx_get_stack,
+    consts::{BPF_F_SKIP_FIELD_MASK, BPF_F_USER_STACK, BPF_F_REUSE_STACKID, BPF_MAX_STACK_DEPTH},
 };
 
 /// follow linux convention
 pub type BpfHelperFn = fn(u64, u64, u64, u64, u64) -> i64;
 
-pub const HELPER_FN_COUNT: usize = 17;
+/// helper function ids, follow linux convention
+pub const BPF_FUNC_MAP_LOOKUP_ELEM: usize = 1;
+pub const BPF_FUNC_MAP_UPDATE_ELEM: usize = 2;
+pub const BPF_FUNC_MAP_DELETE_ELEM: usize = 3;
+pub const BPF_FUNC_PROBE_READ: usize = 4;
+pub const BPF_FUNC_KTIME_GET_NS: usize = 5;
+pub const BPF_FUNC_TRACE_PRINTK: usize = 6;
+pub const BPF_FUNC_GET_PRANDOM_U32: usize = 7;
+pub const BPF_FUNC_GET_SMP_PROCESSOR_ID: usize = 8;
+pub const BPF_FUNC_TAIL_CALL: usize = 12;
+pub const BPF_FUNC_GET_CURRENT_PID_TGID: usize = 14;
+pub const BPF_FUNC_GET_CURRENT_UID_GID: usize = 15;
+pub const BPF_FUNC_GET_CURRENT_COMM: usize = 16;
+pub const BPF_FUNC_GET_STACKID: usize = 27;
+pub const BPF_FUNC_PROBE_READ_STR: usize = 45;
+pub const BPF_FUNC_MAP_PUSH_ELEM: usize = 87;
+pub const BPF_FUNC_MAP_POP_ELEM: usize = 88;
+pub const BPF_FUNC_MAP_PEEK_ELEM: usize = 89;
+pub const BPF_FUNC_PROBE_READ_USER: usize = 112;
+pub const BPF_FUNC_PROBE_READ_KERNEL: usize = 113;
+pub const BPF_FUNC_PROBE_READ_USER_STR: usize = 114;
+pub const BPF_FUNC_PROBE_READ_KERNEL_STR: usize = 115;
+pub const BPF_FUNC_RINGBUF_OUTPUT: usize = 130;
+pub const BPF_FUNC_RINGBUF_RESERVE: usize = 131;
+pub const BPF_FUNC_RINGBUF_SUBMIT: usize = 132;
+pub const BPF_FUNC_RINGBUF_DISCARD: usize = 133;
+pub const BPF_FUNC_RINGBUF_QUERY: usize = 134;
+
+pub const HELPER_FN_COUNT: usize = 135;
 
 /// use static to make address never change
-/// some function are still in progress, they are redirect to NOP
-pub static HELPER_FN_TABLE: [BpfHelperFn; HELPER_FN_COUNT] = [
-    bpf_helper_nop,
-    bpf_helper_map_lookup_elem,
-    bpf_helper_map_update_elem,
-    bpf_helper_map_delete_elem,
-    bpf_helper_nop, //bpf_helper_probe_read
-    bpf_helper_ktime_get_ns,
-    bpf_helper_trace_printk,
-    bpf_helper_get_prandom_u32,
-    bpf_helper_get_smp_processor_id,
-    bpf_helper_nop, // bpf_skb_store_bytes
-    bpf_helper_nop, // bpf_l3_csum_replace
-    bpf_helper_nop, // bpf_l4_csum_replace
-    bpf_helper_nop, // bpf_tail_call
-    bpf_helper_nop, // bpf_clone_redirect
-    bpf_helper_get_current_pid_tgid,
-    bpf_helper_nop, // bpf_get_current_uid_gid
-    bpf_helper_get_current_comm,
-];
-
-
-/// wrapper function to call bpf_map_lookup_elem with from_user = false
-fn bpf_helper_map_lookup_elem(fd: u64, key: u64, value: u64, _4: u64, _5: u64) -> i64 {
-    match bpf_map_lookup_elem(fd as u32, key as *const u8, value as *mut u8, 0, false) {
+/// helpers not listed here are redirect to NOP
+pub static HELPER_FN_TABLE: [BpfHelperFn; HELPER_FN_COUNT] = {
+    let mut table = [bpf_helper_nop as BpfHelperFn; HELPER_FN_COUNT];
+    table[BPF_FUNC_MAP_LOOKUP_ELEM] = bpf_helper_map_lookup_elem;
+    table[BPF_FUNC_MAP_UPDATE_ELEM] = bpf_helper_map_update_elem;
+    table[BPF_FUNC_MAP_DELETE_ELEM] = bpf_helper_map_delete_elem;
+    table[BPF_FUNC_PROBE_READ] = bpf_helper_probe_read;
+    table[BPF_FUNC_KTIME_GET_NS] = bpf_helper_ktime_get_ns;
+    table[BPF_FUNC_TRACE_PRINTK] = bpf_helper_trace_printk;
+    table[BPF_FUNC_GET_PRANDOM_U32] = bpf_helper_get_prandom_u32;
+    table[BPF_FUNC_GET_SMP_PROCESSOR_ID] = bpf_helper_get_smp_processor_id;
+    table[BPF_FUNC_TAIL_CALL] = bpf_helper_tail_call;
+    table[BPF_FUNC_GET_CURRENT_PID_TGID] = bpf_helper_get_current_pid_tgid;
+    table[BPF_FUNC_GET_CURRENT_UID_GID] = bpf_helper_get_current_uid_gid;
+    table[BPF_FUNC_GET_CURRENT_COMM] = bpf_helper_get_current_comm;
+    table[BPF_FUNC_GET_STACKID] = bpf_helper_get_stackid;
+    table[BPF_FUNC_PROBE_READ_STR] = bpf_helper_probe_read_str;
+    table[BPF_FUNC_MAP_PUSH_ELEM] = bpf_helper_map_push_elem;
+    table[BPF_FUNC_MAP_POP_ELEM] = bpf_helper_map_pop_elem;
+    table[BPF_FUNC_MAP_PEEK_ELEM] = bpf_helper_map_peek_elem;
+    table[BPF_FUNC_PROBE_READ_USER] = bpf_helper_probe_read_user;
+    table[BPF_FUNC_PROBE_READ_KERNEL] = bpf_helper_probe_read_kernel;
+    table[BPF_FUNC_PROBE_READ_USER_STR] = bpf_helper_probe_read_user_str;
+    table[BPF_FUNC_PROBE_READ_KERNEL_STR] = bpf_helper_probe_read_kernel_str;
+    table[BPF_FUNC_RINGBUF_OUTPUT] = bpf_helper_ringbuf_output;
+    table[BPF_FUNC_RINGBUF_RESERVE] = bpf_helper_ringbuf_reserve;
+    table[BPF_FUNC_RINGBUF_SUBMIT] = bpf_helper_ringbuf_submit;
+    table[BPF_FUNC_RINGBUF_DISCARD] = bpf_helper_ringbuf_discard;
+    table[BPF_FUNC_RINGBUF_QUERY] = bpf_helper_ringbuf_query;
+    table
+};
+
+/// argument types of helper functions, checked by the verifier
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BpfArgType {
+    /// unused argument
+    DontCare,
+    /// any initialized value
+    Anything,
+    /// the context pointer passed to the program
+    PtrToCtx,
+    /// a constant map fd
+    ConstMapFd,
+    /// readable memory holding a key of the map in the first argument
+    PtrToMapKey,
+    /// readable memory holding a value of the map in the first argument
+    PtrToMapValue,
+    /// writable memory for a value of the map in the first argument
+    PtrToUninitMapValue,
+    /// readable memory, size is given by the following `ConstSize` argument
+    PtrToMem,
+    /// writable memory, size is given by the following `ConstSize` argument
+    PtrToUninitMem,
+    /// size of the previous memory argument, must have a known upper bound
+    ConstSize,
+    /// size of the memory to allocate, must be a constant
+    ConstAllocSize,
+    /// memory returned by an allocating helper, released by this call
+    PtrToAllocMem,
+}
+
+/// return types of helper functions, checked by the verifier
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BpfRetType {
+    Integer,
+    /// pointer to a value of the map in the first argument, or NULL
+    MapValueOrNull,
+    /// memory of the size in the `ConstAllocSize` argument, or NULL,
+    /// must be released before the program exits
+    AllocMemOrNull,
+}
+
+/// prototype of a helper function
+#[derive(Clone, Copy, Debug)]
+pub struct BpfHelperProto {
+    pub ret: BpfRetType,
+    pub args: [BpfArgType; 5],
+}
+
+const fn proto(ret: BpfRetType, args: &[BpfArgType]) -> Option<BpfHelperProto> {
+    let mut full = [BpfArgType::DontCare; 5];
+    let mut i = 0;
+    while i < args.len() {
+        full[i] = args[i];
+        i += 1;
+    }
+    Some(BpfHelperProto { ret, args: full })
+}
+
+use BpfArgType::*;
+use BpfRetType::*;
+
+/// prototypes indexed the same as `HELPER_FN_TABLE`
+/// helpers redirected to NOP have no prototype and are rejected by the verifier
+pub static HELPER_PROTO_TABLE: [Option<BpfHelperProto>; HELPER_FN_COUNT] = {
+    let mut table = [None; HELPER_FN_COUNT];
+    table[BPF_FUNC_MAP_LOOKUP_ELEM] = proto(MapValueOrNull, &[ConstMapFd, PtrToMapKey]);
+    table[BPF_FUNC_MAP_UPDATE_ELEM] = proto(Integer, &[ConstMapFd, PtrToMapKey, PtrToMapValue, Anything]);
+    table[BPF_FUNC_MAP_DELETE_ELEM] = proto(Integer, &[ConstMapFd, PtrToMapKey]);
+    table[BPF_FUNC_PROBE_READ] = proto(Integer, &[PtrToUninitMem, ConstSize, Anything]);
+    table[BPF_FUNC_KTIME_GET_NS] = proto(Integer, &[]);
+    table[BPF_FUNC_TRACE_PRINTK] = proto(Integer, &[PtrToMem, ConstSize]);
+    table[BPF_FUNC_GET_PRANDOM_U32] = proto(Integer, &[]);
+    table[BPF_FUNC_GET_SMP_PROCESSOR_ID] = proto(Integer, &[]);
+    table[BPF_FUNC_TAIL_CALL] = proto(Integer, &[PtrToCtx, ConstMapFd, Anything]);
+    table[BPF_FUNC_GET_CURRENT_PID_TGID] = proto(Integer, &[]);
+    table[BPF_FUNC_GET_CURRENT_UID_GID] = proto(Integer, &[]);
+    table[BPF_FUNC_GET_CURRENT_COMM] = proto(Integer, &[PtrToUninitMem, ConstSize]);
+    table[BPF_FUNC_GET_STACKID] = proto(Integer, &[PtrToCtx, ConstMapFd, Anything]);
+    table[BPF_FUNC_PROBE_READ_STR] = proto(Integer, &[PtrToUninitMem, ConstSize, Anything]);
+    table[BPF_FUNC_MAP_PUSH_ELEM] = proto(Integer, &[ConstMapFd, PtrToMapValue, Anything]);
+    table[BPF_FUNC_MAP_POP_ELEM] = proto(Integer, &[ConstMapFd, PtrToUninitMapValue]);
+    table[BPF_FUNC_MAP_PEEK_ELEM] = proto(Integer, &[ConstMapFd, PtrToUninitMapValue]);
+    table[BPF_FUNC_PROBE_READ_USER] = proto(Integer, &[PtrToUninitMem, ConstSize, Anything]);
+    table[BPF_FUNC_PROBE_READ_KERNEL] = proto(Integer, &[PtrToUninitMem, ConstSize, Anything]);
+    table[BPF_FUNC_PROBE_READ_USER_STR] = proto(Integer, &[PtrToUninitMem, ConstSize, Anything]);
+    table[BPF_FUNC_PROBE_READ_KERNEL_STR] = proto(Integer, &[PtrToUninitMem, ConstSize, Anything]);
+    table[BPF_FUNC_RINGBUF_OUTPUT] = proto(Integer, &[ConstMapFd, PtrToMem, ConstSize, Anything]);
+    table[BPF_FUNC_RINGBUF_RESERVE] = proto(AllocMemOrNull, &[ConstMapFd, ConstAllocSize, Anything]);
+    table[BPF_FUNC_RINGBUF_SUBMIT] = proto(Integer, &[PtrToAllocMem, Anything]);
+    table[BPF_FUNC_RINGBUF_DISCARD] = proto(Integer, &[PtrToAllocMem, Anything]);
+    table[BPF_FUNC_RINGBUF_QUERY] = proto(Integer, &[ConstMapFd, Anything]);
+    table
+};
+
+/// void *bpf_map_lookup_elem(struct bpf_map *map, const void *key)
+/// return the address of the value in the map, or NULL if the key is absent
+///
+/// the ABI follows linux now: programs used to pass a buffer in r3 that the value was
+/// copied into, and got 0 or -1 back. the returned pointer is checked against NULL
+/// before use, then reads and writes go to the map directly
+fn bpf_helper_map_lookup_elem(fd: u64, key: u64, _3: u64, _4: u64, _5: u64) -> i64 {
+    match bpf_map_lookup_helper(fd as u32, key as *const u8) {
         Ok(val) => val as i64,
-        Err(_) => -1
+        Err(_) => 0
     }
 }
 
@@ -67,6 +209,33 @@ fn bpf_helper_map_delete_elem(fd: u64, key: u64, _3: u64, _4: u64, _5: u64) -> i
     }
 }
 
+/// long bpf_map_push_elem(struct bpf_map *map, const void *value, u64 flags)
+/// push a value into a queue or stack, `BPF_EXIST` drops the oldest value of a full map
+fn bpf_helper_map_push_elem(fd: u64, value: u64, flags: u64, _4: u64, _5: u64) -> i64 {
+    match bpf_map_push_helper(fd as u32, value as *const u8, flags) {
+        Ok(val) => val as i64,
+        Err(_) => -1
+    }
+}
+
+/// long bpf_map_pop_elem(struct bpf_map *map, void *value)
+/// pop the oldest value of a queue or the newest of a stack
+fn bpf_helper_map_pop_elem(fd: u64, value: u64, _3: u64, _4: u64, _5: u64) -> i64 {
+    match bpf_map_pop_helper(fd as u32, value as *mut u8, true) {
+        Ok(val) => val as i64,
+        Err(_) => -1
+    }
+}
+
+/// long bpf_map_peek_elem(struct bpf_map *map, void *value)
+/// like bpf_map_pop_elem, without removing the value
+fn bpf_helper_map_peek_elem(fd: u64, value: u64, _3: u64, _4: u64, _5: u64) -> i64 {
+    match bpf_map_pop_helper(fd as u32, value as *mut u8, false) {
+        Ok(val) => val as i64,
+        Err(_) => -1
+    }
+}
+
 fn bpf_helper_nop(_1: u64, _2: u64, _3: u64, _4: u64, _5: u64) -> i64 {
     0
 }
@@ -94,9 +263,10 @@ fn bpf_helper_trace_printk(fmt: u64, fmt_size: u64, p1: u64, p2: u64, p3: u64) -
     0 // TODO: return number of bytes written
 }
 
-/// not implemented
+/// u32 bpf_get_prandom_u32(void)
+/// uses os_get_random_u32 in `osutils.rs`, not suitable for cryptography
 fn bpf_helper_get_prandom_u32(_1: u64, _2: u64, _3: u64, _4: u64, _5: u64) -> i64 {
-    todo!()
+    os_get_random_u32() as i64
 }
 
 /// calls os_get_current_cpu
@@ -104,28 +274,177 @@ fn bpf_helper_get_smp_processor_id(_1: u64, _2: u64, _3: u64, _4: u64, _5: u64)
     os_get_current_cpu() as i64
 }
 
-/// calls thread.get_pid()
+/// long bpf_tail_call(void *ctx, struct bpf_map *prog_array_map, u32 index)
+/// on success the calling program stops and the target runs with the same ctx,
+/// see `BpfProgram::run`, on failure the caller goes on
+fn bpf_helper_tail_call(_ctx: u64, fd: u64, index: u64, _4: u64, _5: u64) -> i64 {
+    match bpf_tail_call_prepare(fd as u32, index as u32) {
+        Ok(_) => 0,
+        Err(_) => -1
+    }
+}
+
+/// u64 bpf_get_current_pid_tgid(void)
+/// return the pid in the upper 32 bits and the tid in the lower 32 bits
 fn bpf_helper_get_current_pid_tgid(_1: u64, _2: u64, _3: u64, _4: u64, _5: u64) -> i64 {
     let thread = os_current_thread();
     let pid = thread.get_pid();
-    // NOTE: tgid is the same with pid
-    ((pid << 32) | pid) as i64
+    let tid = thread.get_tid();
+    ((pid << 32) | (tid & 0xffffffff)) as i64
+}
+
+/// u64 bpf_get_current_uid_gid(void)
+/// return the gid in the upper 32 bits and the uid in the lower 32 bits
+fn bpf_helper_get_current_uid_gid(_1: u64, _2: u64, _3: u64, _4: u64, _5: u64) -> i64 {
+    let thread = os_current_thread();
+    (((thread.get_gid() as u64) << 32) | thread.get_uid() as u64) as i64
 }
 
-/// get current thread name
+/// long bpf_get_current_comm(void *buf, u32 size_of_buf)
+/// copy the executable name of the current process, truncated to `buf_size - 1` bytes,
+/// the rest of the buffer is zeroed
 fn bpf_helper_get_current_comm(dst: u64, buf_size: u64, _1: u64, _2: u64, _3: u64) -> i64 {
+    let buf_size = buf_size as u32 as usize;
+    if buf_size == 0 {
+        return -1;
+    }
     let thread = os_current_thread();
-    let dst_ptr = dst as *mut u8;
     let name = thread.get_name();
-    let name_ptr = name.as_bytes();
-    let len = name.len();
-    if len > buf_size as usize {
+    let len = name.len().min(buf_size - 1);
+    let dst_slice = unsafe { core::slice::from_raw_parts_mut(dst as *mut u8, buf_size) };
+    dst_slice.fill(0);
+    dst_slice[..len].copy_from_slice(&name.as_bytes()[..len]);
+    0
+}
+
+/// long bpf_get_stackid(void *ctx, struct bpf_map *map, u64 flags)
+/// walk the kernel stack, or the user stack with `BPF_F_USER_STACK`, skip the innermost
+/// `flags & BPF_F_SKIP_FIELD_MASK` frames and store it into a stack trace map
+/// return the stack id
+fn bpf_helper_get_stackid(ctx: u64, fd: u64, flags: u64, _4: u64, _5: u64) -> i64 {
+    if flags & !(BPF_F_SKIP_FIELD_MASK | BPF_F_USER_STACK | BPF_F_REUSE_STACKID) != 0 {
         return -1;
     }
-    unsafe {
-        let dst_slice = core::slice::from_raw_parts_mut(dst_ptr, len);
-        dst_slice.copy_from_slice(name_ptr);
-        *dst_ptr.add(len) = 0;
+    let mut ips = [0u64; BPF_MAX_STACK_DEPTH];
+    let skip = (flags & BPF_F_SKIP_FIELD_MASK) as usize;
+    let nr = bpf_ctx_get_stack(ctx as *const u8, flags & BPF_F_USER_STACK != 0, &mut ips);
+    if skip >= nr {
+        return -1;
+    }
+    match bpf_stack_map_get_stackid(fd as u32, &ips[skip..nr], flags) {
+        Ok(id) => id as i64,
+        Err(_) => -1
+    }
+}
+
+/// long bpf_ringbuf_output(void *ringbuf, void *data, u64 size, u64 flags)
+/// copy `size` bytes of `data` into a new record
+fn bpf_helper_ringbuf_output(fd: u64, data: u64, size: u64, _flags: u64, _5: u64) -> i64 {
+    match bpf_ringbuf_output(fd as u32, data as *const u8, size as usize) {
+        Ok(_) => 0,
+        Err(_) => -1
+    }
+}
+
+/// void *bpf_ringbuf_reserve(void *ringbuf, u64 size, u64 flags)
+/// return the address of a new record, or NULL if the buffer is full
+fn bpf_helper_ringbuf_reserve(fd: u64, size: u64, _flags: u64, _4: u64, _5: u64) -> i64 {
+    match bpf_ringbuf_reserve(fd as u32, size as usize) {
+        Ok(addr) => addr as i64,
+        Err(_) => 0
+    }
+}
+
+/// void bpf_ringbuf_submit(void *data, u64 flags)
+/// make a reserved record visible to user space
+fn bpf_helper_ringbuf_submit(data: u64, _flags: u64, _3: u64, _4: u64, _5: u64) -> i64 {
+    match bpf_ringbuf_commit(data as *mut u8, false) {
+        Ok(_) => 0,
+        Err(_) => -1
+    }
+}
+
+/// void bpf_ringbuf_discard(void *data, u64 flags)
+/// drop a reserved record, user space never sees it
+fn bpf_helper_ringbuf_discard(data: u64, _flags: u64, _3: u64, _4: u64, _5: u64) -> i64 {
+    match bpf_ringbuf_commit(data as *mut u8, true) {
+        Ok(_) => 0,
+        Err(_) => -1
+    }
+}
+
+/// u64 bpf_ringbuf_query(void *ringbuf, u64 flags)
+fn bpf_helper_ringbuf_query(fd: u64, flags: u64, _3: u64, _4: u64, _5: u64) -> i64 {
+    bpf_ringbuf_query(fd as u32, flags).unwrap_or(0) as i64
+}
+
+/// address space a probe read looks into
+#[derive(Clone, Copy, PartialEq)]
+enum ProbeSpace {
+    Kernel,
+    User,
+    /// the legacy helpers try the kernel space, then the user space
+    Any,
+}
+
+/// # probe_read
+/// copy `size` bytes at `src` into `dst`, see `os_probe_read`
+/// # return value
+/// * 0, or the length including the null byte for strings
+/// * -1 if `src` is not readable, `dst` is zeroed then
+fn probe_read(dst: u64, size: u64, src: u64, space: ProbeSpace, is_str: bool) -> i64 {
+    let dst = dst as *mut u8;
+    let size = size as u32 as usize;
+    if size == 0 {
+        return 0;
+    }
+    let read = |user| os_probe_read(dst, src as usize, size, user, is_str);
+    let copied = match space {
+        ProbeSpace::Kernel => read(false),
+        ProbeSpace::User => read(true),
+        ProbeSpace::Any => read(false).or_else(|| read(true)),
+    };
+    let dst = unsafe { core::slice::from_raw_parts_mut(dst, size) };
+    match copied {
+        Some(len) if is_str => {
+            // truncated strings are still null terminated
+            dst[len - 1] = 0;
+            len as i64
+        }
+        Some(_) => 0,
+        None => {
+            dst.fill(0);
+            -1
+        }
     }
-    len as i64
+}
+
+/// long bpf_probe_read(void *dst, u32 size, const void *unsafe_ptr)
+fn bpf_helper_probe_read(dst: u64, size: u64, src: u64, _4: u64, _5: u64) -> i64 {
+    probe_read(dst, size, src, ProbeSpace::Any, false)
+}
+
+/// long bpf_probe_read_str(void *dst, u32 size, const void *unsafe_ptr)
+fn bpf_helper_probe_read_str(dst: u64, size: u64, src: u64, _4: u64, _5: u64) -> i64 {
+    probe_read(dst, size, src, ProbeSpace::Any, true)
+}
+
+/// long bpf_probe_read_user(void *dst, u32 size, const void *unsafe_ptr)
+fn bpf_helper_probe_read_user(dst: u64, size: u64, src: u64, _4: u64, _5: u64) -> i64 {
+    probe_read(dst, size, src, ProbeSpace::User, false)
+}
+
+/// long bpf_probe_read_kernel(void *dst, u32 size, const void *unsafe_ptr)
+fn bpf_helper_probe_read_kernel(dst: u64, size: u64, src: u64, _4: u64, _5: u64) -> i64 {
+    probe_read(dst, size, src, ProbeSpace::Kernel, false)
+}
+
+/// long bpf_probe_read_user_str(void *dst, u32 size, const void *unsafe_ptr)
+fn bpf_helper_probe_read_user_str(dst: u64, size: u64, src: u64, _4: u64, _5: u64) -> i64 {
+    probe_read(dst, size, src, ProbeSpace::User, true)
+}
+
+/// long bpf_probe_read_kernel_str(void *dst, u32 size, const void *unsafe_ptr)
+fn bpf_helper_probe_read_kernel_str(dst: u64, size: u64, src: u64, _4: u64, _5: u64) -> i64 {
+    probe_read(dst, size, src, ProbeSpace::Kernel, true)
 }
@@ -0,0 +1,149 @@
+//! eBPF instruction encoding
+//!
+//!
+//! opcode fields and a decoded form of the 64-bit eBPF instruction,
+//! shared by the interpreter and the verifier
+//!
+//! refer to <https://www.kernel.org/doc/html/latest/bpf/instruction-set.html>
+
+/// instruction classes
+pub const BPF_LD: u8 = 0x00;
+pub const BPF_LDX: u8 = 0x01;
+pub const BPF_ST: u8 = 0x02;
+pub const BPF_STX: u8 = 0x03;
+pub const BPF_ALU: u8 = 0x04;
+pub const BPF_JMP: u8 = 0x05;
+pub const BPF_JMP32: u8 = 0x06;
+pub const BPF_ALU64: u8 = 0x07;
+
+/// load / store sizes
+pub const BPF_W: u8 = 0x00;
+pub const BPF_H: u8 = 0x08;
+pub const BPF_B: u8 = 0x10;
+pub const BPF_DW: u8 = 0x18;
+
+/// load / store modes
+pub const BPF_IMM: u8 = 0x00;
+pub const BPF_ABS: u8 = 0x20;
+pub const BPF_IND: u8 = 0x40;
+pub const BPF_MEM: u8 = 0x60;
+pub const BPF_MEMSX: u8 = 0x80;
+pub const BPF_ATOMIC: u8 = 0xc0;
+
+/// operand source
+pub const BPF_K: u8 = 0x00;
+pub const BPF_X: u8 = 0x08;
+
+/// ALU operations
+pub const BPF_ADD: u8 = 0x00;
+pub const BPF_SUB: u8 = 0x10;
+pub const BPF_MUL: u8 = 0x20;
+pub const BPF_DIV: u8 = 0x30;
+pub const BPF_OR: u8 = 0x40;
+pub const BPF_AND: u8 = 0x50;
+pub const BPF_LSH: u8 = 0x60;
+pub const BPF_RSH: u8 = 0x70;
+pub const BPF_NEG: u8 = 0x80;
+pub const BPF_MOD: u8 = 0x90;
+pub const BPF_XOR: u8 = 0xa0;
+pub const BPF_MOV: u8 = 0xb0;
+pub const BPF_ARSH: u8 = 0xc0;
+pub const BPF_END: u8 = 0xd0;
+
+/// byte swap direction, shares the bit with `BPF_X`
+pub const BPF_TO_LE: u8 = 0x00;
+pub const BPF_TO_BE: u8 = 0x08;
+
+/// jump operations
+pub const BPF_JA: u8 = 0x00;
+pub const BPF_JEQ: u8 = 0x10;
+pub const BPF_JGT: u8 = 0x20;
+pub const BPF_JGE: u8 = 0x30;
+pub const BPF_JSET: u8 = 0x40;
+pub const BPF_JNE: u8 = 0x50;
+pub const BPF_JSGT: u8 = 0x60;
+pub const BPF_JSGE: u8 = 0x70;
+pub const BPF_CALL: u8 = 0x80;
+pub const BPF_EXIT: u8 = 0x90;
+pub const BPF_JLT: u8 = 0xa0;
+pub const BPF_JLE: u8 = 0xb0;
+pub const BPF_JSLT: u8 = 0xc0;
+pub const BPF_JSLE: u8 = 0xd0;
+
+/// atomic operations, stored in `imm`
+pub const BPF_FETCH: i32 = 0x01;
+pub const BPF_XCHG: i32 = 0xe0 | BPF_FETCH;
+pub const BPF_CMPXCHG: i32 = 0xf0 | BPF_FETCH;
+
+/// the wide instruction `dst = imm64`, takes two slots
+pub const BPF_LD_IMM64: u8 = BPF_LD | BPF_IMM | BPF_DW;
+
+/// number of registers, r10 is the read-only frame pointer
+pub const BPF_REG_COUNT: usize = 11;
+pub const BPF_REG_FP: u8 = 10;
+
+/// a decoded eBPF instruction
+#[derive(Clone, Copy, Debug)]
+pub struct BpfInsn {
+    pub code: u8,
+    pub dst: u8,
+    pub src: u8,
+    pub off: i16,
+    pub imm: i32,
+}
+
+impl BpfInsn {
+    /// instructions are stored little endian:
+    /// opcode:8 dst:4 src:4 off:16 imm:32
+    pub fn decode(raw: u64) -> Self {
+        Self {
+            code: raw as u8,
+            dst: (raw >> 8) as u8 & 0xf,
+            src: (raw >> 12) as u8 & 0xf,
+            off: (raw >> 16) as u16 as i16,
+            imm: (raw >> 32) as u32 as i32,
+        }
+    }
+
+    pub fn encode(&self) -> u64 {
+        (self.code as u64)
+            | ((self.dst as u64 & 0xf) << 8)
+            | ((self.src as u64 & 0xf) << 12)
+            | ((self.off as u16 as u64) << 16)
+            | ((self.imm as u32 as u64) << 32)
+    }
+
+    pub fn class(&self) -> u8 {
+        self.code & 0x07
+    }
+
+    /// operation of ALU and JMP classes
+    pub fn op(&self) -> u8 {
+        self.code & 0xf0
+    }
+
+    /// operand source of ALU and JMP classes
+    pub fn source(&self) -> u8 {
+        self.code & 0x08
+    }
+
+    /// access size of load and store classes
+    pub fn size(&self) -> u8 {
+        self.code & 0x18
+    }
+
+    /// addressing mode of load and store classes
+    pub fn mode(&self) -> u8 {
+        self.code & 0xe0
+    }
+
+    /// access size in bytes
+    pub fn size_bytes(&self) -> usize {
+        match self.size() {
+            BPF_B => 1,
+            BPF_H => 2,
+            BPF_W => 4,
+            _ => 8,
+        }
+    }
+}
@@ -0,0 +1,367 @@
+//! eBPF interpreter
+//!
+//!
+//! executes eBPF bytecode one instruction at a time
+//!
+//! used when a program is loaded without JIT, or to cross-check the JIT.
+//! memory accesses are performed as-is, so programs must be trusted or verified
+
+use super::{
+    insn::*,
+    consts::BPF_PSEUDO_CALL,
+    helpers::{BpfHelperFn, BPF_FUNC_TAIL_CALL},
+    retcode::BpfErrorCode::{self, *},
+};
+
+/// stack size given to each program, same as the one passed to the JIT
+///
+/// the frames of BPF-to-BPF calls are carved out of it
+pub const BPF_STACK_SIZE: usize = 512;
+
+/// maximum depth of BPF-to-BPF calls, including the entry function, follows linux
+pub const MAX_CALL_FRAMES: usize = 8;
+
+/// registers of a caller saved across a BPF-to-BPF call
+#[derive(Clone, Copy, Default)]
+struct CallFrame {
+    ret_pc: usize,
+    /// r6 - r9 and the frame pointer
+    regs: [u64; 5],
+}
+
+/// # interpret
+/// run `insns` with `ctx` in r1
+/// # arguments
+/// * insns - the eBPF instructions, already relocated
+/// * helpers - helper function table, indexed by the `imm` of call instructions
+/// * ctx - context pointer passed to the program
+/// # BPF-to-BPF calls
+/// the `off` of a call marked with `BPF_PSEUDO_CALL` holds the stack depth of the caller,
+/// filled in by the loader after verification. the frame of the callee starts below it
+/// # return value
+/// * r0 when the program exits, or EINVAL on malformed instructions
+pub fn interpret(insns: &[u64], helpers: &[BpfHelperFn], ctx: *const u8) -> Result<u64, BpfErrorCode> {
+    // u64 elements keep the stack 8-byte aligned
+    let mut stack = [0u64; BPF_STACK_SIZE / 8];
+    let mut reg = [0u64; BPF_REG_COUNT];
+    reg[1] = ctx as u64;
+    reg[BPF_REG_FP as usize] = stack.as_mut_ptr() as u64 + BPF_STACK_SIZE as u64;
+    let mut frames = [CallFrame::default(); MAX_CALL_FRAMES - 1];
+    let mut depth = 0;
+
+    let mut pc: usize = 0;
+    loop {
+        let insn = BpfInsn::decode(*insns.get(pc).ok_or(EINVAL)?);
+        pc += 1;
+        let dst = insn.dst as usize;
+        let src = insn.src as usize;
+        if dst >= BPF_REG_COUNT || src >= BPF_REG_COUNT {
+            return Err(EINVAL);
+        }
+
+        match insn.class() {
+            BPF_ALU64 => {
+                let operand = match insn.source() {
+                    BPF_X => reg[src],
+                    _ => insn.imm as i64 as u64,
+                };
+                reg[dst] = alu64(&insn, reg[dst], operand)?;
+            }
+            BPF_ALU => {
+                let operand = match insn.source() {
+                    BPF_X => reg[src] as u32,
+                    _ => insn.imm as u32,
+                };
+                reg[dst] = alu32(&insn, reg[dst], operand)?;
+            }
+            BPF_JMP | BPF_JMP32 => match insn.op() {
+                BPF_JA => {
+                    // the 32-bit variant (gotol) keeps its offset in imm
+                    let off = match insn.class() {
+                        BPF_JMP => insn.off as i64,
+                        _ => insn.imm as i64,
+                    };
+                    pc = jump_target(pc, off, insns.len())?;
+                }
+                BPF_CALL if insn.src as u32 == BPF_PSEUDO_CALL => {
+                    if insn.class() != BPF_JMP {
+                        return Err(EINVAL);
+                    }
+                    let frame = frames.get_mut(depth).ok_or(EINVAL)?;
+                    let fp = reg[BPF_REG_FP as usize].wrapping_sub(insn.off as u16 as u64);
+                    if fp < stack.as_ptr() as u64 {
+                        return Err(EINVAL);
+                    }
+                    frame.ret_pc = pc;
+                    frame.regs.copy_from_slice(&reg[6..=BPF_REG_FP as usize]);
+                    depth += 1;
+                    reg[BPF_REG_FP as usize] = fp;
+                    pc = jump_target(pc, insn.imm as i64, insns.len())?;
+                }
+                BPF_CALL => {
+                    if insn.class() != BPF_JMP || insn.src != 0 {
+                        return Err(EINVAL);
+                    }
+                    let helper = helpers.get(insn.imm as u32 as usize).ok_or(EINVAL)?;
+                    reg[0] = helper(reg[1], reg[2], reg[3], reg[4], reg[5]) as u64;
+                    // a taken tail call leaves the program, the caller runs the target
+                    if insn.imm as u32 as usize == BPF_FUNC_TAIL_CALL && reg[0] == 0 {
+                        return Ok(0);
+                    }
+                }
+                BPF_EXIT => {
+                    if insn.class() != BPF_JMP {
+                        return Err(EINVAL);
+                    }
+                    if depth == 0 {
+                        return Ok(reg[0]);
+                    }
+                    // return to the caller, r0 is the return value
+                    depth -= 1;
+                    let frame = &frames[depth];
+                    reg[6..=BPF_REG_FP as usize].copy_from_slice(&frame.regs);
+                    pc = frame.ret_pc;
+                }
+                _ => {
+                    let operand = match insn.source() {
+                        BPF_X => reg[src],
+                        _ => insn.imm as i64 as u64,
+                    };
+                    let taken = match insn.class() {
+                        BPF_JMP => condition(insn.op(), reg[dst], operand, false)?,
+                        _ => condition(insn.op(), reg[dst] as u32 as u64, operand as u32 as u64, true)?,
+                    };
+                    if taken {
+                        pc = jump_target(pc, insn.off as i64, insns.len())?;
+                    }
+                }
+            },
+            BPF_LD => {
+                if insn.code != BPF_LD_IMM64 {
+                    // legacy packet access is not supported
+                    return Err(EINVAL);
+                }
+                let next = BpfInsn::decode(*insns.get(pc).ok_or(EINVAL)?);
+                pc += 1;
+                reg[dst] = (insn.imm as u32 as u64) | ((next.imm as u32 as u64) << 32);
+            }
+            BPF_LDX => {
+                let addr = reg[src].wrapping_add(insn.off as i64 as u64) as usize;
+                reg[dst] = match insn.mode() {
+                    BPF_MEM => unsafe { load(addr, insn.size()) },
+                    BPF_MEMSX => unsafe { load_signed(addr, insn.size())? },
+                    _ => return Err(EINVAL),
+                };
+            }
+            BPF_ST => {
+                if insn.mode() != BPF_MEM {
+                    return Err(EINVAL);
+                }
+                let addr = reg[dst].wrapping_add(insn.off as i64 as u64) as usize;
+                unsafe { store(addr, insn.size(), insn.imm as i64 as u64) };
+            }
+            BPF_STX => {
+                let addr = reg[dst].wrapping_add(insn.off as i64 as u64) as usize;
+                match insn.mode() {
+                    BPF_MEM => unsafe { store(addr, insn.size(), reg[src]) },
+                    BPF_ATOMIC => unsafe { atomic(&insn, addr, &mut reg)? },
+                    _ => return Err(EINVAL),
+                }
+            }
+            _ => return Err(EINVAL),
+        }
+    }
+}
+
+/// compute the instruction index after a jump, `pc` already points to the next instruction
+fn jump_target(pc: usize, off: i64, len: usize) -> Result<usize, BpfErrorCode> {
+    let target = pc as i64 + off;
+    if target < 0 || target as usize >= len {
+        return Err(EINVAL);
+    }
+    Ok(target as usize)
+}
+
+/// 64-bit ALU operations
+pub(super) fn alu64(insn: &BpfInsn, dst: u64, src: u64) -> Result<u64, BpfErrorCode> {
+    // `off` = 1 selects the signed variant of div and mod
+    let signed = insn.off == 1;
+    let result = match insn.op() {
+        BPF_ADD => dst.wrapping_add(src),
+        BPF_SUB => dst.wrapping_sub(src),
+        BPF_MUL => dst.wrapping_mul(src),
+        BPF_DIV => match (src, signed) {
+            (0, _) => 0,
+            (_, false) => dst / src,
+            (_, true) => (dst as i64).wrapping_div(src as i64) as u64,
+        },
+        BPF_MOD => match (src, signed) {
+            (0, _) => dst,
+            (_, false) => dst % src,
+            (_, true) => (dst as i64).wrapping_rem(src as i64) as u64,
+        },
+        BPF_OR => dst | src,
+        BPF_AND => dst & src,
+        BPF_XOR => dst ^ src,
+        BPF_LSH => dst.wrapping_shl(src as u32 & 63),
+        BPF_RSH => dst.wrapping_shr(src as u32 & 63),
+        BPF_ARSH => (dst as i64).wrapping_shr(src as u32 & 63) as u64,
+        BPF_NEG => (dst as i64).wrapping_neg() as u64,
+        BPF_MOV => match insn.off {
+            // movsx, sign extend from a narrower source
+            8 => src as i8 as i64 as u64,
+            16 => src as i16 as i64 as u64,
+            32 => src as i32 as i64 as u64,
+            _ => src,
+        },
+        // unconditional byte swap
+        BPF_END => match insn.imm {
+            16 => (dst as u16).swap_bytes() as u64,
+            32 => (dst as u32).swap_bytes() as u64,
+            64 => dst.swap_bytes(),
+            _ => return Err(EINVAL),
+        },
+        _ => return Err(EINVAL),
+    };
+    Ok(result)
+}
+
+/// 32-bit ALU operations, the result is zero extended into 64 bits
+pub(super) fn alu32(insn: &BpfInsn, dst: u64, src: u32) -> Result<u64, BpfErrorCode> {
+    let signed = insn.off == 1;
+    let lhs = dst as u32;
+    let result = match insn.op() {
+        BPF_ADD => lhs.wrapping_add(src),
+        BPF_SUB => lhs.wrapping_sub(src),
+        BPF_MUL => lhs.wrapping_mul(src),
+        BPF_DIV => match (src, signed) {
+            (0, _) => 0,
+            (_, false) => lhs / src,
+            (_, true) => (lhs as i32).wrapping_div(src as i32) as u32,
+        },
+        BPF_MOD => match (src, signed) {
+            (0, _) => lhs,
+            (_, false) => lhs % src,
+            (_, true) => (lhs as i32).wrapping_rem(src as i32) as u32,
+        },
+        BPF_OR => lhs | src,
+        BPF_AND => lhs & src,
+        BPF_XOR => lhs ^ src,
+        BPF_LSH => lhs.wrapping_shl(src & 31),
+        BPF_RSH => lhs.wrapping_shr(src & 31),
+        BPF_ARSH => (lhs as i32).wrapping_shr(src & 31) as u32,
+        BPF_NEG => (lhs as i32).wrapping_neg() as u32,
+        BPF_MOV => match insn.off {
+            8 => src as i8 as i32 as u32,
+            16 => src as i16 as i32 as u32,
+            _ => src,
+        },
+        // byte order conversion works on the full 64-bit register
+        BPF_END => {
+            return match (insn.source(), insn.imm) {
+                (BPF_TO_LE, 16) => Ok(dst as u16 as u64),
+                (BPF_TO_LE, 32) => Ok(dst as u32 as u64),
+                (BPF_TO_LE, 64) => Ok(dst),
+                (BPF_TO_BE, 16) => Ok((dst as u16).swap_bytes() as u64),
+                (BPF_TO_BE, 32) => Ok((dst as u32).swap_bytes() as u64),
+                (BPF_TO_BE, 64) => Ok(dst.swap_bytes()),
+                _ => Err(EINVAL),
+            };
+        }
+        _ => return Err(EINVAL),
+    };
+    Ok(result as u64)
+}
+
+/// evaluate a conditional jump, `is32` operands are already truncated
+pub(super) fn condition(op: u8, dst: u64, src: u64, is32: bool) -> Result<bool, BpfErrorCode> {
+    let (sdst, ssrc) = match is32 {
+        true => (dst as u32 as i32 as i64, src as u32 as i32 as i64),
+        false => (dst as i64, src as i64),
+    };
+    let taken = match op {
+        BPF_JEQ => dst == src,
+        BPF_JNE => dst != src,
+        BPF_JGT => dst > src,
+        BPF_JGE => dst >= src,
+        BPF_JLT => dst < src,
+        BPF_JLE => dst <= src,
+        BPF_JSET => dst & src != 0,
+        BPF_JSGT => sdst > ssrc,
+        BPF_JSGE => sdst >= ssrc,
+        BPF_JSLT => sdst < ssrc,
+        BPF_JSLE => sdst <= ssrc,
+        _ => return Err(EINVAL),
+    };
+    Ok(taken)
+}
+
+unsafe fn load(addr: usize, size: u8) -> u64 {
+    match size {
+        BPF_B => core::ptr::read_unaligned(addr as *const u8) as u64,
+        BPF_H => core::ptr::read_unaligned(addr as *const u16) as u64,
+        BPF_W => core::ptr::read_unaligned(addr as *const u32) as u64,
+        _ => core::ptr::read_unaligned(addr as *const u64),
+    }
+}
+
+unsafe fn load_signed(addr: usize, size: u8) -> Result<u64, BpfErrorCode> {
+    let value = match size {
+        BPF_B => core::ptr::read_unaligned(addr as *const i8) as i64,
+        BPF_H => core::ptr::read_unaligned(addr as *const i16) as i64,
+        BPF_W => core::ptr::read_unaligned(addr as *const i32) as i64,
+        _ => return Err(EINVAL),
+    };
+    Ok(value as u64)
+}
+
+unsafe fn store(addr: usize, size: u8, value: u64) {
+    match size {
+        BPF_B => core::ptr::write_unaligned(addr as *mut u8, value as u8),
+        BPF_H => core::ptr::write_unaligned(addr as *mut u16, value as u16),
+        BPF_W => core::ptr::write_unaligned(addr as *mut u32, value as u32),
+        _ => core::ptr::write_unaligned(addr as *mut u64, value),
+    }
+}
+
+/// atomic read-modify-write, only word and double word sizes are allowed
+///
+/// rCore runs on a single hart with interrupts off in the kernel,
+/// so a plain read-modify-write is atomic here
+unsafe fn atomic(insn: &BpfInsn, addr: usize, reg: &mut [u64; BPF_REG_COUNT]) -> Result<(), BpfErrorCode> {
+    let size = insn.size();
+    if size != BPF_W && size != BPF_DW {
+        return Err(EINVAL);
+    }
+    let src = insn.src as usize;
+    let truncate = |v: u64| if size == BPF_W { v as u32 as u64 } else { v };
+    let old = load(addr, size);
+    let operand = truncate(reg[src]);
+    match insn.imm {
+        BPF_CMPXCHG => {
+            if old == truncate(reg[0]) {
+                store(addr, size, operand);
+            }
+            reg[0] = old;
+            return Ok(());
+        }
+        BPF_XCHG => {
+            store(addr, size, operand);
+            reg[src] = old;
+            return Ok(());
+        }
+        _ => (),
+    }
+    let new = match (insn.imm & !BPF_FETCH) as u8 {
+        BPF_ADD => old.wrapping_add(operand),
+        BPF_OR => old | operand,
+        BPF_AND => old & operand,
+        BPF_XOR => old ^ operand,
+        _ => return Err(EINVAL),
+    };
+    store(addr, size, new);
+    if insn.imm & BPF_FETCH != 0 {
+        reg[src] = old;
+    }
+    Ok(())
+}
@@ -6,8 +6,8 @@
 
 use super::{
     BpfResult,
-    retcode::BpfErrorCode::*,
-    osutil::{memcmp, copy},
+    retcode::BpfErrorCode::{self, *},
+    osutil::{memcmp, copy, os_alloc_frames, OsFrames, OS_PAGE_SIZE},
 };
 use super::internal::{
     InternalMapAttr,
@@ -20,6 +20,10 @@ use alloc::vec::Vec;
 pub struct ArrayMap {
     attr: InternalMapAttr,
     storage: Vec<u8>,
+    /// whole frames holding the values instead of `storage`, for `BPF_F_MMAPABLE`
+    frames: Option<OsFrames>,
+    /// kernel space address of the values
+    base: usize,
 }
 
 impl ArrayMap {
@@ -27,14 +31,25 @@ impl ArrayMap {
         let size = attr.max_entries * attr.value_size;
         let mut storage = Vec::with_capacity(size);
         storage.resize(size, 0u8);
-        Self { attr, storage }
+        let base = storage.as_ptr() as usize;
+        Self { attr, storage, frames: None, base }
+    }
+
+    /// the values fill whole frames, so user space can mmap them, see `bpf_map_mmap`
+    pub fn new_mmapable(attr: InternalMapAttr) -> Result<Self, BpfErrorCode> {
+        let size = attr.max_entries * attr.value_size;
+        if size == 0 {
+            return Err(EINVAL);
+        }
+        let (frames, base) = os_alloc_frames((size + OS_PAGE_SIZE - 1) / OS_PAGE_SIZE).ok_or(ENOMEM)?;
+        Ok(Self { attr, storage: Vec::new(), frames: Some(frames), base })
     }
 
     /// get element kernel space address by index
     /// needs to dereference to get the actual value
     fn get_element_addr(&self, index: usize) -> usize {
         let offset = self.attr.value_size * index;
-        self.storage.as_ptr() as usize + offset
+        self.base + offset
     }
 }
 
@@ -67,7 +82,10 @@ impl BpfMap for ArrayMap {
 
     fn next_key(&self, key: *const u8, next_key: *mut u8) -> BpfResult {
         let out = next_key as *mut u32;
-        let index = unsafe { *(key as *const u32) } as usize;
+        let index = match key.is_null() {
+            true => usize::MAX,
+            false => unsafe { *(key as *const u32) as usize },
+        };
         if index >= self.attr.max_entries {
             unsafe {
                 *out = 0u32;
@@ -89,7 +107,7 @@ impl BpfMap for ArrayMap {
         self.attr
     }
 
-    fn lookup_helper(&self, key: *const u8) -> BpfResult {
+    fn lookup_helper(&mut self, key: *const u8) -> BpfResult {
         let index = unsafe { *(key as *const u32) } as usize;
         if index >= self.attr.max_entries {
             return Err(ENOENT);
@@ -97,4 +115,15 @@ impl BpfMap for ArrayMap {
 
         Ok(self.get_element_addr(index))
     }
+
+    fn direct_value_addr(&self) -> Option<usize> {
+        match self.attr.max_entries {
+            1 => Some(self.get_element_addr(0)),
+            _ => None,
+        }
+    }
+
+    fn mmap_frames(&self) -> Option<OsFrames> {
+        self.frames.clone()
+    }
 }
@@ -1,8 +1,8 @@
 //! eBPF hash map
 //!
-//! 
-//! a hash table implementation
-//! does not differ much from traditional hash index
+//!
+//! a fixed-capacity hash table with separate chaining
+//! all elements are preallocated at creation, so helpers called in probe context never allocate
 //! assume that all pointer are in kernel space
 
 use super::{
@@ -16,71 +16,153 @@ use super::internal::{
     BpfMap,
 };
 
-use alloc::{boxed::Box,
-            collections::BTreeMap,
-            vec::Vec};
+use alloc::vec::Vec;
 use core::{slice};
 
 
 type HashCode = u32;
-type MapKey = Box<[u8]>;
-type MapValue = Box<[u8]>;
+/// index of an element in the preallocated pool
+pub(super) type ElemIndex = u32;
+
+/// end of a bucket chain or the free list
+pub(super) const NIL: ElemIndex = ElemIndex::MAX;
 
-/// hash map is internal a BTreeMap
+fn round_up(size: usize) -> usize {
+    (size + 7) & !7
+}
+
+/// hash map is an array of bucket chains over a preallocated element pool
+///
+/// each element stores the key, then the value at an 8-byte aligned offset
 pub struct HashMap {
     attr: InternalMapAttr,
-    map: BTreeMap<HashCode, Vec<(MapKey, MapValue)>>,
+    /// u64 elements keep values 8-byte aligned
+    storage: Vec<u64>,
+    /// size of an element in bytes
+    elem_size: usize,
+    /// head of the chain of each bucket, the number of buckets is a power of 2
+    buckets: Vec<ElemIndex>,
+    /// next element in the same chain, or in the free list
+    next: Vec<ElemIndex>,
+    free_head: ElemIndex,
     total_elems: usize, // total number of elements
 }
 
 
 impl HashMap {
     pub fn new(attr: InternalMapAttr) -> Self {
-        let map = BTreeMap::new();
+        let elem_size = round_up(attr.key_size) + round_up(attr.value_size);
+        let storage = alloc::vec![0u64; attr.max_entries * elem_size / 8];
+        let n_buckets = attr.max_entries.next_power_of_two();
+        // initially every element is free, chained in order
+        let next = (1..=attr.max_entries)
+            .map(|i| if i < attr.max_entries { i as ElemIndex } else { NIL })
+            .collect();
         Self {
             attr,
-            map,
+            storage,
+            elem_size,
+            buckets: alloc::vec![NIL; n_buckets],
+            next,
+            free_head: if attr.max_entries > 0 { 0 } else { NIL },
             total_elems: 0,
         }
     }
 
-    /// get hash value from what kptr points to 
+    /// FNV-1a hash of what kptr points to
     fn hash(kptr: *const u8, ksize: usize) -> HashCode {
-        let seed: HashCode = 131313;
-        let mut hash: HashCode = 0;
+        let mut hash: HashCode = 0x811c9dc5;
         for &i in unsafe { slice::from_raw_parts(kptr, ksize) } {
-            hash = hash.wrapping_mul(seed).wrapping_add(i as HashCode);
+            hash = (hash ^ i as HashCode).wrapping_mul(0x01000193);
         }
         hash
     }
 
-    /// find value by key that kptr points to
-    fn find(&self, kptr: *const u8) -> Option<&MapValue> {
-        let hashcode = HashMap::hash(kptr, self.attr.key_size);
-        if let Some(kvlist) = self.map.get(&hashcode) {
-            for kv in kvlist {
-                let len = self.attr.key_size;
-                if memcmp(kv.0.as_ptr(), kptr, len) {
-                    return Some(&kv.1)
+    fn bucket_of(&self, kptr: *const u8) -> usize {
+        HashMap::hash(kptr, self.attr.key_size) as usize & (self.buckets.len() - 1)
+    }
+
+    pub(super) fn key_addr(&self, elem: ElemIndex) -> *mut u8 {
+        let base = self.storage.as_ptr() as *mut u8;
+        unsafe { base.add(elem as usize * self.elem_size) }
+    }
+
+    pub(super) fn value_addr(&self, elem: ElemIndex) -> *mut u8 {
+        unsafe { self.key_addr(elem).add(round_up(self.attr.key_size)) }
+    }
+
+    /// find the element of key that kptr points to
+    pub(super) fn find(&self, kptr: *const u8) -> Option<ElemIndex> {
+        let mut elem = self.buckets[self.bucket_of(kptr)];
+        while elem != NIL {
+            if memcmp(self.key_addr(elem), kptr, self.attr.key_size) {
+                return Some(elem);
+            }
+            elem = self.next[elem as usize];
+        }
+        None
+    }
+
+    /// take an element from the free list and put it at the tail of its bucket,
+    /// so that elements already visited by `next_key` stay before it
+    pub(super) fn insert(&mut self, kptr: *const u8, vptr: *const u8) -> Option<ElemIndex> {
+        let elem = self.free_head;
+        if elem == NIL {
+            return None;
+        }
+        self.free_head = self.next[elem as usize];
+        self.next[elem as usize] = NIL;
+        copy(self.key_addr(elem), kptr, self.attr.key_size);
+        copy(self.value_addr(elem), vptr, self.attr.value_size);
+
+        let bucket = self.bucket_of(kptr);
+        match self.buckets[bucket] {
+            NIL => self.buckets[bucket] = elem,
+            mut tail => {
+                while self.next[tail as usize] != NIL {
+                    tail = self.next[tail as usize];
                 }
+                self.next[tail as usize] = elem;
             }
         }
+        self.total_elems += 1;
+        Some(elem)
+    }
+
+    /// unlink the element of key that kptr points to and return it to the free list
+    pub(super) fn remove(&mut self, kptr: *const u8) -> Option<ElemIndex> {
+        let bucket = self.bucket_of(kptr);
+        let mut prev = NIL;
+        let mut elem = self.buckets[bucket];
+        while elem != NIL {
+            if memcmp(self.key_addr(elem), kptr, self.attr.key_size) {
+                let next = self.next[elem as usize];
+                match prev {
+                    NIL => self.buckets[bucket] = next,
+                    _ => self.next[prev as usize] = next,
+                }
+                self.next[elem as usize] = self.free_head;
+                self.free_head = elem;
+                self.total_elems -= 1;
+                return Some(elem);
+            }
+            prev = elem;
+            elem = self.next[elem as usize];
+        }
         None
     }
 
-    /// rehash 
-    fn alloc(size: usize) -> Box<[u8]> {
-        let mut storage = Vec::with_capacity(size);
-        storage.resize(size, 0u8);
-        storage.into_boxed_slice()
+    /// first element in bucket order starting from `bucket`
+    fn first_from(&self, bucket: usize) -> Option<ElemIndex> {
+        self.buckets[bucket..].iter().copied().find(|&elem| elem != NIL)
     }
 }
 
 /// implement four operations for hashmap
 impl BpfMap for HashMap {
     fn lookup(&self, key: *const u8, value: *mut u8) -> BpfResult {
-        if let Some(mv) = self.find(key) {
-            copy(value, mv.as_ptr(), self.attr.value_size);
+        if let Some(elem) = self.find(key) {
+            copy(value, self.value_addr(elem), self.attr.value_size);
             Ok(0)
         } else {
             Err(ENOENT)
@@ -96,12 +178,10 @@ impl BpfMap for HashMap {
         }
 
         // handle different cases
-        let key_size = self.attr.key_size;
-        let value_size = self.attr.value_size;
-        if let Some(v) = self.find(key) {
+        if let Some(elem) = self.find(key) {
             match flags {
                 BPF_ANY | BPF_EXIST => {
-                    copy(v.as_ptr() as *mut u8, value, value_size);
+                    copy(self.value_addr(elem), value, self.attr.value_size);
                     Ok(0)
                 }
                 _ => Err(EEXIST), // existing entry
@@ -109,23 +189,8 @@ impl BpfMap for HashMap {
         } else {
             match flags {
                 BPF_ANY | BPF_NOEXIST => {
-                    if self.total_elems >= self.attr.max_entries {
-                        return Err(ENOMEM); // should we return something else?
-                    }
-                    // create one, copy key and value into kernel space
-                    let mut map_key = HashMap::alloc(key_size);
-                    let mut map_value = HashMap::alloc(value_size);
-                    copy(map_key.as_mut_ptr(), key, key_size);
-                    copy(map_value.as_mut_ptr(), value, value_size);
-
-                    let hashcode = HashMap::hash(key, key_size);
-                    if let Some(vec) = self.map.get_mut(&hashcode) {
-                        vec.push((map_key, map_value));
-                    } else {
-                        let vec = vec![(map_key, map_value)];
-                        self.map.insert(hashcode, vec);
-                    }
-                    self.total_elems += 1;
+                    // copy key and value into a free element
+                    self.insert(key, value).ok_or(E2BIG)?;
                     Ok(0)
                 }
                 _ => Err(ENOENT),
@@ -134,81 +199,43 @@ impl BpfMap for HashMap {
     }
 
     fn delete(&mut self, key: *const u8) -> BpfResult {
-        let hashcode = HashMap::hash(key, self.attr.key_size);
-        if let Some(kvlist) = self.map.get_mut(&hashcode) {
-            for (i, kv) in kvlist.iter().enumerate() {
-                if memcmp(kv.0.as_ptr(), key, self.attr.key_size) {
-                    let _ = kvlist.remove(i);
-                    self.total_elems -= 1;
-
-                    // remove the empty Vec to avoid problems in next_key
-                    if kvlist.is_empty() {
-                        let _ = self.map.remove(&hashcode);
-                    }
-                    return Ok(0);
-                
-                }
-            }
-        }
-        Err(ENOENT)
+        self.remove(key).map(|_| 0).ok_or(ENOENT)
     }
 
+    /// iterate in bucket order, then chain order
+    ///
+    /// a key that is not in the map restarts from the first key, like linux
     fn next_key(&self, key: *const u8, next_key: *mut u8) -> BpfResult {
         let key_size = self.attr.key_size;
-        let hashcode = HashMap::hash(key, key_size);
-
-        let get_first_key = || {
-            //returns the first valid key
-            if let Some((_, first_vec)) = self.map.first_key_value() {
-                let first_kv = first_vec.first().unwrap();
-                copy(next_key, first_kv.0.as_ptr(), key_size);
-                Ok(0)
-            } else {
-                // the hash map is empty
-                Err(ENOENT)
-            }
+        let found = match key.is_null() {
+            true => None,
+            false => self.find(key),
         };
-
-        let mut iter = self.map.range(hashcode..);
-        match iter.next() {
-            Some((_, vec)) => {
-                let mut opt_idx = None;
-                for (i, kv) in vec.iter().enumerate() {
-                    if memcmp(kv.0.as_ptr(), key, key_size) {
-                        opt_idx = Some(i);
-                        break;
+        let next = match found {
+            Some(elem) => match self.next[elem as usize] {
+                NIL => {
+                    let bucket = self.bucket_of(key);
+                    match bucket + 1 < self.buckets.len() {
+                        true => self.first_from(bucket + 1),
+                        false => None,
                     }
                 }
-                if opt_idx.is_none() {
-                    return get_first_key();
-                }
-
-                let index = opt_idx.unwrap();
-                if index < vec.len() - 1 {
-                    copy(next_key, vec[index + 1].0.as_ptr(), key_size);
-                    return Ok(0);
-                }
-
-                // move on to next entry
-                if let Some((_, next_vec)) = iter.next() {
-                    let first_kv = next_vec.first().unwrap();
-                    copy(next_key, first_kv.0.as_ptr(), key_size);
-                    Ok(0)
-                } else {
-                    Err(ENOENT)
-                }
-            }
-            None => get_first_key(),
-        }
+                next => Some(next),
+            },
+            None => self.first_from(0),
+        };
+        let elem = next.ok_or(ENOENT)?;
+        copy(next_key, self.key_addr(elem), key_size);
+        Ok(0)
     }
 
     fn get_attr(&self) -> InternalMapAttr {
         self.attr
     }
 
-    fn lookup_helper(&self, key: *const u8) -> BpfResult {
+    fn lookup_helper(&mut self, key: *const u8) -> BpfResult {
         match self.find(key) {
-            Some(map_key) => Ok(map_key.as_ptr() as usize),
+            Some(elem) => Ok(self.value_addr(elem) as usize),
             None => Err(ENOENT),
         }
     }
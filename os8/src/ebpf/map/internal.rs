@@ -6,26 +6,34 @@
 use super::{
     MapAttr,
     BpfResult,
+    retcode::BpfErrorCode::EINVAL,
 };
 
 
 use core::{slice};
 use core::slice::{from_raw_parts, from_raw_parts_mut};
-use super::osutil::{copy, memcmp};
+use super::osutil::{copy, memcmp, OsFrames};
+use super::ringbuf::RingBufMap;
+use super::prog_array::ProgArrayMap;
+use super::stack_trace::StackTraceMap;
 
 #[derive(Debug, Clone, Copy)]
 pub struct InternalMapAttr {
+    pub map_type: u32,
     pub key_size: usize,
     pub value_size: usize,
     pub max_entries: usize,
+    pub map_flags: u32,
 }
 
 impl From<MapAttr> for InternalMapAttr {
     fn from(attr: MapAttr) -> Self {
         Self {
+            map_type: attr.map_type,
             key_size: attr.key_size as usize,
             value_size: attr.value_size as usize,
             max_entries: attr.max_entries as usize,
+            map_flags: attr.map_flags,
         }
     }
 }
@@ -37,12 +45,47 @@ pub trait BpfMap {
     fn update(&mut self, key: *const u8, value: *const u8, flags: u64) -> BpfResult;
     /// delete: delete a kv by k
     fn delete(&mut self, key: *const u8) -> BpfResult;
-    /// used when iterate through hashmap
+    /// used when iterate through hashmap, a null key gets the first key
     fn next_key(&self, key: *const u8, next_key: *mut u8) -> BpfResult;
     fn get_attr(&self) -> InternalMapAttr;
 
     // this lookup is intended for the helper function
-    fn lookup_helper(&self, key: *const u8) -> BpfResult;
+    fn lookup_helper(&mut self, key: *const u8) -> BpfResult;
+
+    /// address of the value of a single-element array, programs access it directly
+    fn direct_value_addr(&self) -> Option<usize> {
+        None
+    }
+
+    /// only ring buffers support the ringbuf helpers
+    fn as_ringbuf(&mut self) -> Option<&mut RingBufMap> {
+        None
+    }
+
+    /// only program arrays hold tail call targets
+    fn as_prog_array(&mut self) -> Option<&mut ProgArrayMap> {
+        None
+    }
+
+    /// only stack trace maps store stacks for `bpf_get_stackid`
+    fn as_stack_trace(&mut self) -> Option<&mut StackTraceMap> {
+        None
+    }
+
+    /// only arrays created with `BPF_F_MMAPABLE` are backed by frames user space can map
+    fn mmap_frames(&self) -> Option<OsFrames> {
+        None
+    }
+
+    /// only queues and stacks support push, pop and peek
+    fn push(&mut self, _value: *const u8, _flags: u64) -> BpfResult {
+        Err(EINVAL)
+    }
+
+    /// pop, or peek if not `delete`
+    fn pop(&mut self, _value: *mut u8, _delete: bool) -> BpfResult {
+        Err(EINVAL)
+    }
 }
 
 
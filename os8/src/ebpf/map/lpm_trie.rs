@@ -0,0 +1,376 @@
+//! eBPF longest prefix match trie
+//!
+//!
+//! the key is `struct { u32 prefixlen; u8 data[]; }`, data is in network byte order
+//! lookups return the value of the longest stored prefix matching the key,
+//! updates and deletes work on the exact prefix
+//!
+//! a binary trie with path compression, like linux. nodes without a value are
+//! intermediate nodes joining two subtrees. every insertion takes at most a leaf and
+//! an intermediate node, so the pool holds twice `max_entries` nodes, preallocated
+//! so that helpers never allocate
+//! assume that all pointer are in kernel space
+
+use super::{
+    BpfResult,
+    consts::*,
+    retcode::BpfErrorCode::*,
+    osutil::copy,
+};
+use super::internal::{
+    InternalMapAttr,
+    BpfMap,
+};
+
+use alloc::vec::Vec;
+use core::slice;
+
+type NodeIndex = u32;
+
+/// no node
+const NIL: NodeIndex = NodeIndex::MAX;
+/// size of `prefixlen` in the key
+const LPM_PREFIXLEN_SIZE: usize = 4;
+/// longest data of a key, linux allows 256 bytes
+pub const LPM_DATA_SIZE_MAX: usize = 256;
+
+fn round_up(size: usize) -> usize {
+    (size + 7) & !7
+}
+
+#[derive(Clone, Copy)]
+struct LpmNode {
+    prefixlen: u32,
+    child: [NodeIndex; 2],
+    /// joins two subtrees, has no value
+    intermediate: bool,
+    used: bool,
+}
+
+const FREE_NODE: LpmNode = LpmNode {
+    prefixlen: 0,
+    child: [NIL; 2],
+    intermediate: false,
+    used: false,
+};
+
+pub struct LpmTrieMap {
+    attr: InternalMapAttr,
+    nodes: Vec<LpmNode>,
+    /// key then value of each node, the value at an 8-byte aligned offset
+    storage: Vec<u64>,
+    /// size of the key and value of a node in bytes
+    elem_size: usize,
+    data_size: usize,
+    max_prefixlen: u32,
+    root: NodeIndex,
+    /// number of nodes with a value
+    n_entries: usize,
+}
+
+/// the bit at `index` of `data`, most significant bit first
+fn extract_bit(data: &[u8], index: u32) -> usize {
+    ((data[index as usize / 8] >> (7 - index % 8)) & 1) as usize
+}
+
+impl LpmTrieMap {
+    pub fn new(attr: InternalMapAttr) -> Self {
+        let data_size = attr.key_size - LPM_PREFIXLEN_SIZE;
+        let elem_size = round_up(attr.key_size) + round_up(attr.value_size);
+        let n_nodes = attr.max_entries * 2;
+        Self {
+            attr,
+            nodes: alloc::vec![FREE_NODE; n_nodes],
+            storage: alloc::vec![0u64; n_nodes * elem_size / 8],
+            elem_size,
+            data_size,
+            max_prefixlen: data_size as u32 * 8,
+            root: NIL,
+            n_entries: 0,
+        }
+    }
+
+    fn key_addr(&self, node: NodeIndex) -> *mut u8 {
+        let base = self.storage.as_ptr() as *mut u8;
+        unsafe { base.add(node as usize * self.elem_size) }
+    }
+
+    fn value_addr(&self, node: NodeIndex) -> *mut u8 {
+        unsafe { self.key_addr(node).add(round_up(self.attr.key_size)) }
+    }
+
+    fn data(&self, node: NodeIndex) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.key_addr(node).add(LPM_PREFIXLEN_SIZE), self.data_size) }
+    }
+
+    /// prefixlen and data of the key that kptr points to
+    fn key_parts(&self, kptr: *const u8) -> (u32, &[u8]) {
+        let prefixlen = unsafe { *(kptr as *const u32) };
+        let data = unsafe { slice::from_raw_parts(kptr.add(LPM_PREFIXLEN_SIZE), self.data_size) };
+        (prefixlen, data)
+    }
+
+    /// number of leading bits shared by the node and the key, at most the shorter prefix
+    fn longest_prefix_match(&self, node: NodeIndex, prefixlen: u32, data: &[u8]) -> u32 {
+        let limit = self.nodes[node as usize].prefixlen.min(prefixlen);
+        let node_data = self.data(node);
+        let mut matched = 0;
+        for (a, b) in node_data.iter().zip(data) {
+            if matched >= limit {
+                break;
+            }
+            let diff = a ^ b;
+            if diff != 0 {
+                matched += diff.leading_zeros();
+                break;
+            }
+            matched += 8;
+        }
+        matched.min(limit)
+    }
+
+    fn alloc_node(&mut self, prefixlen: u32, kptr: *const u8, intermediate: bool) -> Option<NodeIndex> {
+        let node = self.nodes.iter().position(|node| !node.used)? as NodeIndex;
+        self.nodes[node as usize] = LpmNode {
+            prefixlen,
+            child: [NIL; 2],
+            intermediate,
+            used: true,
+        };
+        copy(self.key_addr(node), kptr, self.attr.key_size);
+        unsafe {
+            *(self.key_addr(node) as *mut u32) = prefixlen;
+        }
+        Some(node)
+    }
+
+    fn free_node(&mut self, node: NodeIndex) {
+        self.nodes[node as usize] = FREE_NODE;
+    }
+
+    /// node of the longest prefix matching the key that kptr points to
+    fn find_longest(&self, kptr: *const u8) -> Option<NodeIndex> {
+        let (prefixlen, data) = self.key_parts(kptr);
+        let mut found = None;
+        let mut node = self.root;
+        while node != NIL {
+            let matchlen = self.longest_prefix_match(node, prefixlen, data);
+            let n = self.nodes[node as usize];
+            if matchlen < n.prefixlen {
+                break;
+            }
+            if !n.intermediate {
+                found = Some(node);
+            }
+            if matchlen == self.max_prefixlen || matchlen == prefixlen {
+                break;
+            }
+            node = n.child[extract_bit(data, n.prefixlen)];
+        }
+        found
+    }
+
+    /// node with exactly the prefix of the key that kptr points to, intermediate nodes included,
+    /// the prefix of the key must not exceed `max_prefixlen`
+    fn find_exact(&self, kptr: *const u8) -> Option<NodeIndex> {
+        let (prefixlen, data) = self.key_parts(kptr);
+        let mut node = self.root;
+        while node != NIL {
+            let n = self.nodes[node as usize];
+            let matchlen = self.longest_prefix_match(node, prefixlen, data);
+            if matchlen < n.prefixlen {
+                return None;
+            }
+            if n.prefixlen == prefixlen {
+                return Some(node);
+            }
+            node = n.child[extract_bit(data, n.prefixlen)];
+        }
+        None
+    }
+
+    /// the slot pointing to `node`
+    fn set_slot(&mut self, parent: NodeIndex, bit: usize, node: NodeIndex) {
+        match parent {
+            NIL => self.root = node,
+            _ => self.nodes[parent as usize].child[bit] = node,
+        }
+    }
+}
+
+impl BpfMap for LpmTrieMap {
+    /// longest prefix match
+    fn lookup(&self, key: *const u8, value: *mut u8) -> BpfResult {
+        let node = self.find_longest(key).ok_or(ENOENT)?;
+        copy(value, self.value_addr(node), self.attr.value_size);
+        Ok(0)
+    }
+
+    /// insert or update the exact prefix of the key
+    fn update(&mut self, key: *const u8, value: *const u8, flags: u64) -> BpfResult {
+        if !(flags == BPF_ANY || flags == BPF_EXIST || flags == BPF_NOEXIST) {
+            return Err(EINVAL);
+        }
+        let (prefixlen, _) = self.key_parts(key);
+        if prefixlen > self.max_prefixlen {
+            return Err(EINVAL);
+        }
+
+        // the node already there, with or without a value
+        if let Some(node) = self.find_exact(key) {
+            let n = self.nodes[node as usize];
+            match (n.intermediate, flags) {
+                (false, BPF_NOEXIST) => return Err(EEXIST),
+                (true, BPF_EXIST) => return Err(ENOENT),
+                (true, _) if self.n_entries == self.attr.max_entries => return Err(E2BIG),
+                _ => (),
+            }
+            if n.intermediate {
+                // the key of an intermediate node was taken from one of its subtrees
+                copy(self.key_addr(node), key, self.attr.key_size);
+                self.nodes[node as usize].intermediate = false;
+                self.n_entries += 1;
+            }
+            copy(self.value_addr(node), value, self.attr.value_size);
+            return Ok(0);
+        }
+        if flags == BPF_EXIST {
+            return Err(ENOENT);
+        }
+        if self.n_entries == self.attr.max_entries {
+            return Err(E2BIG);
+        }
+
+        let new = self.alloc_node(prefixlen, key, false).ok_or(E2BIG)?;
+        copy(self.value_addr(new), value, self.attr.value_size);
+        self.n_entries += 1;
+
+        // walk down to the first node the new prefix does not extend
+        let data: Vec<u8> = self.data(new).to_vec();
+        let (mut parent, mut bit) = (NIL, 0);
+        let mut node = self.root;
+        let mut matchlen = 0;
+        while node != NIL {
+            let n = self.nodes[node as usize];
+            matchlen = self.longest_prefix_match(node, prefixlen, &data);
+            if n.prefixlen != matchlen || n.prefixlen == self.max_prefixlen {
+                break;
+            }
+            parent = node;
+            bit = extract_bit(&data, n.prefixlen);
+            node = n.child[bit];
+        }
+        if node == NIL {
+            self.set_slot(parent, bit, new);
+            return Ok(0);
+        }
+
+        if matchlen == prefixlen {
+            // the new node is a prefix of the node
+            let next_bit = extract_bit(self.data(node), matchlen);
+            self.nodes[new as usize].child[next_bit] = node;
+            self.set_slot(parent, bit, new);
+            return Ok(0);
+        }
+
+        // the prefixes diverge, join them with an intermediate node
+        let node_key = self.key_addr(node);
+        let im = match self.alloc_node(matchlen, node_key, true) {
+            Some(im) => im,
+            None => {
+                self.free_node(new);
+                self.n_entries -= 1;
+                return Err(E2BIG);
+            }
+        };
+        let new_bit = extract_bit(&data, matchlen);
+        self.nodes[im as usize].child[new_bit] = new;
+        self.nodes[im as usize].child[1 - new_bit] = node;
+        self.set_slot(parent, bit, im);
+        Ok(0)
+    }
+
+    /// delete the exact prefix of the key
+    fn delete(&mut self, key: *const u8) -> BpfResult {
+        let (prefixlen, data) = self.key_parts(key);
+        if prefixlen > self.max_prefixlen {
+            return Err(EINVAL);
+        }
+        let data: Vec<u8> = data.to_vec();
+        let (mut grandparent, mut parent_bit) = (NIL, 0);
+        let (mut parent, mut bit) = (NIL, 0);
+        let mut node = self.root;
+        while node != NIL {
+            let n = self.nodes[node as usize];
+            let matchlen = self.longest_prefix_match(node, prefixlen, &data);
+            if n.prefixlen != matchlen || n.prefixlen == prefixlen {
+                break;
+            }
+            grandparent = parent;
+            parent_bit = bit;
+            parent = node;
+            bit = extract_bit(&data, n.prefixlen);
+            node = n.child[bit];
+        }
+        if node == NIL {
+            return Err(ENOENT);
+        }
+        let n = self.nodes[node as usize];
+        if n.prefixlen != prefixlen || n.intermediate
+            || self.longest_prefix_match(node, prefixlen, &data) != prefixlen {
+            return Err(ENOENT);
+        }
+        self.n_entries -= 1;
+
+        // still joins two subtrees
+        if n.child[0] != NIL && n.child[1] != NIL {
+            self.nodes[node as usize].intermediate = true;
+            return Ok(0);
+        }
+
+        // a leaf under an intermediate node, the sibling takes the place of the parent
+        if parent != NIL && self.nodes[parent as usize].intermediate && n.child == [NIL; 2] {
+            let sibling = self.nodes[parent as usize].child[1 - bit];
+            self.set_slot(grandparent, parent_bit, sibling);
+            self.free_node(parent);
+            self.free_node(node);
+            return Ok(0);
+        }
+
+        let only_child = match n.child[0] {
+            NIL => n.child[1],
+            child => child,
+        };
+        self.set_slot(parent, bit, only_child);
+        self.free_node(node);
+        Ok(0)
+    }
+
+    /// iterate in pool order, a key that is not in the map restarts from the first key
+    fn next_key(&self, key: *const u8, next_key: *mut u8) -> BpfResult {
+        if !key.is_null() && self.key_parts(key).0 > self.max_prefixlen {
+            return Err(EINVAL);
+        }
+        let start = match key.is_null() {
+            true => None,
+            false => self.find_exact(key).filter(|node| !self.nodes[*node as usize].intermediate),
+        };
+        let start = start.map_or(0, |node| node as usize + 1);
+        let next = (start..self.nodes.len())
+            .find(|&i| self.nodes[i].used && !self.nodes[i].intermediate)
+            .ok_or(ENOENT)?;
+        copy(next_key, self.key_addr(next as NodeIndex), self.attr.key_size);
+        Ok(0)
+    }
+
+    fn get_attr(&self) -> InternalMapAttr {
+        self.attr
+    }
+
+    fn lookup_helper(&mut self, key: *const u8) -> BpfResult {
+        match self.find_longest(key) {
+            Some(node) => Ok(self.value_addr(node) as usize),
+            None => Err(ENOENT),
+        }
+    }
+}
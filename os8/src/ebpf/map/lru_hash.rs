@@ -0,0 +1,144 @@
+//! eBPF LRU hash map
+//!
+//!
+//! a hash map that never fails on update when it is full,
+//! the least recently used element is evicted instead
+//! assume that all pointer are in kernel space
+
+use super::{
+    BpfResult,
+    consts::*,
+    retcode::BpfErrorCode::*,
+    osutil::copy,
+};
+use super::internal::{
+    InternalMapAttr,
+    BpfMap,
+};
+use super::hash::{HashMap, ElemIndex, NIL};
+
+use alloc::vec::Vec;
+
+/// elements are kept in a recency list, the head is the most recently used
+///
+/// the list is indexed by the elements of the inner `HashMap`, so it never allocates either
+pub struct LruHashMap {
+    map: HashMap,
+    prev: Vec<ElemIndex>,
+    next: Vec<ElemIndex>,
+    head: ElemIndex,
+    tail: ElemIndex,
+}
+
+impl LruHashMap {
+    pub fn new(attr: InternalMapAttr) -> Self {
+        Self {
+            map: HashMap::new(attr),
+            prev: alloc::vec![NIL; attr.max_entries],
+            next: alloc::vec![NIL; attr.max_entries],
+            head: NIL,
+            tail: NIL,
+        }
+    }
+
+    fn unlink(&mut self, elem: ElemIndex) {
+        let (prev, next) = (self.prev[elem as usize], self.next[elem as usize]);
+        match prev {
+            NIL => self.head = next,
+            _ => self.next[prev as usize] = next,
+        }
+        match next {
+            NIL => self.tail = prev,
+            _ => self.prev[next as usize] = prev,
+        }
+    }
+
+    fn push_front(&mut self, elem: ElemIndex) {
+        self.prev[elem as usize] = NIL;
+        self.next[elem as usize] = self.head;
+        match self.head {
+            NIL => self.tail = elem,
+            head => self.prev[head as usize] = elem,
+        }
+        self.head = elem;
+    }
+
+    /// mark an element as the most recently used
+    fn touch(&mut self, elem: ElemIndex) {
+        if self.head != elem {
+            self.unlink(elem);
+            self.push_front(elem);
+        }
+    }
+
+    /// remove the least recently used element to make room for a new one
+    fn evict(&mut self) {
+        let victim = self.tail;
+        if victim == NIL {
+            return;
+        }
+        self.unlink(victim);
+        let key = self.map.key_addr(victim);
+        let _ = self.map.remove(key);
+    }
+}
+
+/// lookup from user space does not change recency, only the helper does
+impl BpfMap for LruHashMap {
+    fn lookup(&self, key: *const u8, value: *mut u8) -> BpfResult {
+        self.map.lookup(key, value)
+    }
+
+    fn update(&mut self, key: *const u8, value: *const u8, flags: u64) -> BpfResult {
+        // check flags
+        if !(flags == BPF_ANY || flags == BPF_EXIST || flags == BPF_NOEXIST) {
+            return Err(EINVAL);
+        }
+
+        if let Some(elem) = self.map.find(key) {
+            match flags {
+                BPF_ANY | BPF_EXIST => {
+                    copy(self.map.value_addr(elem), value, self.map.get_attr().value_size);
+                    self.touch(elem);
+                    Ok(0)
+                }
+                _ => Err(EEXIST), // existing entry
+            }
+        } else {
+            match flags {
+                BPF_ANY | BPF_NOEXIST => {
+                    let elem = match self.map.insert(key, value) {
+                        Some(elem) => elem,
+                        None => {
+                            self.evict();
+                            self.map.insert(key, value).ok_or(E2BIG)?
+                        }
+                    };
+                    self.push_front(elem);
+                    Ok(0)
+                }
+                _ => Err(ENOENT),
+            }
+        }
+    }
+
+    fn delete(&mut self, key: *const u8) -> BpfResult {
+        let elem = self.map.remove(key).ok_or(ENOENT)?;
+        self.unlink(elem);
+        Ok(0)
+    }
+
+    fn next_key(&self, key: *const u8, next_key: *mut u8) -> BpfResult {
+        self.map.next_key(key, next_key)
+    }
+
+    fn get_attr(&self) -> InternalMapAttr {
+        self.map.get_attr()
+    }
+
+    fn lookup_helper(&mut self, key: *const u8) -> BpfResult {
+        let elem = self.map.find(key).ok_or(ENOENT)?;
+        self.touch(elem);
+        Ok(self.map.value_addr(elem) as usize)
+    }
+}
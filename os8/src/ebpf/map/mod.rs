@@ -4,22 +4,40 @@
 //! ebpf map utility
 //! provides interface for map operations
 use lock::Mutex;
-use alloc::sync::Arc;
+use alloc::collections::BTreeSet;
+use alloc::sync::{Arc, Weak};
+use lazy_static::lazy_static;
 
 
 use super::consts::*;
-use super::retcode::{BpfResult, BpfErrorCode::*};
+use super::retcode::{BpfResult, BpfErrorCode, BpfErrorCode::*};
 use super::*;
-use super::osutil::{os_copy_from_user, os_copy_to_user};
+use super::osutil::{os_copy_from_user, os_copy_to_user, os_mmap_frames, copy, OS_PAGE_SIZE};
 use self::internal::{InternalMapAttr, BpfMap};
 use self::array::ArrayMap;
 use self::hash::HashMap;
-mod internal;
+use self::lru_hash::LruHashMap;
+use self::lpm_trie::{LpmTrieMap, LPM_DATA_SIZE_MAX};
+use self::queue_stack::QueueStackMap;
+use self::prog_array::ProgArrayMap;
+use self::ringbuf::{RingBufMap, BPF_RINGBUF_HDR_SZ, BPF_RINGBUF_MAX_SIZE};
+use self::stack_trace::StackTraceMap;
+pub(super) mod internal;
 mod array;
 mod hash;
+mod lru_hash;
+pub(super) mod lpm_trie;
+mod queue_stack;
+mod prog_array;
+mod ringbuf;
+mod stack_trace;
 
 
+/// elements copied from user space at a time by batch updates and deletes
+const BPF_BATCH_CHUNK: usize = 64;
+
 pub type SharedBpfMap = Arc<Mutex<dyn BpfMap + Send + Sync>>;
+pub type WeakBpfMap = Weak<Mutex<dyn BpfMap + Send + Sync>>;
 
 /// MapAttr, follows the linux convection
 /// 
@@ -31,11 +49,14 @@ pub struct MapAttr {
     pub key_size: u32,
     pub value_size: u32,
     pub max_entries: u32,
+    /// `BPF_F_MMAPABLE` for arrays, 0 otherwise
+    pub map_flags: u32,
 }
 
 /// MapOpAttr, follows the linux convection
 /// 
 /// Used by BPF_MAP_*_ELEM and BPF_MAP_GET_NEXT_KEY commands 
+/// BPF_MAP_FREEZE only uses `map_fd`
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
 pub struct MapOpAttr {
@@ -45,12 +66,64 @@ pub struct MapOpAttr {
     pub flags: u64,
 }
 
+/// MapBatchAttr, follows the linux convection
+///
+/// Used by BPF_MAP_LOOKUP_BATCH, BPF_MAP_UPDATE_BATCH and BPF_MAP_DELETE_BATCH
+/// `keys` and `values` are arrays of `count` keys and values
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct MapBatchAttr {
+    /// key to continue after, 0 to start from the first key
+    pub in_batch: u64,
+    /// the last key looked up is written here, pass it as `in_batch` of the next call
+    pub out_batch: u64,
+    pub keys: u64,
+    pub values: u64,
+    /// size of the arrays, written back with the number of elements processed
+    pub count: u32,
+    pub map_fd: u32,
+    /// flags of each element update
+    pub elem_flags: u64,
+    pub flags: u64,
+}
+
+/// RingBufReadAttr
+///
+/// Used by BPF_RINGBUF_READ
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct RingBufReadAttr {
+    pub map_fd: u32,
+    pub buf_size: u32,
+    pub buf: u64,
+}
+
+/// BpfMapInfo, follows the linux convection
+///
+/// Filled by BPF_OBJ_GET_INFO_BY_FD
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct BpfMapInfo {
+    pub map_type: u32,
+    pub id: u32,
+    pub key_size: u32,
+    pub value_size: u32,
+    pub max_entries: u32,
+    pub map_flags: u32,
+}
+
+lazy_static! {
+    /// ids of the maps that user space can no longer change, see `bpf_map_freeze`
+    static ref BPF_FROZEN_MAPS: Mutex<BTreeSet<u32>> = Mutex::new(BTreeSet::new());
+}
+
 #[derive(Debug)]
 pub enum BpfMapOp {
     LookUp,
     Update,
     Delete,
     GetNextKey,
+    LookUpAndDelete,
 }
 
 /// # bpf_map_create
@@ -61,39 +134,140 @@ pub enum BpfMapOp {
 /// * fd of the map created
 pub fn bpf_map_create(attr: MapAttr) -> BpfResult {
     let internal_attr = InternalMapAttr::from(attr);
+    // only arrays can be mmapped
+    let flags_allowed = match attr.map_type {
+        BPF_MAP_TYPE_ARRAY => BPF_F_MMAPABLE,
+        _ => 0,
+    };
+    if attr.map_flags & !flags_allowed != 0 {
+        return Err(EINVAL);
+    }
     match attr.map_type {
         BPF_MAP_TYPE_ARRAY => {
             // array index must have size of 4
             if internal_attr.key_size != 4 {
                 return Err(EINVAL);
             }
+            if attr.map_flags & BPF_F_MMAPABLE != 0 {
+                let map = ArrayMap::new_mmapable(internal_attr)?;
+                return bpf_object_create_map(Arc::new(Mutex::new(map)));
+            }
             let map = ArrayMap::new(internal_attr);
-            let shared_map = Arc::new(Mutex::new(map));
-            let fd = bpf_allocate_fd();
-            bpf_object_create_map(fd, shared_map);
-            Ok(fd as usize)
+            bpf_object_create_map(Arc::new(Mutex::new(map)))
         }
         BPF_MAP_TYPE_HASH => {
+            // elements are preallocated, an empty map is useless
+            if internal_attr.key_size == 0 || internal_attr.value_size == 0 || internal_attr.max_entries == 0 {
+                return Err(EINVAL);
+            }
             let map = HashMap::new(internal_attr);
-            let shared_map = Arc::new(Mutex::new(map));
-            let fd = bpf_allocate_fd();
-            bpf_object_create_map(fd, shared_map);
-            Ok(fd as usize)
+            bpf_object_create_map(Arc::new(Mutex::new(map)))
+        }
+        BPF_MAP_TYPE_LRU_HASH => {
+            if internal_attr.key_size == 0 || internal_attr.value_size == 0 || internal_attr.max_entries == 0 {
+                return Err(EINVAL);
+            }
+            let map = LruHashMap::new(internal_attr);
+            bpf_object_create_map(Arc::new(Mutex::new(map)))
+        }
+        BPF_MAP_TYPE_QUEUE | BPF_MAP_TYPE_STACK => {
+            // values have no keys
+            if internal_attr.key_size != 0 || internal_attr.value_size == 0 || internal_attr.max_entries == 0 {
+                return Err(EINVAL);
+            }
+            let map = QueueStackMap::new(internal_attr);
+            bpf_object_create_map(Arc::new(Mutex::new(map)))
+        }
+        BPF_MAP_TYPE_LPM_TRIE => {
+            // keys are a u32 prefix length followed by at least one byte of data
+            let key_size = internal_attr.key_size;
+            if key_size <= 4 || key_size > 4 + LPM_DATA_SIZE_MAX
+                || internal_attr.value_size == 0 || internal_attr.max_entries == 0 {
+                return Err(EINVAL);
+            }
+            let map = LpmTrieMap::new(internal_attr);
+            bpf_object_create_map(Arc::new(Mutex::new(map)))
+        }
+        BPF_MAP_TYPE_PROG_ARRAY => {
+            // keys are indices and values are program fds
+            if internal_attr.key_size != 4 || internal_attr.value_size != 4 || internal_attr.max_entries == 0 {
+                return Err(EINVAL);
+            }
+            let map = ProgArrayMap::new(internal_attr);
+            bpf_object_create_map(Arc::new(Mutex::new(map)))
+        }
+        BPF_MAP_TYPE_STACK_TRACE => {
+            // keys are stack ids and values are arrays of u64 ips
+            let value_size = internal_attr.value_size;
+            if internal_attr.key_size != 4 || value_size == 0 || value_size % 8 != 0
+                || value_size / 8 > BPF_MAX_STACK_DEPTH || internal_attr.max_entries == 0 {
+                return Err(EINVAL);
+            }
+            let map = StackTraceMap::new(internal_attr);
+            bpf_object_create_map(Arc::new(Mutex::new(map)))
+        }
+        BPF_MAP_TYPE_RINGBUF => {
+            // max_entries is the buffer size, key and value are unused
+            let size = internal_attr.max_entries;
+            if internal_attr.key_size != 0 || internal_attr.value_size != 0
+                || !size.is_power_of_two() || size < OS_PAGE_SIZE || size > BPF_RINGBUF_MAX_SIZE {
+                return Err(EINVAL);
+            }
+            let map = RingBufMap::new(internal_attr);
+            bpf_object_create_map(Arc::new(Mutex::new(map)))
         }
         _ => Err(EINVAL),
     }
 }
 
-/// remove a map objects
-pub fn bpf_map_close(fd: u32) -> BpfResult {
-    bpf_object_remove(fd).map_or(Ok(0), |_| Err(ENOENT))
+/// describe the map `id` for BPF_OBJ_GET_INFO_BY_FD
+pub fn bpf_map_get_info(id: u32, map: &SharedBpfMap) -> BpfMapInfo {
+    let attr = map.lock().get_attr();
+    BpfMapInfo {
+        map_type: attr.map_type,
+        id,
+        key_size: attr.key_size as u32,
+        value_size: attr.value_size as u32,
+        max_entries: attr.max_entries as u32,
+        map_flags: attr.map_flags,
+    }
+}
+
+/// # bpf_map_create_global_data
+/// create the array map backing a global data section of a program, like libbpf
+/// * the map has a single value initialized with `data`, it is not put into the fd table
+/// * `map_flags` is `BPF_F_RDONLY_PROG` for `.rodata`, 0 otherwise
+/// # return value
+/// * id of the map and the map, the program keeps it alive
+pub fn bpf_map_create_global_data(data: &[u8], map_flags: u32) -> Result<(u32, SharedBpfMap), BpfErrorCode> {
+    if data.is_empty() {
+        return Err(EINVAL);
+    }
+    let attr = InternalMapAttr {
+        map_type: BPF_MAP_TYPE_ARRAY,
+        key_size: 4,
+        value_size: data.len(),
+        max_entries: 1,
+        map_flags,
+    };
+    let mut map = ArrayMap::new(attr);
+    let key = 0u32;
+    map.update(&key as *const u32 as *const u8, data.as_ptr(), BPF_ANY)?;
+    let shared_map: SharedBpfMap = Arc::new(Mutex::new(map));
+    let id = bpf_object_register(&BpfObject::Map(shared_map.clone()));
+    Ok((id, shared_map))
+}
+
+/// kernel space address of the value of map `id`, see `BpfMap::direct_value_addr`
+pub fn bpf_map_direct_value_addr(id: u32) -> Option<usize> {
+    let shared_map = bpf_map_get(id).ok()?;
+    let addr = shared_map.lock().direct_value_addr();
+    addr
 }
 
-/// get map attributes
-pub fn bpf_map_get_attr(fd: u32) -> Option<InternalMapAttr> {
-    let bpf_objs = BPF_OBJECTS.lock();
-    let obj = bpf_objs.get(&fd)?;
-    let shared_map = obj.is_map()?;
+/// get map attributes by map id
+pub fn bpf_map_get_attr(id: u32) -> Option<InternalMapAttr> {
+    let shared_map = bpf_map_get(id).ok()?;
     let attr = shared_map.lock().get_attr();
     Some(attr)
 }
@@ -101,22 +275,30 @@ pub fn bpf_map_get_attr(fd: u32) -> Option<InternalMapAttr> {
 /// # bpf_map_ops
 /// wrapper function for map operations
 /// # arguments
-/// * fd - the file descriptor of the map
+/// * fd - the file descriptor of the map if from_user, otherwise the map id
 /// * op - map operation type, include `lookup`, `delete`, `update`
 /// * key - a pointer to key
 /// * value - a pointer to value
 /// * flags - see linux document for details
 /// * from_user - does key/value points to a user space address, or kernel space address
 /// # procedure
-/// * get the map objects by fd
+/// * get the map objects by fd or id
 /// * does the map operation according to op
 /// * refer to <https://livingshade.github.io/ebpf-doc/rcore/#bpf-map-operations> for the details
 #[allow(unreachable_patterns)]
 pub fn bpf_map_ops(fd: u32, op: BpfMapOp, key: *const u8, value: *mut u8, flags: u64, from_user: bool) -> BpfResult {
     trace!("bpf map ops fd:{}, op:{:?} key:{:x} value:{:x}", fd, op, key as usize, value as usize);
-    let bpf_objs = BPF_OBJECTS.lock();
-    let obj = bpf_objs.get(&fd).ok_or(ENOENT)?;
-    let shared_map = obj.is_map().ok_or(ENOENT)?;
+    let shared_map = match from_user {
+        true => {
+            let (id, map) = bpf_map_get_fd(fd)?;
+            let write = matches!(op, BpfMapOp::Update | BpfMapOp::Delete | BpfMapOp::LookUpAndDelete);
+            if write && bpf_map_is_frozen(id) {
+                return Err(EPERM);
+            }
+            map
+        }
+        false => bpf_map_get(fd)?,
+    };
     let mut map = shared_map.lock();
     if from_user {
         let key_size = map.get_attr().key_size;
@@ -139,8 +321,20 @@ pub fn bpf_map_ops(fd: u32, op: BpfMapOp, key: *const u8, value: *mut u8, flags:
             },
             BpfMapOp::Delete => map.delete(kptr),
             BpfMapOp::GetNextKey => {
-                let ret = map.next_key(kptr, vptr);
-                os_copy_to_user(value as usize, vptr, value_size);
+                // the next key has the size of a key, not a value
+                let mut next_key_kern_buf = alloc::vec![0 as u8; key_size];
+                let nptr = next_key_kern_buf.as_mut_ptr();
+                let ret = map.next_key(kptr, nptr);
+                if ret.is_ok() {
+                    os_copy_to_user(value as usize, nptr, key_size);
+                }
+                ret
+            }
+            BpfMapOp::LookUpAndDelete => {
+                let ret = map_lookup_and_delete(&mut *map, kptr, vptr);
+                if ret.is_ok() {
+                    os_copy_to_user(value as usize, vptr, value_size);
+                }
                 ret
             }
             _ => Err(EINVAL),
@@ -151,6 +345,7 @@ pub fn bpf_map_ops(fd: u32, op: BpfMapOp, key: *const u8, value: *mut u8, flags:
             BpfMapOp::Update => map.update(key, value, flags),
             BpfMapOp::Delete => map.delete(key),
             BpfMapOp::GetNextKey => map.next_key(key, value),
+            BpfMapOp::LookUpAndDelete => map_lookup_and_delete(&mut *map, key, value),
             _ => Err(EINVAL),
         }
     }
@@ -162,6 +357,16 @@ pub fn bpf_map_lookup_elem(fd: u32, key: *const u8, value: *mut u8, flags: u64,
     bpf_map_ops(fd, BpfMapOp::LookUp, key, value, flags, from_user)   
 }
 
+/// # bpf_map_lookup_helper
+/// lookup for helper functions, key points to kernel space
+/// # return value
+/// * kernel space address of the value, it stays valid until the element is deleted
+pub fn bpf_map_lookup_helper(id: u32, key: *const u8) -> BpfResult {
+    let shared_map = bpf_map_get(id)?;
+    let mut map = shared_map.lock();
+    map.lookup_helper(key)
+}
+
 /// wrapper that calls bpf_map_ops
 pub fn bpf_map_update_elem(fd: u32, key: *const u8, value: *mut u8, flags: u64, from_user: bool) -> BpfResult {
     bpf_map_ops(fd, BpfMapOp::Update, key, value, flags, from_user)   
@@ -177,4 +382,285 @@ pub fn bpf_map_get_next_key(fd: u32, key: *const u8, value: *mut u8, flags: u64,
     bpf_map_ops(fd, BpfMapOp::GetNextKey, key, value, flags, from_user)   
 }
 
+/// queues and stacks pop, other maps look up the key and delete it
+fn map_lookup_and_delete(map: &mut (dyn BpfMap + Send + Sync), key: *const u8, value: *mut u8) -> BpfResult {
+    match map.get_attr().key_size {
+        0 => map.pop(value, true),
+        _ => map.lookup(key, value).and_then(|_| map.delete(key)),
+    }
+}
+
+/// wrapper that calls bpf_map_ops, for queues, stacks and maps that support delete
+pub fn bpf_map_lookup_and_delete_elem(fd: u32, key: *const u8, value: *mut u8, flags: u64, from_user: bool) -> BpfResult {
+    bpf_map_ops(fd, BpfMapOp::LookUpAndDelete, key, value, flags, from_user)
+}
+
+/// # bpf_map_lookup_batch
+/// look up at most `count` elements of map `map_fd` after the key `in_batch`
+/// * keys and values are copied to user space once, the last key is written to `out_batch`
+/// * `count` is capped at `max_entries`, a map never holds more
+/// # return value
+/// * `count` is set to the number of elements copied,
+///   ENOENT once the last element of the map is copied
+pub fn bpf_map_lookup_batch(attr: &mut MapBatchAttr) -> BpfResult {
+    if attr.flags != 0 {
+        return Err(EINVAL);
+    }
+    let (_, shared_map) = bpf_map_get_fd(attr.map_fd)?;
+    let map = shared_map.lock();
+    let InternalMapAttr { key_size, value_size, max_entries, .. } = map.get_attr();
+    let max_count = (attr.count as usize).min(max_entries);
+    let mut keys = alloc::vec![0 as u8; max_count * key_size];
+    let mut values = alloc::vec![0 as u8; max_count * value_size];
+    let mut prev_key = alloc::vec![0 as u8; key_size];
+    let mut has_prev = attr.in_batch != 0;
+    if has_prev {
+        os_copy_from_user(attr.in_batch as usize, prev_key.as_mut_ptr(), key_size);
+    }
 
+    let mut count = 0;
+    let mut ret = Ok(0);
+    while count < max_count {
+        let prev = match has_prev {
+            true => prev_key.as_ptr(),
+            false => core::ptr::null(),
+        };
+        let kptr = keys[count * key_size..].as_mut_ptr();
+        if let Err(err) = map.next_key(prev, kptr) {
+            ret = Err(err);
+            break;
+        }
+        copy(prev_key.as_mut_ptr(), kptr, key_size);
+        has_prev = true;
+        // elements without a value, like empty stack ids, are skipped
+        match map.lookup(kptr, values[count * value_size..].as_mut_ptr()) {
+            Ok(_) => count += 1,
+            Err(ENOENT) => (),
+            Err(err) => {
+                ret = Err(err);
+                break;
+            }
+        }
+    }
+    drop(map);
+
+    if count > 0 {
+        os_copy_to_user(attr.keys as usize, keys.as_ptr(), count * key_size);
+        os_copy_to_user(attr.values as usize, values.as_ptr(), count * value_size);
+        if attr.out_batch != 0 {
+            os_copy_to_user(attr.out_batch as usize, prev_key.as_ptr(), key_size);
+        }
+    }
+    attr.count = count as u32;
+    ret
+}
+
+/// # bpf_map_update_batch
+/// update `count` elements of map `map_fd` with `elem_flags`, like BPF_MAP_UPDATE_ELEM
+/// # return value
+/// * `count` is set to the number of elements updated, it stops at the first error
+pub fn bpf_map_update_batch(attr: &mut MapBatchAttr) -> BpfResult {
+    bpf_map_write_batch(attr, true)
+}
+
+/// # bpf_map_delete_batch
+/// delete `count` keys of map `map_fd`, `values` is unused
+/// # return value
+/// * `count` is set to the number of elements deleted, it stops at the first error
+pub fn bpf_map_delete_batch(attr: &mut MapBatchAttr) -> BpfResult {
+    bpf_map_write_batch(attr, false)
+}
+
+/// apply the elements in chunks of `BPF_BATCH_CHUNK`, the count from user space is not trusted
+/// to size allocations
+fn bpf_map_write_batch(attr: &mut MapBatchAttr, update: bool) -> BpfResult {
+    if attr.flags != 0 {
+        return Err(EINVAL);
+    }
+    let (id, shared_map) = bpf_map_get_fd(attr.map_fd)?;
+    if bpf_map_is_frozen(id) {
+        return Err(EPERM);
+    }
+    let InternalMapAttr { key_size, value_size, .. } = shared_map.lock().get_attr();
+    let value_size = match update {
+        true => value_size,
+        false => 0,
+    };
+    let max_count = attr.count as usize;
+    let mut keys = alloc::vec![0 as u8; BPF_BATCH_CHUNK * key_size];
+    let mut values = alloc::vec![0 as u8; BPF_BATCH_CHUNK * value_size];
+
+    let mut count = 0;
+    let mut ret = Ok(0);
+    'chunks: while count < max_count {
+        let chunk = (max_count - count).min(BPF_BATCH_CHUNK);
+        os_copy_from_user(attr.keys as usize + count * key_size, keys.as_mut_ptr(), chunk * key_size);
+        if update {
+            os_copy_from_user(attr.values as usize + count * value_size, values.as_mut_ptr(), chunk * value_size);
+        }
+        let mut map = shared_map.lock();
+        for i in 0..chunk {
+            let kptr = keys[i * key_size..].as_ptr();
+            let result = match update {
+                true => map.update(kptr, values[i * value_size..].as_ptr(), attr.elem_flags),
+                false => map.delete(kptr),
+            };
+            if let Err(err) = result {
+                ret = Err(err);
+                break 'chunks;
+            }
+            count += 1;
+        }
+    }
+    attr.count = count as u32;
+    ret
+}
+
+/// # bpf_map_freeze
+/// make map `fd` read-only from user space, programs can still change it
+/// # return value
+/// * EBUSY if the map is already frozen, or mmapped
+pub fn bpf_map_freeze(fd: u32) -> BpfResult {
+    let (id, shared_map) = bpf_map_get_fd(fd)?;
+    // mappings made before could still be written, besides the map only this clone holds the frames
+    if let Some(frames) = shared_map.lock().mmap_frames() {
+        if Arc::strong_count(&frames) > 2 {
+            return Err(EBUSY);
+        }
+    }
+    let mut frozen = BPF_FROZEN_MAPS.lock();
+    // forget the maps freed since
+    frozen.retain(|&id| bpf_object_get(id).is_some());
+    match frozen.insert(id) {
+        true => Ok(0),
+        false => Err(EBUSY),
+    }
+}
+
+/// # bpf_map_mmap
+/// map the frames of mmapable array `fd` into the current process from `start`, see `sys_mmap`
+/// * `len` is rounded up to pages and cannot exceed the frames of the map
+/// * `port` has the bits of R, W, X, a frozen map cannot be mapped writable
+pub fn bpf_map_mmap(fd: u32, start: usize, len: usize, port: usize) -> BpfResult {
+    let (id, shared_map) = bpf_map_get_fd(fd)?;
+    let frames = shared_map.lock().mmap_frames().ok_or(EINVAL)?;
+    if start % OS_PAGE_SIZE != 0 || len == 0 || len > frames.len() * OS_PAGE_SIZE
+        || port & !0x7 != 0 || port & 0x7 == 0 {
+        return Err(EINVAL);
+    }
+    if port & 0x2 != 0 && bpf_map_is_frozen(id) {
+        return Err(EPERM);
+    }
+    let end = start.checked_add(len).ok_or(EINVAL)?;
+    match os_mmap_frames(start, end, frames, port) {
+        true => Ok(0),
+        false => Err(EINVAL),
+    }
+}
+
+/// whether user space can no longer change map `id`
+pub fn bpf_map_is_frozen(id: u32) -> bool {
+    BPF_FROZEN_MAPS.lock().contains(&id)
+}
+
+/// push the kernel space `value` into queue or stack `id`, used by `bpf_map_push_elem`
+pub fn bpf_map_push_helper(id: u32, value: *const u8, flags: u64) -> BpfResult {
+    let shared_map = bpf_map_get(id)?;
+    let mut map = shared_map.lock();
+    map.push(value, flags)
+}
+
+/// pop or peek queue or stack `id` into the kernel space `value`,
+/// used by `bpf_map_pop_elem` and `bpf_map_peek_elem`
+pub fn bpf_map_pop_helper(id: u32, value: *mut u8, delete: bool) -> BpfResult {
+    let shared_map = bpf_map_get(id)?;
+    let mut map = shared_map.lock();
+    map.pop(value, delete)
+}
+
+/// get the map object by id, the lock of `BPF_OBJECTS` is released on return,
+/// so map operations may look up other objects, like program fds in a program array
+fn bpf_map_get(id: u32) -> Result<SharedBpfMap, BpfErrorCode> {
+    let obj = bpf_object_get(id).ok_or(ENOENT)?;
+    obj.is_map().cloned().ok_or(ENOENT)
+}
+
+/// get the map id and the map object by fd of the current process
+pub fn bpf_map_get_fd(fd: u32) -> Result<(u32, SharedBpfMap), BpfErrorCode> {
+    let (id, obj) = bpf_object_get_fd(fd).ok_or(EBADF)?;
+    Ok((id, obj.is_map().cloned().ok_or(EINVAL)?))
+}
+
+/// copy `size` bytes of kernel memory `data` into a new record of ring buffer `id`
+pub fn bpf_ringbuf_output(id: u32, data: *const u8, size: usize) -> BpfResult {
+    let shared_map = bpf_map_get(id)?;
+    let mut map = shared_map.lock();
+    let ringbuf = map.as_ringbuf().ok_or(EINVAL)?;
+    ringbuf.output(data, size, id)
+}
+
+/// # bpf_ringbuf_reserve
+/// reserve a record of `size` bytes in ring buffer `id`
+/// # return value
+/// * kernel space address of the record, valid until it is committed
+pub fn bpf_ringbuf_reserve(id: u32, size: usize) -> BpfResult {
+    let shared_map = bpf_map_get(id)?;
+    let mut map = shared_map.lock();
+    let ringbuf = map.as_ringbuf().ok_or(EINVAL)?;
+    ringbuf.reserve(size, id).map(|addr| addr as usize).ok_or(EAGAIN)
+}
+
+/// # bpf_ringbuf_commit
+/// submit or discard a record returned by `bpf_ringbuf_reserve`
+/// * the owner map is found through the map id saved in the record header
+pub fn bpf_ringbuf_commit(data: *mut u8, discard: bool) -> BpfResult {
+    let id = unsafe { *(data.sub(BPF_RINGBUF_HDR_SZ / 2) as *const u32) };
+    let shared_map = bpf_map_get(id)?;
+    let mut map = shared_map.lock();
+    let ringbuf = map.as_ringbuf().ok_or(EINVAL)?;
+    ringbuf.commit(data, discard)
+}
+
+/// query positions and sizes of ring buffer `id`, see `BPF_RB_*`
+pub fn bpf_ringbuf_query(id: u32, flags: u64) -> BpfResult {
+    let shared_map = bpf_map_get(id)?;
+    let mut map = shared_map.lock();
+    let ringbuf = map.as_ringbuf().ok_or(EINVAL)?;
+    Ok(ringbuf.query(flags) as usize)
+}
+
+/// # bpf_ringbuf_read
+/// consume submitted records of ring buffer `fd` into user buffer `buf`
+/// * at most the size of the ring is read at once, that is all it can hold
+/// # return value
+/// * number of bytes written, records are laid out as `[u32 len][u32 pad][data]`,
+///   each 8-byte aligned
+pub fn bpf_ringbuf_read(fd: u32, buf: *mut u8, buf_size: usize) -> BpfResult {
+    let (_, shared_map) = bpf_map_get_fd(fd)?;
+    let ring_size = shared_map.lock().get_attr().max_entries;
+    let mut kern_buf = alloc::vec![0 as u8; buf_size.min(ring_size)];
+    let len = {
+        let mut map = shared_map.lock();
+        let ringbuf = map.as_ringbuf().ok_or(EINVAL)?;
+        ringbuf.consume(&mut kern_buf)?
+    };
+    if len > 0 {
+        os_copy_to_user(buf as usize, kern_buf.as_ptr(), len);
+    }
+    Ok(len)
+}
+
+/// the program in slot `index` of program array `id`, used by `bpf_tail_call`
+pub fn bpf_prog_array_get(id: u32, index: u32) -> Option<Arc<BpfProgram>> {
+    let shared_map = bpf_map_get(id).ok()?;
+    let mut map = shared_map.lock();
+    map.as_prog_array()?.get(index as usize)
+}
+
+/// store stack `ips` into stack trace map `id`, see `StackTraceMap::get_stackid`
+pub fn bpf_stack_map_get_stackid(id: u32, ips: &[u64], flags: u64) -> BpfResult {
+    let shared_map = bpf_map_get(id)?;
+    let mut map = shared_map.lock();
+    let stack_map = map.as_stack_trace().ok_or(EINVAL)?;
+    stack_map.get_stackid(ips, flags)
+}
@@ -0,0 +1,139 @@
+//! eBPF program array map
+//!
+//!
+//! an array of programs, the targets of `bpf_tail_call`
+//! the value written from user space is a program fd, a reference to the program is kept
+//! the value read back is the program id, like linux
+//! all programs in one array must have the same type
+//! programs may refer to the array they are in, so the slots are cleared once the last fd
+//! or pin of the array is gone, like the user references of a linux map
+
+use super::{
+    BpfResult,
+    retcode::BpfErrorCode::*,
+    program::BpfProgram,
+    bpf_program_get_fd,
+};
+use super::internal::{
+    InternalMapAttr,
+    BpfMap,
+};
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+pub struct ProgArrayMap {
+    attr: InternalMapAttr,
+    /// program id and the program of each slot
+    progs: Vec<Option<(u32, Arc<BpfProgram>)>>,
+    /// type of the first program inserted
+    owner_type: Option<u32>,
+    /// number of fds and pins of the array
+    urefs: usize,
+}
+
+impl ProgArrayMap {
+    pub fn new(attr: InternalMapAttr) -> Self {
+        let mut progs = Vec::with_capacity(attr.max_entries);
+        progs.resize(attr.max_entries, None);
+        Self { attr, progs, owner_type: None, urefs: 0 }
+    }
+
+    fn index(key: *const u8) -> usize {
+        unsafe { *(key as *const u32) as usize }
+    }
+
+    /// the program in slot `index`, used by `bpf_tail_call`
+    pub fn get(&self, index: usize) -> Option<Arc<BpfProgram>> {
+        self.progs.get(index)?.as_ref().map(|(_, prog)| prog.clone())
+    }
+
+    /// count a new fd or pin of the array
+    pub fn uref_get(&mut self) {
+        self.urefs += 1;
+    }
+
+    /// drop an fd or pin of the array, the programs are taken out of the slots with the last one,
+    /// the caller drops them after unlocking the array since they may hold it
+    pub fn uref_put(&mut self) -> Vec<Arc<BpfProgram>> {
+        self.urefs -= 1;
+        if self.urefs > 0 {
+            return Vec::new();
+        }
+        self.progs.iter_mut().filter_map(|slot| slot.take()).map(|(_, prog)| prog).collect()
+    }
+}
+
+impl BpfMap for ProgArrayMap {
+    /// returns the program id
+    fn lookup(&self, key: *const u8, value: *mut u8) -> BpfResult {
+        let index = Self::index(key);
+        match self.progs.get(index) {
+            Some(Some((id, _))) => {
+                unsafe {
+                    *(value as *mut u32) = *id;
+                }
+                Ok(0)
+            }
+            _ => Err(ENOENT),
+        }
+    }
+
+    fn update(&mut self, key: *const u8, value: *const u8, _flags: u64) -> BpfResult {
+        let index = Self::index(key);
+        if index >= self.attr.max_entries {
+            return Err(E2BIG);
+        }
+        let fd = unsafe { *(value as *const u32) };
+        let (id, prog) = bpf_program_get_fd(fd).ok_or(EBADF)?;
+        match self.owner_type {
+            Some(prog_type) if prog_type != prog.prog_type => return Err(EINVAL),
+            _ => self.owner_type = Some(prog.prog_type),
+        }
+        self.progs[index] = Some((id, prog));
+        Ok(0)
+    }
+
+    fn delete(&mut self, key: *const u8) -> BpfResult {
+        let index = Self::index(key);
+        match self.progs.get_mut(index) {
+            Some(slot) if slot.is_some() => {
+                *slot = None;
+                Ok(0)
+            }
+            _ => Err(ENOENT),
+        }
+    }
+
+    fn next_key(&self, key: *const u8, next_key: *mut u8) -> BpfResult {
+        let out = next_key as *mut u32;
+        let index = match key.is_null() {
+            true => usize::MAX,
+            false => Self::index(key),
+        };
+        let next = match index >= self.attr.max_entries {
+            true => 0,
+            false => index + 1,
+        };
+        if next >= self.attr.max_entries {
+            return Err(ENOENT);
+        }
+        unsafe {
+            *out = next as u32;
+        }
+        Ok(0)
+    }
+
+    fn get_attr(&self) -> InternalMapAttr {
+        self.attr
+    }
+
+    /// programs are only reachable through `bpf_tail_call`
+    fn lookup_helper(&mut self, _key: *const u8) -> BpfResult {
+        Err(EINVAL)
+    }
+
+    fn as_prog_array(&mut self) -> Option<&mut ProgArrayMap> {
+        Some(self)
+    }
+}
@@ -0,0 +1,115 @@
+//! eBPF queue and stack maps
+//!
+//!
+//! bounded FIFO or LIFO of values without keys, like linux
+//! values are pushed and popped by the push/pop/peek helpers,
+//! `update` pushes and `lookup` peeks so that the map syscalls work too
+//! storage is a preallocated ring, so the helpers never allocate
+
+use super::{
+    BpfResult,
+    consts::*,
+    retcode::BpfErrorCode::*,
+    osutil::copy,
+};
+use super::internal::{
+    InternalMapAttr,
+    BpfMap,
+};
+
+use alloc::vec::Vec;
+
+pub struct QueueStackMap {
+    attr: InternalMapAttr,
+    storage: Vec<u8>,
+    /// slot of the oldest value
+    tail: usize,
+    /// number of values
+    count: usize,
+    /// pop the newest value instead of the oldest
+    is_stack: bool,
+}
+
+impl QueueStackMap {
+    pub fn new(attr: InternalMapAttr) -> Self {
+        let size = attr.max_entries * attr.value_size;
+        Self {
+            attr,
+            storage: alloc::vec![0u8; size],
+            tail: 0,
+            count: 0,
+            is_stack: attr.map_type == BPF_MAP_TYPE_STACK,
+        }
+    }
+
+    /// kernel space address of the `n`-th value from the oldest
+    fn slot_addr(&self, n: usize) -> *mut u8 {
+        let index = (self.tail + n) % self.attr.max_entries;
+        unsafe { (self.storage.as_ptr() as *mut u8).add(index * self.attr.value_size) }
+    }
+}
+
+impl BpfMap for QueueStackMap {
+    /// same as peek
+    fn lookup(&self, _key: *const u8, value: *mut u8) -> BpfResult {
+        if self.count == 0 {
+            return Err(ENOENT);
+        }
+        let n = match self.is_stack {
+            true => self.count - 1,
+            false => 0,
+        };
+        copy(value, self.slot_addr(n), self.attr.value_size);
+        Ok(0)
+    }
+
+    /// same as push
+    fn update(&mut self, _key: *const u8, value: *const u8, flags: u64) -> BpfResult {
+        self.push(value, flags)
+    }
+
+    fn delete(&mut self, _key: *const u8) -> BpfResult {
+        Err(EINVAL)
+    }
+
+    fn next_key(&self, _key: *const u8, _next_key: *mut u8) -> BpfResult {
+        Err(EINVAL)
+    }
+
+    fn get_attr(&self) -> InternalMapAttr {
+        self.attr
+    }
+
+    fn lookup_helper(&mut self, _key: *const u8) -> BpfResult {
+        Err(EINVAL)
+    }
+
+    /// a full map fails with E2BIG, unless `BPF_EXIST` is given to drop the oldest value
+    fn push(&mut self, value: *const u8, flags: u64) -> BpfResult {
+        if flags & BPF_NOEXIST != 0 || flags > BPF_EXIST {
+            return Err(EINVAL);
+        }
+        if self.count == self.attr.max_entries {
+            if flags & BPF_EXIST == 0 {
+                return Err(E2BIG);
+            }
+            self.tail = (self.tail + 1) % self.attr.max_entries;
+            self.count -= 1;
+        }
+        copy(self.slot_addr(self.count), value, self.attr.value_size);
+        self.count += 1;
+        Ok(0)
+    }
+
+    /// the oldest value of a queue, the newest of a stack
+    fn pop(&mut self, value: *mut u8, delete: bool) -> BpfResult {
+        self.lookup(core::ptr::null(), value)?;
+        if delete {
+            if !self.is_stack {
+                self.tail = (self.tail + 1) % self.attr.max_entries;
+            }
+            self.count -= 1;
+        }
+        Ok(0)
+    }
+}
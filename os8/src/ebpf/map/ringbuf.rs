@@ -0,0 +1,206 @@
+//! eBPF ring buffer map
+//!
+//!
+//! a multi-producer, single-consumer queue of variable sized records
+//! programs reserve space, fill it and submit it, user space consumes the records
+//!
+//! each record starts with an 8-byte header, `len` with busy and discard bits,
+//! then the id of the map. records are 8-byte aligned and never wrap around the end,
+//! a discarded padding record fills the tail instead.
+//! storage is preallocated, so helpers never allocate
+
+use super::{
+    BpfResult,
+    retcode::BpfErrorCode::*,
+    osutil::copy,
+};
+use super::internal::{
+    InternalMapAttr,
+    BpfMap,
+};
+
+use alloc::vec::Vec;
+
+/// the record is reserved but not submitted yet
+const BPF_RINGBUF_BUSY_BIT: u32 = 1 << 31;
+/// the record is discarded and skipped by the consumer
+const BPF_RINGBUF_DISCARD_BIT: u32 = 1 << 30;
+pub const BPF_RINGBUF_HDR_SZ: usize = 8;
+/// largest buffer, the kernel heap is small
+pub const BPF_RINGBUF_MAX_SIZE: usize = 256 * 1024;
+
+/// bpf_ringbuf_query flags
+pub const BPF_RB_AVAIL_DATA: u64 = 0;
+pub const BPF_RB_RING_SIZE: u64 = 1;
+pub const BPF_RB_CONS_POS: u64 = 2;
+pub const BPF_RB_PROD_POS: u64 = 3;
+
+fn round_up(size: usize) -> usize {
+    (size + 7) & !7
+}
+
+pub struct RingBufMap {
+    attr: InternalMapAttr,
+    /// u64 elements keep records 8-byte aligned
+    storage: Vec<u64>,
+    /// both positions only grow, the offset in storage is `pos & mask`
+    consumer_pos: usize,
+    producer_pos: usize,
+}
+
+impl RingBufMap {
+    /// `max_entries` is the size of the buffer in bytes, a power of 2
+    pub fn new(attr: InternalMapAttr) -> Self {
+        let storage = alloc::vec![0u64; attr.max_entries / 8];
+        Self { attr, storage, consumer_pos: 0, producer_pos: 0 }
+    }
+
+    fn size(&self) -> usize {
+        self.attr.max_entries
+    }
+
+    fn base(&self) -> *mut u8 {
+        self.storage.as_ptr() as *mut u8
+    }
+
+    /// header of the record at `pos`, (len word, id word)
+    fn header(&self, pos: usize) -> (*mut u32, *mut u32) {
+        let hdr = unsafe { self.base().add(pos & (self.size() - 1)) } as *mut u32;
+        (hdr, unsafe { hdr.add(1) })
+    }
+
+    /// # reserve
+    /// reserve a record of `size` bytes, `owner` is stored in the header to find the map on submit
+    /// # return value
+    /// * address of the record data, None if the buffer is full
+    pub fn reserve(&mut self, size: usize, owner: u32) -> Option<*mut u8> {
+        let total = round_up(size + BPF_RINGBUF_HDR_SZ);
+        if size >= BPF_RINGBUF_DISCARD_BIT as usize || total > self.size() {
+            return None;
+        }
+        let offset = self.producer_pos & (self.size() - 1);
+        let pad = match offset + total > self.size() {
+            true => self.size() - offset,
+            false => 0,
+        };
+        if self.producer_pos + pad + total - self.consumer_pos > self.size() {
+            return None;
+        }
+        if pad > 0 {
+            let (len, id) = self.header(self.producer_pos);
+            unsafe {
+                *len = (pad - BPF_RINGBUF_HDR_SZ) as u32 | BPF_RINGBUF_DISCARD_BIT;
+                *id = owner;
+            }
+            self.producer_pos += pad;
+        }
+        let pos = self.producer_pos;
+        let (len, id) = self.header(pos);
+        unsafe {
+            *len = size as u32 | BPF_RINGBUF_BUSY_BIT;
+            *id = owner;
+        }
+        self.producer_pos += total;
+        Some(unsafe { self.base().add((pos & (self.size() - 1)) + BPF_RINGBUF_HDR_SZ) })
+    }
+
+    /// # commit
+    /// submit or discard a record returned by `reserve`
+    pub fn commit(&mut self, data: *mut u8, discard: bool) -> BpfResult {
+        let offset = (data as usize).wrapping_sub(self.base() as usize + BPF_RINGBUF_HDR_SZ);
+        if offset >= self.size() || offset % 8 != 0 {
+            return Err(EINVAL);
+        }
+        let (len, _) = self.header(offset);
+        let hdr = unsafe { *len };
+        if hdr & BPF_RINGBUF_BUSY_BIT == 0 {
+            return Err(EINVAL);
+        }
+        let flag = if discard { BPF_RINGBUF_DISCARD_BIT } else { 0 };
+        unsafe {
+            *len = (hdr & !BPF_RINGBUF_BUSY_BIT) | flag;
+        }
+        Ok(0)
+    }
+
+    /// copy `size` bytes from `data` into a new record
+    pub fn output(&mut self, data: *const u8, size: usize, owner: u32) -> BpfResult {
+        let record = self.reserve(size, owner).ok_or(EAGAIN)?;
+        copy(record, data, size);
+        self.commit(record, false)
+    }
+
+    pub fn query(&self, flags: u64) -> u64 {
+        match flags {
+            BPF_RB_AVAIL_DATA => (self.producer_pos - self.consumer_pos) as u64,
+            BPF_RB_RING_SIZE => self.size() as u64,
+            BPF_RB_CONS_POS => self.consumer_pos as u64,
+            BPF_RB_PROD_POS => self.producer_pos as u64,
+            _ => 0,
+        }
+    }
+
+    /// # consume
+    /// move submitted records into `buf`, stops at the first busy record
+    /// each record is copied with its header, the id word is cleared
+    /// # return value
+    /// * number of bytes written, ENOSPC if the first record does not fit
+    pub fn consume(&mut self, buf: &mut [u8]) -> BpfResult {
+        let mut written = 0;
+        while self.consumer_pos < self.producer_pos {
+            let (len, _) = self.header(self.consumer_pos);
+            let hdr = unsafe { *len };
+            if hdr & BPF_RINGBUF_BUSY_BIT != 0 {
+                break;
+            }
+            let size = (hdr & !BPF_RINGBUF_DISCARD_BIT) as usize;
+            let total = round_up(size + BPF_RINGBUF_HDR_SZ);
+            if hdr & BPF_RINGBUF_DISCARD_BIT == 0 {
+                if written + total > buf.len() {
+                    if written == 0 {
+                        return Err(ENOSPC);
+                    }
+                    break;
+                }
+                let out = &mut buf[written..written + total];
+                out.fill(0);
+                out[..4].copy_from_slice(&(size as u32).to_ne_bytes());
+                copy(out[BPF_RINGBUF_HDR_SZ..].as_mut_ptr(), unsafe { (len as *const u8).add(BPF_RINGBUF_HDR_SZ) }, size);
+                written += total;
+            }
+            self.consumer_pos += total;
+        }
+        Ok(written)
+    }
+}
+
+/// a ring buffer has no keys, element operations are not supported
+impl BpfMap for RingBufMap {
+    fn lookup(&self, _key: *const u8, _value: *mut u8) -> BpfResult {
+        Err(EINVAL)
+    }
+
+    fn update(&mut self, _key: *const u8, _value: *const u8, _flags: u64) -> BpfResult {
+        Err(EINVAL)
+    }
+
+    fn delete(&mut self, _key: *const u8) -> BpfResult {
+        Err(EINVAL)
+    }
+
+    fn next_key(&self, _key: *const u8, _next_key: *mut u8) -> BpfResult {
+        Err(EINVAL)
+    }
+
+    fn get_attr(&self) -> InternalMapAttr {
+        self.attr
+    }
+
+    fn lookup_helper(&mut self, _key: *const u8) -> BpfResult {
+        Err(EINVAL)
+    }
+
+    fn as_ringbuf(&mut self) -> Option<&mut RingBufMap> {
+        Some(self)
+    }
+}
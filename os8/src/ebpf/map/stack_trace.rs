@@ -0,0 +1,144 @@
+//! eBPF stack trace map
+//!
+//!
+//! buckets of instruction pointers filled by `bpf_get_stackid`
+//! the key is the stack id returned by the helper, the value is an array of u64 ips
+//! identical stacks share one bucket, so the id can be used as a key of other maps
+//! storage is preallocated, so the helper never allocates
+
+use super::{
+    BpfResult,
+    consts::*,
+    retcode::BpfErrorCode::*,
+};
+use super::internal::{
+    InternalMapAttr,
+    BpfMap,
+};
+
+use alloc::vec::Vec;
+use core::slice;
+
+pub struct StackTraceMap {
+    attr: InternalMapAttr,
+    /// `depth` ips of each bucket
+    ips: Vec<u64>,
+    /// number of valid ips of each bucket, 0 if the bucket is empty
+    nr: Vec<u32>,
+    depth: usize,
+}
+
+impl StackTraceMap {
+    /// `value_size` is the size of the ip array, the number of buckets is a power of 2
+    pub fn new(attr: InternalMapAttr) -> Self {
+        let n_buckets = attr.max_entries.next_power_of_two();
+        let depth = attr.value_size / 8;
+        Self {
+            attr,
+            ips: alloc::vec![0u64; n_buckets * depth],
+            nr: alloc::vec![0u32; n_buckets],
+            depth,
+        }
+    }
+
+    fn bucket(&self, id: usize) -> &[u64] {
+        &self.ips[id * self.depth..id * self.depth + self.nr[id] as usize]
+    }
+
+    /// FNV-1a hash of the ips
+    fn hash(ips: &[u64]) -> u32 {
+        let mut hash: u32 = 0x811c9dc5;
+        for ip in ips {
+            for byte in ip.to_ne_bytes() {
+                hash = (hash ^ byte as u32).wrapping_mul(0x01000193);
+            }
+        }
+        hash
+    }
+
+    /// # get_stackid
+    /// store the stack `ips` unless the same stack is already there
+    /// # arguments
+    /// * ips - the stack, truncated to the depth of the map
+    /// * flags - `BPF_F_REUSE_STACKID` replaces another stack with the same hash
+    /// # return value
+    /// * the stack id, EEXIST if the bucket holds another stack
+    pub fn get_stackid(&mut self, ips: &[u64], flags: u64) -> BpfResult {
+        let ips = &ips[..ips.len().min(self.depth)];
+        if ips.is_empty() {
+            return Err(EFAULT);
+        }
+        let id = Self::hash(ips) as usize & (self.nr.len() - 1);
+        if self.bucket(id) == ips {
+            return Ok(id);
+        }
+        if self.nr[id] != 0 && flags & BPF_F_REUSE_STACKID == 0 {
+            return Err(EEXIST);
+        }
+        self.ips[id * self.depth..id * self.depth + ips.len()].copy_from_slice(ips);
+        self.nr[id] = ips.len() as u32;
+        Ok(id)
+    }
+
+    fn index(key: *const u8) -> usize {
+        unsafe { *(key as *const u32) as usize }
+    }
+}
+
+/// stacks are only written by `bpf_get_stackid`
+impl BpfMap for StackTraceMap {
+    /// the ips are copied, the rest of the value is zeroed
+    fn lookup(&self, key: *const u8, value: *mut u8) -> BpfResult {
+        let id = Self::index(key);
+        if id >= self.nr.len() || self.nr[id] == 0 {
+            return Err(ENOENT);
+        }
+        let out = unsafe { slice::from_raw_parts_mut(value as *mut u64, self.depth) };
+        let ips = self.bucket(id);
+        out.fill(0);
+        out[..ips.len()].copy_from_slice(ips);
+        Ok(0)
+    }
+
+    fn update(&mut self, _key: *const u8, _value: *const u8, _flags: u64) -> BpfResult {
+        Err(EINVAL)
+    }
+
+    fn delete(&mut self, key: *const u8) -> BpfResult {
+        let id = Self::index(key);
+        if id >= self.nr.len() || self.nr[id] == 0 {
+            return Err(ENOENT);
+        }
+        self.nr[id] = 0;
+        Ok(0)
+    }
+
+    /// iterate over the stored stacks in id order
+    fn next_key(&self, key: *const u8, next_key: *mut u8) -> BpfResult {
+        let id = match key.is_null() {
+            true => usize::MAX,
+            false => Self::index(key),
+        };
+        let start = match id < self.nr.len() {
+            true => id + 1,
+            false => 0,
+        };
+        let next = (start..self.nr.len()).find(|&id| self.nr[id] != 0).ok_or(ENOENT)?;
+        unsafe {
+            *(next_key as *mut u32) = next as u32;
+        }
+        Ok(0)
+    }
+
+    fn get_attr(&self) -> InternalMapAttr {
+        self.attr
+    }
+
+    fn lookup_helper(&mut self, _key: *const u8) -> BpfResult {
+        Err(EINVAL)
+    }
+
+    fn as_stack_trace(&mut self) -> Option<&mut StackTraceMap> {
+        Some(self)
+    }
+}
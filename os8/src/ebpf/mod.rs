@@ -11,22 +11,30 @@
 
 pub mod consts;
 mod helpers;
+pub mod insn;
+pub mod interpreter;
 pub mod map;
 pub mod program;
 pub mod tracepoints;
 pub mod retcode;
 pub mod osutil;
+pub mod verifier;
 
 use lock::Mutex;
 use alloc::collections::BTreeMap;
-use alloc::sync::Arc;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
 use core::sync::atomic::{AtomicU32, Ordering};
 use lazy_static::lazy_static;
-use map::SharedBpfMap;
+use map::{SharedBpfMap, WeakBpfMap};
 use program::BpfProgram;
+use osutil::{os_bpf_fd_install, os_bpf_fd_get, os_current_thread};
+use retcode::{BpfResult, BpfErrorCode::*};
 
 /// currently, a BpfObject is either a map or a program 
-/// they are identified by a `fd`
+/// user space refers to them by a `fd` in its fd table,
+/// programs and helpers refer to them by a global `id`
+#[derive(Clone)]
 pub enum BpfObject {
     Map(SharedBpfMap),
     Program(Arc<BpfProgram>),
@@ -47,36 +55,231 @@ impl BpfObject {
             _ => None,
         }
     }
+    fn downgrade(&self) -> WeakBpfObject {
+        match self {
+            BpfObject::Map(map) => WeakBpfObject::Map(Arc::downgrade(map)),
+            BpfObject::Program(program) => WeakBpfObject::Program(Arc::downgrade(program)),
+        }
+    }
+}
+
+/// the index does not keep objects alive, they are freed once the last fd,
+/// program, program array or tracepoint referring to them is gone
+enum WeakBpfObject {
+    Map(WeakBpfMap),
+    Program(Weak<BpfProgram>),
+}
+
+impl WeakBpfObject {
+    fn upgrade(&self) -> Option<BpfObject> {
+        match self {
+            WeakBpfObject::Map(map) => map.upgrade().map(BpfObject::Map),
+            WeakBpfObject::Program(program) => program.upgrade().map(BpfObject::Program),
+        }
+    }
+}
+
+/// Bpf Objects are store in a index, with key = id, value = (Weak<Object>, pid of the creator)
+lazy_static! {
+    static ref BPF_ID_COUNTER: AtomicU32 = AtomicU32::new(1);
+    static ref BPF_OBJECTS: Mutex<BTreeMap<u32, (WeakBpfObject, u64)>> = Mutex::new(BTreeMap::new());
 }
 
-/// to avoid conflict with real fd
-const BPF_FD_BASE: u32 = 0x70000000;
+/// pinned objects live under this path, like a mounted bpffs
+pub const BPF_FS_ROOT: &str = "/sys/fs/bpf/";
 
-/// Bpf Objects are store in a index, with key = fd, value = Arc<Object> 
+/// Pinned objects are store in a index, with key = path, value = (id, Object)
+/// a pinned object stays alive until it is unpinned, even if no process holds it
 lazy_static! {
-    static ref BPF_FD_COUNTER: AtomicU32 = AtomicU32::new(BPF_FD_BASE);
-    pub static ref BPF_OBJECTS: Mutex<BTreeMap<u32, BpfObject>> = Mutex::new(BTreeMap::new());
+    static ref BPF_PINNED: Mutex<BTreeMap<String, (u32, BpfObject)>> = Mutex::new(BTreeMap::new());
+}
+
+/// ObjPinAttr, follows the linux convection
+///
+/// Used by BPF_OBJ_PIN and BPF_OBJ_GET
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ObjPinAttr {
+    pub pathname: u64,
+    pub bpf_fd: u32,
+    pub file_flags: u32,
+}
+
+/// ObjIdAttr, follows the linux convection
+///
+/// Used by BPF_PROG_GET_NEXT_ID, BPF_MAP_GET_NEXT_ID, BPF_PROG_GET_FD_BY_ID and BPF_MAP_GET_FD_BY_ID
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ObjIdAttr {
+    /// the id to start after, or the id to open
+    pub start_id: u32,
+    /// written back by the GET_NEXT_ID commands
+    pub next_id: u32,
+    pub open_flags: u32,
+}
+
+/// ObjInfoAttr, follows the linux convection
+///
+/// Used by BPF_OBJ_GET_INFO_BY_FD, `info` points to a `BpfMapInfo` or a `BpfProgInfo`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ObjInfoAttr {
+    pub bpf_fd: u32,
+    /// size of the user buffer, written back with the size filled in
+    pub info_len: u32,
+    pub info: u64,
+}
+
+/// count an fd or pin of `obj` as a user reference, see `ProgArrayMap::uref_get`
+pub fn bpf_object_uref_get(obj: &BpfObject) {
+    if let Some(map) = obj.is_map() {
+        if let Some(prog_array) = map.lock().as_prog_array() {
+            prog_array.uref_get();
+        }
+    }
+}
+
+/// drop a user reference of `obj`, program arrays release their programs with the last one
+pub fn bpf_object_uref_put(obj: &BpfObject) {
+    if let Some(map) = obj.is_map() {
+        let released = map.lock().as_prog_array().map(|prog_array| prog_array.uref_put());
+        drop(released);
+    }
 }
 
 /// use atomic fetch and add for concurrency
-pub fn bpf_allocate_fd() -> u32 {
-    BPF_FD_COUNTER.fetch_add(1, Ordering::Relaxed)
+pub fn bpf_allocate_id() -> u32 {
+    BPF_ID_COUNTER.fetch_add(1, Ordering::Relaxed)
 }
 
-/// insert into kv
-pub fn bpf_object_create(fd: u32, obj: BpfObject) {
-    trace!("bpf object create (fd):{:x}", fd);
-    BPF_OBJECTS.lock().insert(fd, obj);
+/// insert into the index without an fd, the caller keeps the object alive
+/// # return value
+/// * id of the object
+pub fn bpf_object_register(obj: &BpfObject) -> u32 {
+    let id = bpf_allocate_id();
+    trace!("bpf object create (id):{}", id);
+    let owner = os_current_thread().get_pid();
+    let mut objs = BPF_OBJECTS.lock();
+    // forget the objects freed since
+    objs.retain(|_, (obj, _)| obj.upgrade().is_some());
+    objs.insert(id, (obj.downgrade(), owner));
+    id
+}
+
+/// # bpf_object_create
+/// insert into the index, and into the fd table of the current process
+/// # return value
+/// * fd of the object
+pub fn bpf_object_create(obj: BpfObject) -> BpfResult {
+    let id = bpf_object_register(&obj);
+    Ok(os_bpf_fd_install(id, obj))
+}
+
+pub fn bpf_object_create_map(map: SharedBpfMap) -> BpfResult {
+    bpf_object_create(BpfObject::Map(map))
+}
+
+pub fn bpf_object_create_program(prog: BpfProgram) -> BpfResult {
+    bpf_object_create(BpfObject::Program(Arc::new(prog)))
+}
+
+/// get the object by id, None if it is freed
+pub fn bpf_object_get(id: u32) -> Option<BpfObject> {
+    BPF_OBJECTS.lock().get(&id)?.0.upgrade()
+}
+
+/// # bpf_obj_get_next_id
+/// find the smallest id after `start_id` of a live map, or program if `map` is false
+/// # return value
+/// * the id, ENOENT if there is none
+pub fn bpf_obj_get_next_id(start_id: u32, map: bool) -> BpfResult {
+    let first = start_id.checked_add(1).ok_or(ENOENT)?;
+    let objs = BPF_OBJECTS.lock();
+    let next = objs.range(first..).find(|(_, (obj, _))| match obj {
+        WeakBpfObject::Map(weak) => map && weak.strong_count() > 0,
+        WeakBpfObject::Program(weak) => !map && weak.strong_count() > 0,
+    });
+    next.map(|(&id, _)| id as usize).ok_or(ENOENT)
+}
+
+/// # bpf_obj_get_fd_by_id
+/// open the live map, or program if `map` is false, with `id` in the current process
+/// * there are no users in rCore, so instead of `CAP_SYS_ADMIN` in linux, a process can only open
+///  the objects it created, or pinned ones which are shared through `BPF_FS_ROOT` anyway
+/// # return value
+/// * a new fd of the object, EPERM if the object belongs to another process
+pub fn bpf_obj_get_fd_by_id(id: u32, map: bool) -> BpfResult {
+    let (obj, owner) = match BPF_OBJECTS.lock().get(&id) {
+        Some((obj, owner)) => (obj.upgrade().ok_or(ENOENT)?, *owner),
+        None => return Err(ENOENT),
+    };
+    if obj.is_map().is_some() != map {
+        return Err(ENOENT);
+    }
+    let pinned = || BPF_PINNED.lock().values().any(|(pinned_id, _)| *pinned_id == id);
+    if owner != os_current_thread().get_pid() && !pinned() {
+        return Err(EPERM);
+    }
+    Ok(os_bpf_fd_install(id, obj))
+}
+
+/// get the id and the object by fd of the current process
+pub fn bpf_object_get_fd(fd: u32) -> Option<(u32, BpfObject)> {
+    os_bpf_fd_get(fd)
+}
+
+/// get the program by fd of the current process
+pub fn bpf_program_get_fd(fd: u32) -> Option<(u32, Arc<BpfProgram>)> {
+    let (id, obj) = bpf_object_get_fd(fd)?;
+    Some((id, obj.is_program()?.clone()))
+}
+
+/// a pin path is a file name directly under `BPF_FS_ROOT`
+fn bpf_check_pin_path(path: &str) -> BpfResult {
+    match path.strip_prefix(BPF_FS_ROOT) {
+        Some(name) if !name.is_empty() && !name.contains('/') => Ok(0),
+        _ => Err(EINVAL),
+    }
+}
+
+/// # bpf_obj_pin
+/// pin the map or program of `fd` to `path`
+/// # return value
+/// * OK(0) on success, EEXIST if the path is taken
+pub fn bpf_obj_pin(path: &str, fd: u32) -> BpfResult {
+    bpf_check_pin_path(path)?;
+    let (id, obj) = bpf_object_get_fd(fd).ok_or(EBADF)?;
+    let mut pinned = BPF_PINNED.lock();
+    if pinned.contains_key(path) {
+        return Err(EEXIST);
+    }
+    trace!("bpf object pin (id):{} to {}", id, path);
+    bpf_object_uref_get(&obj);
+    pinned.insert(String::from(path), (id, obj));
+    Ok(0)
 }
 
-pub fn bpf_object_create_map(fd: u32, map: SharedBpfMap) {
-    bpf_object_create(fd, BpfObject::Map(map));
+/// # bpf_obj_get
+/// open the object pinned at `path` in the current process
+/// # return value
+/// * a new fd of the object
+pub fn bpf_obj_get(path: &str) -> BpfResult {
+    bpf_check_pin_path(path)?;
+    let (id, obj) = BPF_PINNED.lock().get(path).cloned().ok_or(ENOENT)?;
+    Ok(os_bpf_fd_install(id, obj))
 }
 
-pub fn bpf_object_create_program(fd: u32, prog: BpfProgram) {
-    bpf_object_create(fd, BpfObject::Program(Arc::new(prog)));
+/// # bpf_obj_unpin
+/// remove the pin at `path`, the object is freed once nothing else holds it
+pub fn bpf_obj_unpin(path: &str) -> BpfResult {
+    bpf_check_pin_path(path)?;
+    let (_, obj) = BPF_PINNED.lock().remove(path).ok_or(ENOENT)?;
+    bpf_object_uref_put(&obj);
+    Ok(0)
 }
 
-pub fn bpf_object_remove(fd: u32) -> Option<BpfObject> {
-    BPF_OBJECTS.lock().remove(&fd)
+mod tests;
+pub fn run_tests() {
+    tests::lpm_trie_test::run_lpm_trie_test();
+    tests::interpreter_test::run_interpreter_test();
 }
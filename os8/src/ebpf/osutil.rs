@@ -5,13 +5,29 @@
 //! one needs to change os_* to migrate to another kernel
 
 use super::{
+    BpfObject,
+    ObjPinAttr,
+    ObjIdAttr,
+    ObjInfoAttr,
+    bpf_obj_pin,
+    bpf_obj_get,
+    bpf_obj_get_next_id,
+    bpf_obj_get_fd_by_id,
+    bpf_object_get_fd,
+    bpf_object_uref_get,
+    bpf_object_uref_put,
     map::*,
     map::MapAttr,
     map::MapOpAttr,
+    map::RingBufReadAttr,
+    map::MapBatchAttr,
     retcode::BpfResult,
     tracepoints::KprobeAttachAttr,
     tracepoints::*,
-    program::{bpf_program_load_ex, ProgramLoadExAttr, MapFdEntry},
+    program::{bpf_program_load_ex, bpf_program_load, ProgramLoadExAttr, ProgramLoadAttr, MapFdEntry, ProgramSectionEntry},
+    program::{BpfProgram, BpfProgInfo, bpf_prog_target_buf, BPF_PROG_TARGET_LEN},
+    verifier::{VerifierLog, BPF_MAXINSNS},
+    retcode::BpfErrorCode::{EINVAL, EBADF, E2BIG},
 };
 
 use core::{mem::size_of, fmt::Write, iter::Map};
@@ -28,21 +44,38 @@ pub trait ThreadLike : DowncastSync {
     fn get_pid(&self) -> u64;
     fn get_tid(&self) -> u64;
     fn get_name(&self) -> String;
+    fn get_uid(&self) -> u32;
+    fn get_gid(&self) -> u32;
 }
 
 impl_downcast!(ThreadLike);
 
 /// in rCore, `TaskControlBlock` is the thread abstract
+///
+/// probes may run while the task or process is borrowed, so nothing here panics
 impl ThreadLike for TaskControlBlock {
+    /// kernel threads have no process, their pid is 0
     fn get_pid(&self) -> u64 {
-        let proc = self.process.upgrade().unwrap();
-        return proc.pid.0 as u64;
+        self.process.upgrade().map_or(0, |proc| proc.pid.0 as u64)
     }
+    /// tid is unique within the process, the main thread has tid 0
     fn get_tid(&self) -> u64 {
-        return 0; // no viable in rcore tutor
+        self.try_inner_exclusive_access()
+            .and_then(|inner| inner.res.as_ref().map(|res| res.tid as u64))
+            .unwrap_or(0)
     }
+    /// name of the executable of the process
     fn get_name(&self) -> String {
-        return String::from("not viable in rcore tutorial")
+        self.process.upgrade()
+            .and_then(|proc| proc.name.try_exclusive_access().map(|name| name.clone()))
+            .unwrap_or_default()
+    }
+    /// there are no users in rCore, everything runs as root
+    fn get_uid(&self) -> u32 {
+        0
+    }
+    fn get_gid(&self) -> u32 {
+        0
     }
 }
 
@@ -55,11 +88,75 @@ pub fn os_current_thread() -> Arc<dyn ThreadLike> {
     }
 }
 
+/// the syscall filters of the current process, cloned so that they run without borrowing it,
+/// only called on syscall entry where the process is never borrowed, so filters cannot be skipped
+pub fn os_current_syscall_filters() -> alloc::vec::Vec<Arc<BpfProgram>> {
+    let process = crate::task::current_process();
+    let inner = process.inner_exclusive_access();
+    inner.syscall_filters.clone()
+}
+
+/// add a syscall filter to the current process, false if it is borrowed
+pub fn os_add_syscall_filter(program: Arc<BpfProgram>) -> bool {
+    let process = crate::task::current_process();
+    let mut inner = match process.try_inner_exclusive_access() {
+        Some(inner) => inner,
+        None => return false,
+    };
+    inner.syscall_filters.push(program);
+    true
+}
+
+/// page size, ring buffers are sized in pages
+pub const OS_PAGE_SIZE: usize = crate::config::PAGE_SIZE;
+
+/// whole frames backing a map, shared with the processes that mmap it
+pub type OsFrames = crate::mm::SharedFrames;
+
+/// # os_alloc_frames
+/// allocate `count` zeroed, physically contiguous frames
+/// # return value
+/// * the frames and their kernel space address, physical memory is identically mapped
+pub fn os_alloc_frames(count: usize) -> Option<(OsFrames, usize)> {
+    use crate::mm::{frame_alloc_contiguous, PhysAddr};
+    let frames = frame_alloc_contiguous(count)?;
+    let addr = PhysAddr::from(frames.first()?.ppn).0;
+    Some((Arc::new(frames), addr))
+}
+
+/// # os_mmap_frames
+/// map the pages of `frames` covering `[start, end)` into the current process
+/// * `port` has the bits of R, W, X like `sys_mmap`
+/// # return value
+/// * false if the range is outside of user space or overlaps a mapped page
+pub fn os_mmap_frames(start: usize, end: usize, frames: OsFrames, port: usize) -> bool {
+    use crate::mm::{MapPermission, VirtAddr};
+    let process = crate::task::current_process();
+    let mut inner = process.inner_exclusive_access();
+    let permission = MapPermission::from_bits((port as u8) << 1).unwrap() | MapPermission::U;
+    inner.memory_set.insert_shared_area(VirtAddr::from(start), VirtAddr::from(end), frames, permission)
+}
+
 /// get current time
 pub fn os_current_time() -> u128 {
    crate::timer::get_time_us() as u128 * 1000
 }
 
+/// raw timer ticks, cheap enough to time every program run
+pub fn os_get_ticks() -> usize {
+    crate::timer::get_time()
+}
+
+/// convert timer ticks to nanoseconds
+pub fn os_ticks_to_ns(ticks: u64) -> u64 {
+    (ticks as u128 * 1_000_000_000 / crate::config::CLOCK_FREQ as u128) as u64
+}
+
+/// a random u32 from the kernel generator
+pub fn os_get_random_u32() -> u32 {
+    crate::random::next_u32()
+}
+
 /// get current hart
 pub fn os_get_current_cpu() -> u8 {
    0 // not viable
@@ -70,6 +167,125 @@ pub fn os_console_write_str(s: &str) {
     crate::console::Stdout.write_str(s).unwrap();
 }
 
+/// page table of the kernel space
+///
+/// programs only run in kernel mode, so it is the active one.
+/// `KERNEL_SPACE` is not used as the probed function may hold it
+fn os_kernel_token() -> usize {
+    riscv::register::satp::read().bits()
+}
+
+/// page table of the current user space, None if there is no user thread
+/// or the process is borrowed by the probed function
+fn os_user_token() -> Option<usize> {
+    let task = crate::task::current_task()?;
+    let process = task.process.upgrade()?;
+    let inner = process.try_inner_exclusive_access()?;
+    Some(inner.memory_set.token())
+}
+
+/// # os_translate_readable
+/// translate `va` through the page table `token`
+/// # return value
+/// * physical address, None unless the page is mapped readable, for user or kernel only
+fn os_translate_readable(token: usize, va: usize, user: bool) -> Option<usize> {
+    use crate::mm::{PageTable, VirtAddr, PTEFlags};
+    // SV39 addresses must be sign extended from bit 38
+    let top = (va as isize) >> 38;
+    if top != 0 && top != -1 {
+        return None;
+    }
+    let page_table = PageTable::from_token(token);
+    let va = VirtAddr::from(va);
+    let pte = page_table.translate(va.floor())?;
+    if !pte.is_valid() || !pte.readable() || pte.flags().contains(PTEFlags::U) != user {
+        return None;
+    }
+    page_table.translate_va(va).map(usize::from)
+}
+
+/// # os_probe_read
+/// copy at most `len` bytes from `src` to kernel buffer `dst` without faulting,
+/// every page is checked through the page table before it is read
+/// # arguments
+/// * user - `src` is an address of the current user space, otherwise of the kernel space
+/// * stop_at_nul - stop after a null byte
+/// # return value
+/// * number of bytes copied, None if some page is not readable
+pub fn os_probe_read(dst: *mut u8, src: usize, len: usize, user: bool, stop_at_nul: bool) -> Option<usize> {
+    let token = match user {
+        true => os_user_token()?,
+        false => os_kernel_token(),
+    };
+    let mut copied = 0;
+    while copied < len {
+        let va = src.checked_add(copied)?;
+        let pa = os_translate_readable(token, va, user)?;
+        let chunk = (OS_PAGE_SIZE - va % OS_PAGE_SIZE).min(len - copied);
+        // physical memory is identity mapped in the kernel space
+        let from = unsafe { from_raw_parts(pa as *const u8, chunk) };
+        let to = unsafe { from_raw_parts_mut(dst.add(copied), chunk) };
+        if stop_at_nul {
+            if let Some(pos) = from.iter().position(|&c| c == 0) {
+                to[..=pos].copy_from_slice(&from[..=pos]);
+                return Some(copied + pos + 1);
+            }
+        }
+        to.copy_from_slice(from);
+        copied += chunk;
+    }
+    Some(copied)
+}
+
+/// # os_walk_stack
+/// follow the frame pointer chain from `fp`, each frame saves the caller fp at `fp - 16`
+/// and the return address at `fp - 8`. frames are read with `os_probe_read`,
+/// so a broken chain ends the walk instead of faulting
+/// # return value
+/// * number of return addresses written to `ips`
+pub fn os_walk_stack(mut fp: usize, user: bool, ips: &mut [u64]) -> usize {
+    let mut nr = 0;
+    while nr < ips.len() && fp != 0 && fp % 8 == 0 {
+        let mut frame = [0u64; 2];
+        let frame_ptr = frame.as_mut_ptr() as *mut u8;
+        if os_probe_read(frame_ptr, fp.wrapping_sub(16), 16, user, false) != Some(16) {
+            break;
+        }
+        let (caller_fp, ra) = (frame[0] as usize, frame[1]);
+        if ra == 0 {
+            break;
+        }
+        ips[nr] = ra;
+        nr += 1;
+        // the stack grows down, callers are at higher addresses
+        if caller_fp <= fp {
+            break;
+        }
+        fp = caller_fp;
+    }
+    nr
+}
+
+/// frame pointer of the caller
+#[inline(always)]
+pub fn os_current_fp() -> usize {
+    let fp: usize;
+    unsafe {
+        core::arch::asm!("mv {}, s0", out(reg) fp);
+    }
+    fp
+}
+
+/// pc and frame pointer of the current user thread, saved on the last trap into the kernel
+pub fn os_user_regs() -> Option<(usize, usize)> {
+    let task = crate::task::current_task()?;
+    let inner = task.try_inner_exclusive_access()?;
+    // kernel threads have no user context
+    inner.res.as_ref()?;
+    let trap_cx = inner.get_trap_cx();
+    Some((trap_cx.sepc, trap_cx.x[8]))
+}
+
 /// # os_copy_from_user
 /// copy `len` bytes from user space addresss `usr_addr` to `kern_buf`
 pub fn os_copy_from_user(usr_addr: usize, kern_buf: *mut u8, len: usize) -> i32 {
@@ -104,6 +320,58 @@ pub fn os_copy_to_user(usr_addr: usize, kern_buf: *const u8, len: usize) -> i32
     0
 }
 
+/// a BPF object in the fd table of a process, it can not be read or written
+///
+/// fork shares it with the child, close drops the reference of the fd
+struct BpfObjectFile {
+    id: u32,
+    object: BpfObject,
+}
+
+impl Drop for BpfObjectFile {
+    fn drop(&mut self) {
+        bpf_object_uref_put(&self.object);
+    }
+}
+
+impl crate::fs::File for BpfObjectFile {
+    fn readable(&self) -> bool {
+        false
+    }
+    fn writable(&self) -> bool {
+        false
+    }
+    fn read(&self, _buf: crate::mm::UserBuffer) -> usize {
+        0
+    }
+    fn write(&self, _buf: crate::mm::UserBuffer) -> usize {
+        0
+    }
+    fn bpf_object(&self) -> Option<(u32, BpfObject)> {
+        Some((self.id, self.object.clone()))
+    }
+}
+
+/// # os_bpf_fd_install
+/// put object `id` into the fd table of the current process
+/// # return value
+/// * the new fd
+pub fn os_bpf_fd_install(id: u32, object: BpfObject) -> usize {
+    bpf_object_uref_get(&object);
+    let process = crate::task::current_process();
+    let mut inner = process.inner_exclusive_access();
+    let fd = inner.alloc_fd();
+    inner.fd_table[fd] = Some(Arc::new(BpfObjectFile { id, object }));
+    fd
+}
+
+/// the id and the object of `fd` in the current process, None if it is not a BPF object
+pub fn os_bpf_fd_get(fd: u32) -> Option<(u32, BpfObject)> {
+    let process = crate::task::current_process();
+    let inner = process.inner_exclusive_access();
+    inner.fd_table.get(fd as usize)?.as_ref()?.bpf_object()
+}
+
 /// copy within kernel space
 pub fn copy(dst: *mut u8, src: *const u8, len: usize) {
     let from = unsafe { from_raw_parts(src, len) };
@@ -134,6 +402,19 @@ pub fn get_generic_from_user<T: Copy>(user_addr: usize) -> T {
     attr
 }
 
+/// # get_attr_from_user
+/// copy an attr of type `T` from user space address `user_addr`
+/// * only `size` bytes are copied, fields beyond it are zeroed,
+///   so callers built against an older, shorter attr keep working
+pub fn get_attr_from_user<T: Copy>(user_addr: usize, size: usize) -> T {
+    let ret = vec![0 as u8; size_of::<T>()];
+    let buf = ret.as_ptr() as *mut u8;
+    os_copy_from_user(user_addr, buf, size.min(size_of::<T>()));
+    unsafe {
+        core::ptr::read_unaligned(buf as *const T)
+    }
+}
+
 /// convert a `BpfResult` to `i32` for syscall interface
 fn convert_result(result: BpfResult) -> i32 {
     match result {
@@ -147,8 +428,8 @@ fn convert_result(result: BpfResult) -> i32 {
 
 /// wrapper
 pub fn sys_bpf_map_create(attr: *const u8, size: usize) -> i32 {
-   // assert_eq!(size as usize, size_of::<MapAttr>());
-    let map_attr: MapAttr = get_generic_from_user(attr as usize);
+    // `map_flags` is zeroed for callers without it
+    let map_attr: MapAttr = get_attr_from_user(attr as usize, size);
     convert_result(bpf_map_create(map_attr))
 }
 
@@ -183,6 +464,52 @@ pub fn sys_bpf_map_get_next_key(attr: *const u8, size: usize) -> i32 {
     convert_result(ret)
 }
 
+/// wrapper
+pub fn sys_bpf_map_lookup_and_delete_elem(attr: *const u8, size: usize) -> i32 {
+    let map_op_attr: MapOpAttr = get_generic_from_user(attr as usize);
+    let ret = bpf_map_lookup_and_delete_elem(map_op_attr.map_fd, map_op_attr.key as *const u8, map_op_attr.value_or_nextkey as *mut u8, map_op_attr.flags, true);
+    convert_result(ret)
+}
+
+/// wrapper
+pub fn sys_bpf_map_freeze(attr: *const u8, size: usize) -> i32 {
+    let map_op_attr: MapOpAttr = get_attr_from_user(attr as usize, size);
+    convert_result(bpf_map_freeze(map_op_attr.map_fd))
+}
+
+/// # sys_bpf_map_batch
+/// wrapper of the BPF_MAP_*_BATCH commands
+/// * `count` and `out_batch` are written back into the attr even if the batch stops early,
+///   so user space knows how many elements were processed
+fn sys_bpf_map_batch(attr: *const u8, size: usize, op: fn(&mut MapBatchAttr) -> BpfResult) -> i32 {
+    let mut batch_attr: MapBatchAttr = get_attr_from_user(attr as usize, size);
+    let ret = op(&mut batch_attr);
+    os_copy_to_user(attr as usize, &batch_attr as *const MapBatchAttr as *const u8, size.min(size_of::<MapBatchAttr>()));
+    convert_result(ret)
+}
+
+/// wrapper
+pub fn sys_bpf_map_lookup_batch(attr: *const u8, size: usize) -> i32 {
+    sys_bpf_map_batch(attr, size, bpf_map_lookup_batch)
+}
+
+/// wrapper
+pub fn sys_bpf_map_update_batch(attr: *const u8, size: usize) -> i32 {
+    sys_bpf_map_batch(attr, size, bpf_map_update_batch)
+}
+
+/// wrapper
+pub fn sys_bpf_map_delete_batch(attr: *const u8, size: usize) -> i32 {
+    sys_bpf_map_batch(attr, size, bpf_map_delete_batch)
+}
+
+/// wrapper
+pub fn sys_bpf_ringbuf_read(attr: *const u8, size: usize) -> i32 {
+    let read_attr: RingBufReadAttr = get_attr_from_user(attr as usize, size);
+    let ret = bpf_ringbuf_read(read_attr.map_fd, read_attr.buf as *mut u8, read_attr.buf_size as usize);
+    convert_result(ret)
+}
+
 /// wrapper
 pub fn sys_bpf_program_attach(attr: *const u8, size: usize) -> i32 {
   //  assert_eq!(size, size_of::<KprobeAttachAttr>());
@@ -190,27 +517,139 @@ pub fn sys_bpf_program_attach(attr: *const u8, size: usize) -> i32 {
     let len = attach_attr.str_len as usize;
     let mut target_name_buf = vec![0 as u8; len];
     os_copy_from_user(attach_attr.target as usize, target_name_buf.as_mut_ptr(), len);
-    let target_name = unsafe {
-        core::str::from_utf8(
-            target_name_buf.as_slice()
-           // core::slice::from_raw_parts(attach_attr.target, attach_attr.str_len as usize)
-        ).unwrap()
+    let ret = match core::str::from_utf8(target_name_buf.as_slice()) {
+        Ok(target_name) => {
+            trace!("target name str: {}", target_name);
+            bpf_program_attach(target_name, attach_attr.prog_fd)
+        }
+        Err(_) => Err(EINVAL),
     };
-    trace!("target name str: {}", target_name);
-    convert_result(bpf_program_attach(target_name, attach_attr.prog_fd))
+    convert_result(ret)
 }
 
 /// wrapper
+pub fn sys_bpf_syscall_filter_install(prog_fd: u32) -> i32 {
+    convert_result(bpf_syscall_filter_install(prog_fd))
+}
+
+/// wrapper
+/// a null target detaches the program from every hookpoint
 pub fn sys_bpf_program_detach(attr: *const u8, size: usize) -> i32 {
     let detach_attr: KprobeAttachAttr = get_generic_from_user(attr as usize);
     trace!("detach fd {}", detach_attr.prog_fd);
-    convert_result(bpf_program_detach(detach_attr.prog_fd))
+    if detach_attr.target.is_null() || detach_attr.str_len == 0 {
+        return convert_result(bpf_program_detach(None, detach_attr.prog_fd));
+    }
+    let len = detach_attr.str_len as usize;
+    let mut target_name_buf = vec![0 as u8; len];
+    os_copy_from_user(detach_attr.target as usize, target_name_buf.as_mut_ptr(), len);
+    let ret = match core::str::from_utf8(target_name_buf.as_slice()) {
+        Ok(target_name) => bpf_program_detach(Some(target_name), detach_attr.prog_fd),
+        Err(_) => Err(EINVAL),
+    };
+    convert_result(ret)
+}
+
+/// wrapper
+pub fn sys_bpf_obj_pin(attr: *const u8, size: usize) -> i32 {
+    let pin_attr: ObjPinAttr = get_attr_from_user(attr as usize, size);
+    let path = unsafe { read_null_terminated_str(pin_attr.pathname as *const u8) };
+    convert_result(bpf_obj_pin(&path, pin_attr.bpf_fd))
+}
+
+/// wrapper
+pub fn sys_bpf_obj_get(attr: *const u8, size: usize) -> i32 {
+    let pin_attr: ObjPinAttr = get_attr_from_user(attr as usize, size);
+    let path = unsafe { read_null_terminated_str(pin_attr.pathname as *const u8) };
+    convert_result(bpf_obj_get(&path))
+}
+
+/// wrapper, the next id is written back into the attr
+pub fn sys_bpf_obj_get_next_id(attr: *const u8, size: usize, map: bool) -> i32 {
+    let mut id_attr: ObjIdAttr = get_attr_from_user(attr as usize, size);
+    let ret = bpf_obj_get_next_id(id_attr.start_id, map).map(|next_id| {
+        id_attr.next_id = next_id as u32;
+        os_copy_to_user(attr as usize, &id_attr as *const ObjIdAttr as *const u8, size.min(size_of::<ObjIdAttr>()));
+        0
+    });
+    convert_result(ret)
+}
+
+/// wrapper
+pub fn sys_bpf_obj_get_fd_by_id(attr: *const u8, size: usize, map: bool) -> i32 {
+    let id_attr: ObjIdAttr = get_attr_from_user(attr as usize, size);
+    convert_result(bpf_obj_get_fd_by_id(id_attr.start_id, map))
+}
+
+/// # sys_bpf_obj_get_info_by_fd
+/// describe the map or program of an fd, the linux way
+/// # arguments
+/// * attr - a pointer that should points to a `ObjInfoAttr` objects
+/// * size - size of the attr in user space
+/// # procedure
+/// * copy at most `info_len` bytes of a `BpfMapInfo` or a `BpfProgInfo` to `info`
+/// * for programs, the user info is read first, the map ids and attach points are copied
+///   up to the capacity of its arrays, and the counts are set to the actual numbers
+/// * write the size filled in back into `info_len`
+pub fn sys_bpf_obj_get_info_by_fd(attr: *const u8, size: usize) -> i32 {
+    let mut info_attr: ObjInfoAttr = get_attr_from_user(attr as usize, size);
+    let info_len = info_attr.info_len as usize;
+    let ret = match bpf_object_get_fd(info_attr.bpf_fd) {
+        Some((id, BpfObject::Map(map))) => {
+            let info = bpf_map_get_info(id, &map);
+            let len = info_len.min(size_of::<BpfMapInfo>());
+            os_copy_to_user(info_attr.info as usize, &info as *const BpfMapInfo as *const u8, len);
+            Ok(len)
+        }
+        Some((id, BpfObject::Program(program))) => {
+            let user: BpfProgInfo = get_attr_from_user(info_attr.info as usize, info_len);
+            let (mut info, map_ids) = program.get_info(id);
+            let attach_points = bpf_program_attach_points(&program);
+            if user.map_ids != 0 {
+                let count = map_ids.len().min(user.nr_map_ids as usize);
+                os_copy_to_user(user.map_ids as usize, map_ids.as_ptr() as *const u8, count * size_of::<u32>());
+            }
+            if user.attach_points != 0 {
+                for (i, target) in attach_points.iter().take(user.nr_attach_points as usize).enumerate() {
+                    let buf = bpf_prog_target_buf(target);
+                    os_copy_to_user(user.attach_points as usize + i * BPF_PROG_TARGET_LEN, buf.as_ptr(), BPF_PROG_TARGET_LEN);
+                }
+            }
+            info.nr_attach_points = attach_points.len() as u32;
+            info.map_ids = user.map_ids;
+            info.attach_points = user.attach_points;
+            let len = info_len.min(size_of::<BpfProgInfo>());
+            os_copy_to_user(info_attr.info as usize, &info as *const BpfProgInfo as *const u8, len);
+            Ok(len)
+        }
+        None => Err(EBADF),
+    };
+    if let Ok(len) = ret {
+        info_attr.info_len = len as u32;
+        os_copy_to_user(attr as usize, &info_attr as *const ObjInfoAttr as *const u8, size.min(size_of::<ObjInfoAttr>()));
+    }
+    convert_result(ret.map(|_| 0))
 }
 
 /// wrapper
 /// this is a custome function, so we just copy from rCore
-pub fn sys_bpf_program_load_ex(prog: &mut [u8], map_info: &[(String, u32)]) -> i32 {
-    let ret = convert_result(bpf_program_load_ex(prog, &map_info));
+///
+/// without `prog_array` the fd of the only program is returned,
+/// otherwise the entries are copied to `prog_array` and their number is returned
+pub fn sys_bpf_program_load_ex(prog: &mut [u8], map_info: &[(String, u32)], prog_type: u32, prog_flags: u32, prog_array: u64, prog_array_len: u32, log: &mut VerifierLog) -> i32 {
+    let max_progs = match prog_array {
+        0 => 1,
+        _ => prog_array_len as usize,
+    };
+    let result = bpf_program_load_ex(prog, &map_info, prog_type, prog_flags, max_progs, log).map(|entries| {
+        if prog_array == 0 {
+            return entries[0].fd as usize;
+        }
+        let size = entries.len() * size_of::<ProgramSectionEntry>();
+        os_copy_to_user(prog_array as usize, entries.as_ptr() as *const u8, size);
+        entries.len()
+    });
+    let ret = convert_result(result);
     trace!("load ex ret: {}", ret);
     ret
 }
@@ -219,16 +658,17 @@ pub fn sys_bpf_program_load_ex(prog: &mut [u8], map_info: &[(String, u32)]) -> i
 /// a wrapper that parse the `attr_ptr` and then call `bpf_program_load_ex`
 /// # argumetns
 /// * attr_ptr - a pointer that should points to a `ProgramLoadExAttr` objects
-/// * size - unused
+/// * size - size of the attr in user space, fields it does not cover are zero
 /// # procedure
-/// * cast the attr using `get_generic_from_user`
+/// * cast the attr using `get_attr_from_user`
 /// * copy the BPF elf from user space 
 /// * copy the map fd info if there is one
-/// * call `sys_bpf_program_load_ex`
+/// * call `sys_bpf_program_load_ex`, which copies the loaded programs to `prog_array`
+/// * copy the verifier log back if a log buffer is given
 #[allow(unused_mut)]
 pub fn sys_preprocess_bpf_program_load_ex(attr_ptr: *const u8, size: usize) -> i32 {
 
-    let attr:ProgramLoadExAttr = get_generic_from_user(attr_ptr as usize);
+    let attr:ProgramLoadExAttr = get_attr_from_user(attr_ptr as usize, size);
 
    trace!("prog load attr\n prog_base:{:x} prog_size={} map_base:{:x} map_num={}", attr.elf_prog, attr.elf_size, attr.map_array as usize, attr.map_array_len);
     let base = attr.elf_prog as usize;
@@ -254,7 +694,53 @@ pub fn sys_preprocess_bpf_program_load_ex(attr_ptr: *const u8, size: usize) -> i
         }   
     }
 
-    sys_bpf_program_load_ex(&mut prog[..], &map_info[..])
+    let mut log = VerifierLog::new(attr.log_level);
+    let ret = sys_bpf_program_load_ex(&mut prog[..], &map_info[..], attr.prog_type, attr.prog_flags, attr.prog_array, attr.prog_array_len, &mut log);
+    os_copy_log_to_user(attr.log_buf as usize, attr.log_size as usize, &log);
+    ret
+}
+
+/// # sys_bpf_program_load
+/// a wrapper that parse the `attr_ptr` and then call `bpf_program_load`
+/// # arguments
+/// * attr_ptr - a pointer that should points to a `ProgramLoadAttr` objects
+/// * size - size of the attr in user space
+/// # procedure
+/// * cast the attr using `get_attr_from_user`
+/// * copy the instructions and the license from user space
+/// * call `bpf_program_load`
+/// * copy the verifier log back if a log buffer is given
+pub fn sys_bpf_program_load(attr_ptr: *const u8, size: usize) -> i32 {
+    let attr: ProgramLoadAttr = get_attr_from_user(attr_ptr as usize, size);
+    trace!("prog load attr\n type:{} insns:{:x} insn_cnt={}", attr.prog_type, attr.insns, attr.insn_cnt);
+    if attr.insns == 0 {
+        return convert_result(Err(EINVAL));
+    }
+    if attr.insn_cnt as usize > BPF_MAXINSNS {
+        return convert_result(Err(E2BIG));
+    }
+    let mut insns = vec![0u64; attr.insn_cnt as usize];
+    os_copy_from_user(attr.insns as usize, insns.as_mut_ptr() as *mut u8, insns.len() * size_of::<u64>());
+    if attr.license != 0 {
+        let license = unsafe { read_null_terminated_str(attr.license as *const u8) };
+        trace!("prog license: {}", license);
+    }
+
+    let mut log = VerifierLog::new(attr.log_level);
+    let ret = convert_result(bpf_program_load(attr.prog_type, &mut insns[..], attr.prog_flags, &mut log));
+    os_copy_log_to_user(attr.log_buf as usize, attr.log_size as usize, &log);
+    ret
+}
+
+/// copy the verifier log to a user buffer of `size` bytes,
+/// the log is truncated and always null terminated
+fn os_copy_log_to_user(buf: usize, size: usize, log: &VerifierLog) {
+    if buf == 0 || size == 0 {
+        return;
+    }
+    let len = log.buf.len().min(size - 1);
+    os_copy_to_user(buf, log.buf.as_ptr(), len);
+    os_copy_to_user(buf + len, [0u8].as_ptr(), 1);
 }
 
 /// read a C style string from user space pointed by `ptr`
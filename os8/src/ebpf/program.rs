@@ -3,11 +3,16 @@
 //! load the program into kernel and do the relocation
  
 use alloc::string::String;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use lazy_static::lazy_static;
+use lock::Mutex;
 use xmas_elf;
 use xmas_elf::header::Machine;
 use xmas_elf::sections::*;
-use xmas_elf::symbol_table::Entry;
+use xmas_elf::symbol_table::{Entry, Type};
+use core::fmt::Write;
 
 #[cfg(target_arch = "riscv64")]
 use ebpf2rv::compile;
@@ -16,7 +21,13 @@ use super::{
     *,
     consts::*,
     helpers::*,
-    retcode::BpfErrorCode::*,
+    insn::*,
+    interpreter::interpret,
+    map::{bpf_map_get_fd, bpf_prog_array_get, bpf_map_create_global_data, bpf_map_direct_value_addr, SharedBpfMap},
+    verifier::{bpf_verify, BpfSubprog, VerifierLog, BPF_MAXINSNS},
+    tracepoints::bpf_prog_ctx_size,
+    osutil::os_ticks_to_ns,
+    retcode::BpfErrorCode::{self, *},
     retcode::BpfResult,
 };
 
@@ -34,46 +45,255 @@ pub struct ProgramLoadExAttr {
     pub elf_size: u32,
     pub map_array_len: u32,
     pub map_array: *const MapFdEntry,
+    /// same as the one of `ProgramLoadAttr`, programs calling functions of `.text`
+    /// make BPF-to-BPF calls, so they are interpreted
+    pub prog_flags: u32,
+    pub log_level: u32,
+    pub log_size: u32,
+    pub log_buf: u64,
+    /// BPF_PROG_TYPE_UNSPEC is treated as BPF_PROG_TYPE_KPROBE,
+    /// only used by sections whose name tells no program type
+    pub prog_type: u32,
+    /// number of entries in `prog_array`
+    pub prog_array_len: u32,
+    /// receives a `ProgramSectionEntry` for every program of the object.
+    /// if given, the number of programs is returned, otherwise the object
+    /// must have a single program and its fd is returned
+    pub prog_array: u64,
+}
+
+/// length of the attach target of a `ProgramSectionEntry`, including the null terminator
+pub const BPF_PROG_TARGET_LEN: usize = 128;
+
+/// a program loaded from an executable section by BPF_PROG_LOAD_EX
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ProgramSectionEntry {
+    pub fd: u32,
+    pub prog_type: u32,
+    /// null terminated target for BPF_PROG_ATTACH inferred from the section name,
+    /// empty if the name tells none or the target does not fit
+    pub target: [u8; BPF_PROG_TARGET_LEN],
+}
+
+impl ProgramSectionEntry {
+    fn new(fd: usize, prog_type: u32, target: &str) -> Self {
+        Self { fd: fd as u32, prog_type, target: bpf_prog_target_buf(target) }
+    }
+}
+
+/// null terminated copy of an attach target, empty if it does not fit
+pub fn bpf_prog_target_buf(target: &str) -> [u8; BPF_PROG_TARGET_LEN] {
+    let mut buf = [0u8; BPF_PROG_TARGET_LEN];
+    if target.len() < BPF_PROG_TARGET_LEN {
+        buf[..target.len()].copy_from_slice(target.as_bytes());
+    }
+    buf
+}
+
+/// ProgramLoadAttr, follows the linux convection
+///
+/// Used by BPF_PROG_LOAD
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ProgramLoadAttr {
+    pub prog_type: u32,
+    pub insn_cnt: u32,
+    pub insns: u64,
+    pub license: u64,
+    pub log_level: u32,
+    pub log_size: u32,
+    pub log_buf: u64,
+    pub kern_version: u32,
+    /// `BPF_F_INTERPRETER` skips the JIT. programs the JIT cannot compile, the ones
+    /// calling `bpf_tail_call` or making BPF-to-BPF calls, are interpreted as well,
+    /// with a note in the log at level 1 and a zero `jited_prog_len` in their info
+    pub prog_flags: u32,
 }
 
 /// actual defination of BpfProgram,
-/// bpf_insns is unused
+/// bpf_insns keeps the relocated instructions for the interpreter
 pub struct BpfProgram {
+    pub prog_type: u32,
     bpf_insns: Option<Vec<u64>>,
     jited_prog: Option<Vec<u32>>, // TODO: should be something like Vec<u8>
-    pub map_fd_table: Option<Vec<u32>>,
+    /// ids of the maps, relocated map accesses of an elf point into it,
+    /// shared by all programs of the elf
+    pub map_fd_table: Option<Arc<Vec<u32>>>,
+    /// maps used by the program and their ids, kept alive as long as the program
+    maps: Vec<(u32, SharedBpfMap)>,
+    /// number of runs from attach points
+    run_cnt: AtomicU64,
+    /// total time of those runs in timer ticks
+    run_time: AtomicU64,
+}
+
+/// BpfProgInfo, follows the linux convection
+///
+/// Filled by BPF_OBJ_GET_INFO_BY_FD. `nr_map_ids` and `nr_attach_points` are
+/// the capacity of the user arrays on input, and the actual counts on output
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct BpfProgInfo {
+    pub prog_type: u32,
+    pub id: u32,
+    /// number of eBPF instructions
+    pub insn_cnt: u32,
+    /// bytes of JIT code, 0 if the program is interpreted, because of `BPF_F_INTERPRETER`
+    /// or instructions the JIT cannot compile
+    pub jited_prog_len: u32,
+    pub run_time_ns: u64,
+    pub run_cnt: u64,
+    pub nr_map_ids: u32,
+    pub nr_attach_points: u32,
+    /// array of u32 map ids
+    pub map_ids: u64,
+    /// array of `BPF_PROG_TARGET_LEN` byte null terminated targets, like the ones of BPF_PROG_ATTACH
+    pub attach_points: u64,
+}
+
+/// maximum number of tail calls in a chain, follows linux
+pub const MAX_TAIL_CALL_CNT: usize = 33;
+
+/// state of a running chain of tail calls
+struct TailCallState {
+    /// type of the running programs, every program in the chain has the same type
+    prog_type: u32,
+    cnt: usize,
+    /// set by `bpf_tail_call`, run after the current program returns
+    pending: Option<Arc<BpfProgram>>,
+}
+
+lazy_static! {
+    /// a state for each run in progress, the innermost last. a program run while
+    /// another one is running, like one attached to a function its helper calls,
+    /// gets its own state and leaves the outer chain alone
+    static ref TAIL_CALLS: Mutex<Vec<TailCallState>> = Mutex::new(Vec::new());
+}
+
+/// # bpf_tail_call_prepare
+/// called by `bpf_tail_call`, the target replaces the running program once it leaves
+/// # return value
+/// * ENOENT if the slot is empty, E2BIG if the chain is too long
+pub fn bpf_tail_call_prepare(map_fd: u32, index: u32) -> BpfResult {
+    let target = bpf_prog_array_get(map_fd, index).ok_or(ENOENT)?;
+    let mut runs = TAIL_CALLS.lock();
+    let tail_call = runs.last_mut().ok_or(EINVAL)?;
+    if tail_call.cnt >= MAX_TAIL_CALL_CNT {
+        return Err(E2BIG);
+    }
+    if target.prog_type != tail_call.prog_type {
+        return Err(EINVAL);
+    }
+    tail_call.cnt += 1;
+    tail_call.pending = Some(target);
+    Ok(0)
 }
 
 impl BpfProgram {
-    /// run cast pointer to a function and runs it
+    /// run the program, then the targets of its tail calls
+    ///
+    /// the return value is the one of the last program in the chain
     pub fn run(&self, ctx: *const u8) -> i64 {
+        TAIL_CALLS.lock().push(TailCallState {
+            prog_type: self.prog_type,
+            cnt: 0,
+            pending: None,
+        });
+        let mut result = self.run_one(ctx);
+        loop {
+            let next = TAIL_CALLS.lock().last_mut().and_then(|tail_call| tail_call.pending.take());
+            match next {
+                Some(program) => result = program.run_one(ctx),
+                None => break,
+            }
+        }
+        TAIL_CALLS.lock().pop();
+        result
+    }
+
+    /// run cast pointer to a function and runs it
+    fn run_one(&self, ctx: *const u8) -> i64 {
         if let Some(compiled_code) = &self.jited_prog {
-            let result = unsafe {
-                type JitedFn = unsafe fn(*const u8) -> i64;
-                let f = core::mem::transmute::<*const u32, JitedFn>(compiled_code.as_ptr());
-                f(ctx)
-            };
-            return result;
+            return jit_run(compiled_code, ctx);
+        }
+        self.interpret(ctx)
+    }
+
+    /// account a run from an attach point that took `ticks`
+    pub fn record_run(&self, ticks: usize) {
+        self.run_cnt.fetch_add(1, Ordering::Relaxed);
+        self.run_time.fetch_add(ticks as u64, Ordering::Relaxed);
+    }
+
+    /// # get_info
+    /// describe the program for BPF_OBJ_GET_INFO_BY_FD
+    /// # return value
+    /// * the info without user arrays, and the ids of the maps used
+    pub fn get_info(&self, id: u32) -> (BpfProgInfo, Vec<u32>) {
+        let map_ids: Vec<u32> = self.maps.iter().map(|(id, _)| *id).collect();
+        let info = BpfProgInfo {
+            prog_type: self.prog_type,
+            id,
+            insn_cnt: self.bpf_insns.as_ref().map_or(0, |insns| insns.len()) as u32,
+            jited_prog_len: self.jited_prog.as_ref().map_or(0, |code| code.len() * core::mem::size_of::<u32>()) as u32,
+            run_time_ns: os_ticks_to_ns(self.run_time.load(Ordering::Relaxed)),
+            run_cnt: self.run_cnt.load(Ordering::Relaxed),
+            nr_map_ids: map_ids.len() as u32,
+            nr_attach_points: 0,
+            map_ids: 0,
+            attach_points: 0,
+        };
+        (info, map_ids)
+    }
+
+    /// run the program in the interpreter regardless of the JIT code
+    ///
+    /// a malformed instruction aborts the program and returns the negated error code
+    pub fn interpret(&self, ctx: *const u8) -> i64 {
+        let insns = match &self.bpf_insns {
+            Some(insns) => insns,
+            None => return -(EINVAL as i64),
+        };
+        match interpret(insns, &HELPER_FN_TABLE, ctx) {
+            Ok(ret) => ret as i64,
+            Err(err) => {
+                error!("bpf interpreter aborted: {:?}", err);
+                -(err as i64)
+            }
         }
-        todo!("eBPF interpreter missing")
     }
 }
 
-/// load the bpf program with map config into kernel
+/// load the bpf programs of an elf with map config into kernel
 /// # arguments
 /// * `prog` - &mut [u8] the program elf in hexvalue
-/// * `map_info` - [(String, u32)] that store map names and their fd
+/// * `map_info` - [(String, u32)] that store map names and their fd in the current process
+/// * `prog_type` - type of the programs whose section name tells none
+/// * `prog_flags` - load flags, `BPF_F_INTERPRETER` skips the JIT
+/// * `max_progs` - number of programs the caller can take
+/// * `log` - verifier log, holds the reason if a program is rejected
 /// # procedure
 /// * parse the elf
-/// * build the map fd table
-/// * relocate access to map by map fd table
+/// * build the map fd table, holding the map ids
+/// * back `.rodata`, `.data` and `.bss` sections with internal array maps
+/// * relocate access to map by map fd table, and access to global data by the internal maps
 /// * relocate helper functions
-/// * JIT the prog
-/// * create BPF objects 
+/// * every executable section but `.text` is a program, the type and attach target
+///   come from the section name. an elf with only `.text` is a single program
+/// * link the `.text` functions each program calls into it
+/// * verify the progs
+/// * JIT the progs, unless the interpreter is requested
+/// * create BPF objects once every program is accepted
 /// # return value
-/// * fd of the program 
-pub fn bpf_program_load_ex(prog: &mut [u8], map_info: &[(String, u32)]) -> BpfResult {
+/// * the fd, type and attach target of each program, in section order
+/// * ENOSPC if there are more than `max_progs` programs
+pub fn bpf_program_load_ex(prog: &mut [u8], map_info: &[(String, u32)], prog_type: u32, prog_flags: u32, max_progs: usize, log: &mut VerifierLog) -> Result<Vec<ProgramSectionEntry>, BpfErrorCode> {
     trace!("bpf program load ex");
+    let prog_type = match prog_type {
+        BPF_PROG_TYPE_UNSPEC => BPF_PROG_TYPE_KPROBE,
+        _ => prog_type,
+    };
     let _base = prog.as_ptr();
     let elf = xmas_elf::ElfFile::new(prog).map_err(|_| EINVAL)?;
     match elf.header.pt2.machine().as_machine() {
@@ -84,10 +304,14 @@ pub fn bpf_program_load_ex(prog: &mut [u8], map_info: &[(String, u32)]) -> BpfRe
     // build map fd table. storage must be fixed after this.
 
     let mut map_fd_table = Vec::with_capacity(200);
+    let mut maps = Vec::with_capacity(map_info.len());
     for map_fd in map_info {
-        map_fd_table.push(map_fd.1);
-        trace!("bpf map pushed fd: {}", map_fd.1);
+        let (id, map) = bpf_map_get_fd(map_fd.1).map_err(|_| EBADF)?;
+        map_fd_table.push(id);
+        maps.push((id, map));
+        trace!("bpf map pushed fd: {} id: {}", map_fd.1, id);
     }
+    let map_fd_table = Arc::new(map_fd_table);
 
     // build index -> map_fd variable address mapping
     let mut map_symbols = BTreeMap::new();
@@ -113,6 +337,50 @@ pub fn bpf_program_load_ex(prog: &mut [u8], map_info: &[(String, u32)]) -> BpfRe
         return Err(ENOENT);
     }
 
+    // global data sections become single-element array maps, indexed by section
+    let mut data_maps = BTreeMap::new();
+    for (sec_idx, sec_hdr) in elf.section_iter().enumerate() {
+        let name = sec_hdr.get_name(&elf).unwrap_or("");
+        let is_data = [".rodata", ".data", ".bss"].iter()
+            .any(|prefix| name == *prefix || name.starts_with(&alloc::format!("{}.", prefix)));
+        if !is_data || sec_hdr.size() == 0 {
+            continue;
+        }
+        let zeroed;
+        let data = match sec_hdr.get_type() {
+            Ok(ShType::NoBits) => {
+                zeroed = vec![0u8; sec_hdr.size() as usize];
+                &zeroed[..]
+            }
+            _ => sec_hdr.raw_data(&elf),
+        };
+        let map_flags = match name.starts_with(".rodata") {
+            true => BPF_F_RDONLY_PROG,
+            false => 0,
+        };
+        let (id, map) = bpf_map_create_global_data(data, map_flags)?;
+        trace!("bpf global data {} size: {} map id: {}", name, data.len(), id);
+        data_maps.insert(sec_idx as u16, id);
+        maps.push((id, map));
+    }
+    let symbols = match sym_tab_hdr.get_data(&elf) {
+        Ok(SectionData::SymbolTable64(sym_entries)) => sym_entries,
+        _ => &[],
+    };
+
+    // symbol index -> (map id, offset of the symbol in the section)
+    let mut data_symbols = BTreeMap::new();
+    if let Ok(SectionData::SymbolTable64(sym_entries)) = sym_tab_hdr.get_data(&elf) {
+        for (sym_idx, sym) in sym_entries.iter().enumerate() {
+            if let Some(&id) = data_maps.get(&sym.shndx()) {
+                data_symbols.insert(sym_idx, (id, sym.value()));
+            }
+        }
+    }
+
+    // (section, insn) of a BPF-to-BPF call -> (section, insn) of the callee
+    let mut calls = BTreeMap::new();
+
     // relocate maps
     for sec_hdr in elf.section_iter() {
         if let Ok(ShType::Rel) = sec_hdr.get_type() {
@@ -121,6 +389,8 @@ pub fn bpf_program_load_ex(prog: &mut [u8], map_info: &[(String, u32)]) -> BpfRe
                 let target_sec_name = &sec_name[4..]; // ".relXXX"
                 let target_sec_hdr = elf.find_section_by_name(target_sec_name).ok_or(ENOENT)?;
                 let base = target_sec_hdr.raw_data(&elf).as_ptr() as usize;
+                let is_code = target_sec_hdr.flags() & SHF_EXECINSTR != 0;
+                let target_sec_idx = sec_hdr.info() as u16;
 
                 for rel in rel_entries {
                     let offset = rel.get_offset() as usize;
@@ -130,6 +400,27 @@ pub fn bpf_program_load_ex(prog: &mut [u8], map_info: &[(String, u32)]) -> BpfRe
                     let relocated_addr: usize;
                     if let Some(&addr) = map_symbols.get(&sym_idx) {
                         relocated_addr = addr;
+                    } else if let (true, R_BPF_64_64, Some(&(id, sym_off))) = (is_code, rel_type, data_symbols.get(&sym_idx)) {
+                        // like libbpf, the loaded address is map value + symbol offset + addend in imm
+                        let p = (base + offset) as *mut u64;
+                        unsafe {
+                            let insn = BpfInsn::decode(p.read_unaligned());
+                            let next = BpfInsn::decode(p.add(1).read_unaligned());
+                            let off = (insn.imm as i64 + sym_off as i64) as i32;
+                            p.write_unaligned(BpfInsn { src: BPF_PSEUDO_MAP_VALUE as u8, imm: id as i32, ..insn }.encode());
+                            p.add(1).write_unaligned(BpfInsn { imm: off, ..next }.encode());
+                        }
+                        trace!("bpf prog relocate entry idx: {} offset:{:x} to global data map: {}", sym_idx, offset, id);
+                        continue;
+                    } else if let (true, Some(sym)) = (is_code, symbols.get(sym_idx)) {
+                        // like libbpf, the callee is the symbol + imm + 1, linked into each program later
+                        let insn = BpfInsn::decode(unsafe { ((base + offset) as *const u64).read_unaligned() });
+                        if insn.code == BPF_JMP | BPF_CALL && insn.src as u32 == BPF_PSEUDO_CALL {
+                            let callee = (sym.value() / 8) as i64 + insn.imm as i64 + 1;
+                            calls.insert((target_sec_idx, offset / 8), (sym.shndx(), callee));
+                            trace!("bpf prog relocate call offset:{:x} to section {} insn {}", offset, sym.shndx(), callee);
+                        }
+                        continue;
                     } else {
                         continue;
                     }
@@ -165,36 +456,350 @@ pub fn bpf_program_load_ex(prog: &mut [u8], map_info: &[(String, u32)]) -> BpfRe
         }
     }
 
+    let mut text = None;
+    let mut sections = Vec::new();
+    for (sec_idx, sec_hdr) in elf.section_iter().enumerate() {
+        if sec_hdr.flags() & SHF_EXECINSTR == 0 || sec_hdr.size() == 0 {
+            continue;
+        }
+        let name = sec_hdr.get_name(&elf).unwrap_or("");
+        let code = section_insns(sec_hdr.raw_data(&elf));
+        match name {
+            ".text" => text = Some(TextSection { idx: sec_idx as u16, code, funcs: Vec::new() }),
+            _ => sections.push((sec_idx as u16, name, code)),
+        }
+    }
+    if let Some(text) = text.as_mut() {
+        text.funcs = symbols.iter()
+            .filter(|sym| sym.shndx() == text.idx && sym.get_type() == Ok(Type::Func))
+            .map(|sym| ((sym.value() / 8) as usize, (sym.size() / 8) as usize))
+            .collect();
+        if sections.is_empty() {
+            sections.push((text.idx, ".text", text.code.clone()));
+        }
+    }
+    if sections.is_empty() {
+        return Err(ENOENT);
+    }
+    if sections.len() > max_progs {
+        warn!("bpf elf has {} programs, only {} can be returned", sections.len(), max_progs);
+        return Err(ENOSPC);
+    }
+
+    let mut programs = Vec::with_capacity(sections.len());
+    for (sec_idx, name, code) in &sections {
+        let (sec_type, target) = bpf_section_target(name).unwrap_or((prog_type, String::new()));
+        trace!("bpf program in section {}, type: {} target: {}", name, sec_type, target);
+        let bpf_insns = bpf_link_program(*sec_idx, code, text.as_ref(), &calls)?;
+        let program = bpf_program_build(sec_type, &bpf_insns, Some(map_fd_table.clone()), maps.clone(), prog_flags, log)
+            .map_err(|err| {
+                let _ = writeln!(log.buf, "in section {}", name);
+                err
+            })?;
+        programs.push((program, sec_type, target));
+    }
+
+    let mut entries = Vec::with_capacity(programs.len());
+    for (program, sec_type, target) in programs {
+        let fd = bpf_object_create_program(program)?;
+        entries.push(ProgramSectionEntry::new(fd, sec_type, &target));
+    }
+    trace!("bpf prog load ex finished, {} programs", entries.len());
+    Ok(entries)
+}
+
+/// `.text` of an elf, holding the functions called by the programs
+struct TextSection {
+    idx: u16,
+    code: Vec<u64>,
+    /// (first insn, number of insns) of each function
+    funcs: Vec<(usize, usize)>,
+}
+
+/// instructions copied into a program from `len` insns at `start` of section `sec`
+struct LinkedCode {
+    base: usize,
+    sec: u16,
+    start: usize,
+    len: usize,
+}
+
+/// instructions of an elf section, the data is not always 8-byte aligned
+fn section_insns(data: &[u8]) -> Vec<u64> {
+    data.chunks_exact(8).map(|insn| {
+        let mut raw = [0u8; 8];
+        raw.copy_from_slice(insn);
+        u64::from_ne_bytes(raw)
+    }).collect()
+}
+
+/// # bpf_section_target
+/// infer the program type and attach target from a section name, like libbpf
+/// * `kprobe/X` - kprobe at `kprobe$X`
+/// * `kretprobe/X` - kprobe at `kretprobe@exit$X`
+/// * `tracepoint/.../X` - tracepoint at `tracepoint$X`
+/// * `perf_event/N` - perf event at `perf_event$N`
+/// * `uprobe/P:O` - uprobe at `uprobe$P:O`
+/// * `seccomp` - syscall filter, installed instead of attached
+/// # return value
+/// * None if the name tells no program type, the target is empty if only the type is known
+fn bpf_section_target(name: &str) -> Option<(u32, String)> {
+    let (kind, rest) = name.split_once('/').unwrap_or((name, ""));
+    let (prog_type, target) = match kind {
+        "kprobe" => (BPF_PROG_TYPE_KPROBE, alloc::format!("kprobe${}", rest)),
+        "kretprobe" => (BPF_PROG_TYPE_KPROBE, alloc::format!("kretprobe@exit${}", rest)),
+        "tracepoint" | "tp" => (BPF_PROG_TYPE_TRACEPOINT, alloc::format!("tracepoint${}", rest.rsplit('/').next().unwrap_or(rest))),
+        "perf_event" => (BPF_PROG_TYPE_PERF_EVENT, alloc::format!("perf_event${}", rest)),
+        "uprobe" => (BPF_PROG_TYPE_KPROBE, alloc::format!("uprobe${}", rest)),
+        "seccomp" => return Some((BPF_PROG_TYPE_SYSCALL_FILTER, String::new())),
+        _ => return None,
+    };
+    match rest.is_empty() {
+        true => Some((prog_type, String::new())),
+        false => Some((prog_type, target)),
+    }
+}
+
+/// # bpf_link_program
+/// copy a program section and the `.text` functions it calls into one program,
+/// then point its BPF-to-BPF calls to the copies
+/// # arguments
+/// * sec_idx, code - the program section
+/// * text - `.text` of the elf, if any
+/// * calls - relocated calls, calls without relocation stay in their section
+/// # return value
+/// * the linked instructions, EINVAL if a callee is not a function of `.text`
+fn bpf_link_program(sec_idx: u16, code: &[u64], text: Option<&TextSection>, calls: &BTreeMap<(u16, usize), (u16, i64)>) -> Result<Vec<u64>, BpfErrorCode> {
+    let mut insns = code.to_vec();
+    let mut linked = vec![LinkedCode { base: 0, sec: sec_idx, start: 0, len: code.len() }];
+    // appended functions are scanned too, they may call further
+    let mut pc = 0;
+    while pc < insns.len() {
+        let insn = BpfInsn::decode(insns[pc]);
+        if insn.code != BPF_JMP | BPF_CALL || insn.src as u32 != BPF_PSEUDO_CALL {
+            pc += 1;
+            continue;
+        }
+        let from = linked.iter().find(|piece| pc >= piece.base && pc < piece.base + piece.len).ok_or(EINVAL)?;
+        let local = from.start + pc - from.base;
+        let (callee_sec, callee) = match calls.get(&(from.sec, local)) {
+            Some(&callee) => callee,
+            None => (from.sec, local as i64 + insn.imm as i64 + 1),
+        };
+        if callee < 0 {
+            return Err(EINVAL);
+        }
+        let callee = callee as usize;
+        let found = linked.iter()
+            .find(|piece| piece.sec == callee_sec && callee >= piece.start && callee < piece.start + piece.len)
+            .map(|piece| piece.base + callee - piece.start);
+        let target = match (found, text) {
+            (Some(target), _) => target,
+            (None, Some(text)) if text.idx == callee_sec => {
+                let &(start, len) = text.funcs.iter()
+                    .find(|&&(start, len)| callee >= start && callee < start + len)
+                    .ok_or(EINVAL)?;
+                let code = text.code.get(start..start + len).ok_or(EINVAL)?;
+                let base = insns.len();
+                insns.extend_from_slice(code);
+                linked.push(LinkedCode { base, sec: text.idx, start, len });
+                base + callee - start
+            }
+            _ => {
+                warn!("bpf call at insn {} of section {} has no callee", local, from.sec);
+                return Err(EINVAL);
+            }
+        };
+        insns[pc] = BpfInsn { imm: (target as i64 - pc as i64 - 1) as i32, ..insn }.encode();
+        pc += 1;
+    }
+    Ok(insns)
+}
+
+/// # bpf_program_load
+/// load a program from raw eBPF instructions, the linux way
+/// # arguments
+/// * `prog_type` - type of the program, decides where it can be attached
+/// * `insns` - the instructions, copied from user space
+/// * `prog_flags` - load flags, `BPF_F_INTERPRETER` skips the JIT
+/// * `log` - verifier log, holds the reason if the program is rejected
+/// # procedure
+/// * replace map fds in `LD_IMM64` marked with `BPF_PSEUDO_MAP_FD` or `BPF_PSEUDO_MAP_VALUE`
+///   by map ids, the verifier only accepts maps referred this way
+/// * after verification, `BPF_PSEUDO_MAP_FD` becomes a plain constant of the map id,
+///   helpers take map ids directly, and `BPF_PSEUDO_MAP_VALUE` the address of the value
+/// * verify, JIT and create BPF objects like `bpf_program_load_ex`
+/// # return value
+/// * fd of the program
+pub fn bpf_program_load(prog_type: u32, insns: &mut [u64], prog_flags: u32, log: &mut VerifierLog) -> BpfResult {
+    trace!("bpf program load, type: {} insn_cnt: {}", prog_type, insns.len());
+    if insns.is_empty() {
+        return Err(EINVAL);
+    }
+    if insns.len() > BPF_MAXINSNS {
+        return Err(E2BIG);
+    }
+
+    let mut maps = Vec::new();
+    let mut pc = 0;
+    while pc < insns.len() {
+        let insn = BpfInsn::decode(insns[pc]);
+        if insn.code == BPF_LD_IMM64 && insn.src != 0 {
+            let src = insn.src as u32;
+            if (src != BPF_PSEUDO_MAP_FD && src != BPF_PSEUDO_MAP_VALUE) || pc + 1 >= insns.len() {
+                return Err(EINVAL);
+            }
+            let fd = insn.imm as u32;
+            let (id, map) = match bpf_map_get_fd(fd) {
+                Ok(map) => map,
+                Err(_) => {
+                    warn!("bpf program load, insn {} refers to an invalid map fd {}", pc, fd);
+                    return Err(EBADF);
+                }
+            };
+            if !maps.iter().any(|(used, _)| *used == id) {
+                maps.push((id, map));
+            }
+            insns[pc] = BpfInsn { imm: id as i32, ..insn }.encode();
+            if src == BPF_PSEUDO_MAP_FD {
+                let next = BpfInsn::decode(insns[pc + 1]);
+                insns[pc + 1] = BpfInsn { imm: 0, ..next }.encode();
+            }
+        }
+        pc += match insn.code {
+            BPF_LD_IMM64 => 2,
+            _ => 1,
+        };
+    }
+
+    let program = bpf_program_build(prog_type, insns, None, maps, prog_flags, log)?;
+    let fd = bpf_object_create_program(program)?;
+    trace!("bpf prog load finished!");
+    Ok(fd)
+}
+
+/// verify and JIT the relocated instructions into a program
+fn bpf_program_build(prog_type: u32, bpf_insns: &[u64], map_fd_table: Option<Arc<Vec<u32>>>, maps: Vec<(u32, SharedBpfMap)>, prog_flags: u32, log: &mut VerifierLog) -> Result<BpfProgram, BpfErrorCode> {
+    let ctx_size = bpf_prog_ctx_size(prog_type).ok_or(EINVAL)?;
+    let table = map_fd_table.as_deref().map_or(&[][..], |table| &table[..]);
+    let map_ids: Vec<u32> = maps.iter().map(|(id, _)| *id).collect();
+    let subprogs = bpf_verify(bpf_insns, table, &map_ids, ctx_size, log)?;
+    let mut bpf_insns = bpf_insns.to_vec();
+    bpf_fixup_map_values(&mut bpf_insns)?;
+    bpf_fixup_calls(&mut bpf_insns, &subprogs);
+    let bpf_insns = &bpf_insns[..];
+
     // compile eBPF code
     info!("before compile");
-    let sec_hdr = elf.find_section_by_name(".text").ok_or(ENOENT)?;
-    let code = sec_hdr.raw_data(&elf);
-    let bpf_insns = unsafe {
-        core::slice::from_raw_parts(
-            code.as_ptr() as *const u64,
-            code.len() / core::mem::size_of::<u64>(),
-        )
+    let unsupported = bpf_jit_unsupported(bpf_insns);
+    if let Some(reason) = unsupported {
+        if prog_flags & BPF_F_INTERPRETER == 0 && log.level > 0 {
+            let _ = writeln!(log.buf, "not JIT compiled: {}", reason);
+        }
+    }
+    let jited_prog = if prog_flags & BPF_F_INTERPRETER != 0 || unsupported.is_some() {
+        None
+    } else {
+        jit_compile(bpf_insns)
     };
+
+    Ok(BpfProgram {
+        prog_type,
+        bpf_insns: Some(bpf_insns.to_vec()),
+        jited_prog,
+        map_fd_table,
+        maps,
+        run_cnt: AtomicU64::new(0),
+        run_time: AtomicU64::new(0),
+    })
+}
+
+/// replace `LD_IMM64` marked with `BPF_PSEUDO_MAP_VALUE` by the address of the map value,
+/// the verifier has checked the offset, and those marked with `BPF_PSEUDO_MAP_FD` by the map id
+fn bpf_fixup_map_values(bpf_insns: &mut [u64]) -> BpfResult {
+    let mut pc = 0;
+    while pc < bpf_insns.len() {
+        let insn = BpfInsn::decode(bpf_insns[pc]);
+        if insn.code == BPF_LD_IMM64 && insn.src as u32 == BPF_PSEUDO_MAP_FD {
+            bpf_insns[pc] = BpfInsn { src: 0, ..insn }.encode();
+        }
+        if insn.code == BPF_LD_IMM64 && insn.src as u32 == BPF_PSEUDO_MAP_VALUE {
+            let next = BpfInsn::decode(bpf_insns[pc + 1]);
+            let base = bpf_map_direct_value_addr(insn.imm as u32).ok_or(EINVAL)?;
+            let addr = (base as u64).wrapping_add(next.imm as u64);
+            bpf_insns[pc] = BpfInsn { src: 0, imm: addr as u32 as i32, ..insn }.encode();
+            bpf_insns[pc + 1] = BpfInsn { imm: (addr >> 32) as u32 as i32, ..next }.encode();
+        }
+        pc += match insn.code {
+            BPF_LD_IMM64 => 2,
+            _ => 1,
+        };
+    }
+    Ok(0)
+}
+
+/// store the stack depth of the caller in `off` of each BPF-to-BPF call,
+/// the interpreter puts the frame of the callee below it
+fn bpf_fixup_calls(bpf_insns: &mut [u64], subprogs: &[BpfSubprog]) {
+    for pc in 0..bpf_insns.len() {
+        let insn = BpfInsn::decode(bpf_insns[pc]);
+        if insn.code == BPF_JMP | BPF_CALL && insn.src as u32 == BPF_PSEUDO_CALL {
+            let caller = subprogs.partition_point(|subprog| subprog.start <= pc) - 1;
+            bpf_insns[pc] = BpfInsn { off: subprogs[caller].stack_depth as i16, ..insn }.encode();
+        }
+    }
+}
+
+/// does the program make BPF-to-BPF calls
+fn has_pseudo_call(bpf_insns: &[u64]) -> bool {
+    bpf_insns.iter().any(|&raw| {
+        let insn = BpfInsn::decode(raw);
+        insn.code == BPF_JMP | BPF_CALL && insn.src as u32 == BPF_PSEUDO_CALL
+    })
+}
+
+/// why the JIT cannot compile the program, which is interpreted instead
+fn bpf_jit_unsupported(bpf_insns: &[u64]) -> Option<&'static str> {
+    if has_tail_call(bpf_insns) {
+        // JIT code cannot leave the program on a tail call
+        return Some("bpf_tail_call is only supported by the interpreter");
+    }
+    if has_pseudo_call(bpf_insns) {
+        // JIT code does not keep frames for BPF-to-BPF calls
+        return Some("BPF-to-BPF calls are only supported by the interpreter");
+    }
+    None
+}
+
+/// does the program call `bpf_tail_call`
+fn has_tail_call(bpf_insns: &[u64]) -> bool {
+    bpf_insns.iter().any(|&raw| {
+        let insn = BpfInsn::decode(raw);
+        insn.code == BPF_JMP | BPF_CALL && insn.imm as u32 as usize == BPF_FUNC_TAIL_CALL
+    })
+}
+
+/// run the native code of `jit_compile` with `ctx` in r1
+pub(super) fn jit_run(compiled_code: &[u32], ctx: *const u8) -> i64 {
+    unsafe {
+        type JitedFn = unsafe fn(*const u8) -> i64;
+        let f = core::mem::transmute::<*const u32, JitedFn>(compiled_code.as_ptr());
+        f(ctx)
+    }
+}
+
+/// compile eBPF instructions into native code
+#[cfg(target_arch = "riscv64")]
+pub(super) fn jit_compile(bpf_insns: &[u64]) -> Option<Vec<u32>> {
     let mut jit_ctx = compile::JitContext::new(bpf_insns);
     let helper_fn_table =
         unsafe { core::mem::transmute::<&[BpfHelperFn], &[u64]>(&HELPER_FN_TABLE) };
     compile::compile(&mut jit_ctx, helper_fn_table, 512);
-
-    let compiled_code = jit_ctx.code; // partial move
-
-    let program = BpfProgram {
-        bpf_insns: None, // currently we do not store original BPF instructions
-        jited_prog: Some(compiled_code),
-        map_fd_table: Some(map_fd_table),
-    };
-
-    let fd = bpf_allocate_fd();
-    bpf_object_create_program(fd, program);
-    trace!("bpf prog loadex finished!");
-    Ok(fd as usize)
+    Some(jit_ctx.code)
 }
 
+/// no JIT on other architectures, programs always run in the interpreter
 #[cfg(not(target_arch = "riscv64"))]
-pub fn bpf_program_load_ex(prog: &mut [u8], map_info: &[(String, u32)]) -> SysResult {
-    Err(EINVAL) // not supported
+pub(super) fn jit_compile(bpf_insns: &[u64]) -> Option<Vec<u32>> {
+    None
 }
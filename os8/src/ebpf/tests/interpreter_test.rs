@@ -0,0 +1,149 @@
+use super::insn::*;
+use super::interpreter::interpret;
+use super::helpers::{HELPER_FN_TABLE, BPF_FUNC_GET_SMP_PROCESSOR_ID, BPF_FUNC_MAP_LOOKUP_ELEM};
+use super::program::{jit_compile, jit_run};
+use super::verifier::{bpf_verify, VerifierLog};
+
+fn insn(code: u8, dst: u8, src: u8, off: i16, imm: i32) -> u64 {
+    BpfInsn { code, dst, src, off, imm }.encode()
+}
+
+fn mov(dst: u8, imm: i32) -> u64 {
+    insn(BPF_ALU64 | BPF_MOV | BPF_K, dst, 0, 0, imm)
+}
+
+fn exit() -> u64 {
+    insn(BPF_JMP | BPF_EXIT, 0, 0, 0, 0)
+}
+
+/// size of the ctx given to the test programs, a single u64
+const CTX_SIZE: usize = 8;
+
+/// verify, interpret and JIT the program, both have to return `expected` if given,
+/// otherwise the same value
+fn check_accept(name: &str, insns: &[u64], expected: Option<u64>) {
+    let mut log = VerifierLog::new(1);
+    if let Err(err) = bpf_verify(insns, &[], &[], CTX_SIZE, &mut log) {
+        panic!("{} rejected: {:?} {}", name, err, log.buf);
+    }
+    let ctx = 41u64;
+    let ctx = &ctx as *const u64 as *const u8;
+    let interpreted = interpret(insns, &HELPER_FN_TABLE, ctx).unwrap();
+    if let Some(expected) = expected {
+        assert_eq!(interpreted, expected, "{} interpreted", name);
+    }
+    if let Some(code) = jit_compile(insns) {
+        let jited = jit_run(&code, ctx) as u64;
+        assert_eq!(jited, interpreted, "{} jited", name);
+    }
+    println!("[eBPF interpreter test] {} OK", name);
+}
+
+fn check_reject(name: &str, insns: &[u64]) {
+    let mut log = VerifierLog::new(0);
+    assert!(bpf_verify(insns, &[], &[], CTX_SIZE, &mut log).is_err(), "{} accepted", name);
+    println!("[eBPF verifier test] {} rejected: {}", name, log.buf.trim());
+}
+
+pub fn run_interpreter_test() {
+    println!("running eBPF interpreter tests");
+    check_accept("alu64", &[
+        mov(0, 7),
+        mov(1, 3),
+        insn(BPF_ALU64 | BPF_MUL | BPF_X, 0, 1, 0, 0),
+        insn(BPF_ALU64 | BPF_SUB | BPF_K, 0, 0, 0, 1),
+        insn(BPF_ALU64 | BPF_LSH | BPF_K, 0, 0, 0, 4),
+        insn(BPF_ALU64 | BPF_OR | BPF_K, 0, 0, 0, 5),
+        insn(BPF_ALU64 | BPF_XOR | BPF_K, 0, 0, 0, 0xff),
+        insn(BPF_ALU64 | BPF_DIV | BPF_K, 0, 0, 0, 3),
+        insn(BPF_ALU64 | BPF_MOD | BPF_K, 0, 0, 0, 10),
+        exit(),
+    ], Some(((((7 * 3 - 1) << 4) | 5) ^ 0xff) / 3 % 10));
+    // 32-bit results are zero extended
+    check_accept("alu32", &[
+        mov(0, -1),
+        insn(BPF_ALU | BPF_ADD | BPF_K, 0, 0, 0, 1),
+        mov(1, 0x7fff_ffff),
+        insn(BPF_ALU | BPF_ADD | BPF_K, 1, 0, 0, 1),
+        insn(BPF_ALU64 | BPF_ADD | BPF_X, 0, 1, 0, 0),
+        mov(2, -16),
+        insn(BPF_ALU64 | BPF_ARSH | BPF_K, 2, 0, 0, 2),
+        insn(BPF_ALU64 | BPF_ADD | BPF_X, 0, 2, 0, 0),
+        exit(),
+    ], Some(0x7fff_fffc));
+    check_accept("branches", &[
+        mov(0, 0),
+        mov(1, 5),
+        insn(BPF_JMP | BPF_JGT | BPF_K, 1, 0, 1, 3),
+        insn(BPF_ALU64 | BPF_ADD | BPF_K, 0, 0, 0, 100),
+        insn(BPF_ALU64 | BPF_ADD | BPF_K, 0, 0, 0, 1),
+        mov(2, -2),
+        insn(BPF_JMP | BPF_JSLT | BPF_X, 2, 1, 1, 0),
+        insn(BPF_ALU64 | BPF_ADD | BPF_K, 0, 0, 0, 100),
+        insn(BPF_JMP | BPF_JSET | BPF_K, 1, 0, 1, 2),
+        insn(BPF_ALU64 | BPF_ADD | BPF_K, 0, 0, 0, 10),
+        insn(BPF_JMP | BPF_JGT | BPF_X, 2, 1, 1, 0),
+        insn(BPF_ALU64 | BPF_ADD | BPF_K, 0, 0, 0, 100),
+        exit(),
+    ], Some(11));
+    check_accept("stack and imm64", &[
+        insn(BPF_LD_IMM64, 1, 0, 0, 0x5566_7788),
+        insn(0, 0, 0, 0, 0x1122_3344),
+        insn(BPF_STX | BPF_MEM | BPF_DW, BPF_REG_FP, 1, -8, 0),
+        insn(BPF_LDX | BPF_MEM | BPF_W, 0, BPF_REG_FP, -8, 0),
+        insn(BPF_LDX | BPF_MEM | BPF_H, 2, BPF_REG_FP, -2, 0),
+        insn(BPF_ALU64 | BPF_ADD | BPF_X, 0, 2, 0, 0),
+        exit(),
+    ], Some(0x5566_7788 + 0x1122));
+    check_accept("ctx", &[
+        insn(BPF_LDX | BPF_MEM | BPF_DW, 0, 1, 0, 0),
+        insn(BPF_ALU64 | BPF_ADD | BPF_K, 0, 0, 0, 1),
+        exit(),
+    ], Some(42));
+    check_accept("helper", &[
+        insn(BPF_JMP | BPF_CALL, 0, 0, 0, BPF_FUNC_GET_SMP_PROCESSOR_ID as i32),
+        insn(BPF_ALU64 | BPF_ADD | BPF_K, 0, 0, 0, 42),
+        exit(),
+    ], None);
+
+    check_reject("back-edge", &[mov(0, 0), insn(BPF_JMP | BPF_JA, 0, 0, -1, 0), exit()]);
+    check_reject("uninit r0", &[exit()]);
+    check_reject("stack out of bounds", &[insn(BPF_ST | BPF_MEM | BPF_DW, BPF_REG_FP, 0, -520, 0), mov(0, 0), exit()]);
+    check_reject("ctx write", &[insn(BPF_ST | BPF_MEM | BPF_DW, 1, 0, 0, 0), mov(0, 0), exit()]);
+    check_reject("pointer leak", &[insn(BPF_ALU64 | BPF_MOV | BPF_X, 0, BPF_REG_FP, 0, 0), exit()]);
+    check_reject("partial spill read", &[
+        insn(BPF_STX | BPF_MEM | BPF_DW, BPF_REG_FP, BPF_REG_FP, -8, 0),
+        insn(BPF_LDX | BPF_MEM | BPF_W, 0, BPF_REG_FP, -4, 0),
+        exit(),
+    ]);
+    check_reject("scalar map argument", &[
+        mov(1, 1),
+        mov(2, 0),
+        insn(BPF_JMP | BPF_CALL, 0, 0, 0, BPF_FUNC_MAP_LOOKUP_ELEM as i32),
+        mov(0, 0),
+        exit(),
+    ]);
+    // le64 keeps the bits above 32 of an ALU32 register, the offset is 1 << 32 and not 0
+    check_reject("le64 constant offset", &[
+        insn(BPF_LD_IMM64, 1, 0, 0, 0),
+        insn(0, 0, 0, 0, 1),
+        insn(BPF_ALU | BPF_END | BPF_TO_LE, 1, 0, 0, 64),
+        insn(BPF_ALU64 | BPF_MOV | BPF_X, 2, BPF_REG_FP, 0, 0),
+        insn(BPF_ALU64 | BPF_ADD | BPF_X, 2, 1, 0, 0),
+        insn(BPF_ST | BPF_MEM | BPF_DW, 2, 0, -8, 0),
+        mov(0, 0),
+        exit(),
+    ]);
+    // be64 of an unknown value is not bounded by 32 bits, shifting it right by 32 does not make it 0
+    check_reject("be64 unknown offset", &[
+        insn(BPF_LDX | BPF_MEM | BPF_DW, 1, 1, 0, 0),
+        insn(BPF_ALU | BPF_END | BPF_TO_BE, 1, 0, 0, 64),
+        insn(BPF_ALU64 | BPF_RSH | BPF_K, 1, 0, 0, 32),
+        insn(BPF_ALU64 | BPF_MOV | BPF_X, 2, BPF_REG_FP, 0, 0),
+        insn(BPF_ALU64 | BPF_ADD | BPF_X, 2, 1, 0, 0),
+        insn(BPF_ST | BPF_MEM | BPF_DW, 2, 0, -8, 0),
+        mov(0, 0),
+        exit(),
+    ]);
+    println!("eBPF interpreter tests finished");
+}
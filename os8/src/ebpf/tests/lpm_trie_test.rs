@@ -0,0 +1,89 @@
+use super::{InternalMapAttr, BpfMap, LpmTrieMap, BpfErrorCode::*};
+use super::{BPF_MAP_TYPE_LPM_TRIE, BPF_ANY};
+
+/// `struct { u32 prefixlen; u8 data[4]; }` of an IPv4 prefix
+#[repr(C)]
+struct Ipv4Key {
+    prefixlen: u32,
+    data: [u8; 4],
+}
+
+fn key(prefixlen: u32, data: [u8; 4]) -> Ipv4Key {
+    Ipv4Key { prefixlen, data }
+}
+
+fn update(trie: &mut LpmTrieMap, k: &Ipv4Key, value: u32) {
+    let k = k as *const Ipv4Key as *const u8;
+    trie.update(k, &value as *const u32 as *const u8, BPF_ANY).unwrap();
+}
+
+fn lookup(trie: &LpmTrieMap, k: &Ipv4Key) -> Option<u32> {
+    let mut value = 0u32;
+    let k = k as *const Ipv4Key as *const u8;
+    trie.lookup(k, &mut value as *mut u32 as *mut u8).ok().map(|_| value)
+}
+
+fn delete(trie: &mut LpmTrieMap, k: &Ipv4Key) -> bool {
+    trie.delete(k as *const Ipv4Key as *const u8).is_ok()
+}
+
+pub fn run_lpm_trie_test() {
+    println!("running lpm trie tests");
+    let mut trie = LpmTrieMap::new(InternalMapAttr {
+        map_type: BPF_MAP_TYPE_LPM_TRIE,
+        key_size: 8,
+        value_size: 4,
+        max_entries: 8,
+        map_flags: 0,
+    });
+
+    // insert
+    update(&mut trie, &key(8, [10, 0, 0, 0]), 1);
+    update(&mut trie, &key(16, [10, 1, 0, 0]), 2);
+    update(&mut trie, &key(24, [10, 1, 1, 0]), 3);
+    update(&mut trie, &key(16, [192, 168, 0, 0]), 4);
+    update(&mut trie, &key(0, [0; 4]), 5);
+    update(&mut trie, &key(16, [10, 1, 0, 0]), 6);
+
+    // longest match
+    assert_eq!(lookup(&trie, &key(32, [10, 1, 1, 5])), Some(3));
+    assert_eq!(lookup(&trie, &key(32, [10, 1, 2, 3])), Some(6));
+    assert_eq!(lookup(&trie, &key(32, [10, 2, 0, 0])), Some(1));
+    assert_eq!(lookup(&trie, &key(32, [192, 168, 7, 1])), Some(4));
+    assert_eq!(lookup(&trie, &key(32, [11, 0, 0, 0])), Some(5));
+    assert_eq!(lookup(&trie, &key(12, [10, 1, 1, 0])), Some(1));
+    println!("[LPM trie test] longest match OK");
+
+    // delete
+    assert!(delete(&mut trie, &key(16, [10, 1, 0, 0])));
+    assert!(!delete(&mut trie, &key(16, [10, 1, 0, 0])));
+    assert!(!delete(&mut trie, &key(12, [10, 0, 0, 0])));
+    assert_eq!(lookup(&trie, &key(32, [10, 1, 2, 3])), Some(1));
+    assert_eq!(lookup(&trie, &key(32, [10, 1, 1, 5])), Some(3));
+    assert!(delete(&mut trie, &key(0, [0; 4])));
+    assert_eq!(lookup(&trie, &key(32, [11, 0, 0, 0])), None);
+    assert!(delete(&mut trie, &key(24, [10, 1, 1, 0])));
+    assert_eq!(lookup(&trie, &key(32, [10, 1, 1, 5])), Some(1));
+    println!("[LPM trie test] delete OK");
+
+    // iterate over the remaining two entries
+    let mut next = key(0, [0; 4]);
+    let next_ptr = &mut next as *mut Ipv4Key as *mut u8;
+    let mut count = 0;
+    let mut result = trie.next_key(core::ptr::null(), next_ptr);
+    while result.is_ok() {
+        count += 1;
+        let prev = key(next.prefixlen, next.data);
+        result = trie.next_key(&prev as *const Ipv4Key as *const u8, next_ptr);
+    }
+    assert_eq!(count, 2);
+
+    // prefixes longer than the key are rejected instead of reading past the data
+    let long = key(33, [10, 0, 0, 0]);
+    let long_ptr = &long as *const Ipv4Key as *const u8;
+    assert!(matches!(trie.update(long_ptr, &0u32 as *const u32 as *const u8, BPF_ANY), Err(EINVAL)));
+    assert!(matches!(trie.delete(long_ptr), Err(EINVAL)));
+    assert!(matches!(trie.next_key(long_ptr, next_ptr), Err(EINVAL)));
+    println!("[LPM trie test] invalid prefixlen OK");
+    println!("lpm trie tests finished");
+}
@@ -0,0 +1,8 @@
+pub mod lpm_trie_test;
+pub mod interpreter_test;
+pub use super::{insn, interpreter, program, verifier};
+pub(super) use super::helpers;
+pub use super::map::internal::{InternalMapAttr, BpfMap};
+pub use super::map::lpm_trie::LpmTrieMap;
+pub use super::consts::*;
+pub use super::retcode::BpfErrorCode::{self, *};
@@ -2,17 +2,21 @@
 //!
 //! attach a program to hookpoints
 //! 
-//! currently we only support Kprobe
+//! currently we support Kprobe, Kretprobe, Uprobe, the static syscall tracepoints
+//! and perf events sampled on timer interrupts
 use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use lazy_static::lazy_static;
-use crate::probe::{arch::trapframe::TrapFrame, kprobes::unregister_kprobe};
+use crate::probe::arch::trapframe::TrapFrame;
 
 use lock::Mutex;
 
-use crate::{probe::{register_kprobe, register_kretprobe, KProbeArgs, KRetProbeArgs}};
-use super::{BpfObject::*, *, retcode::BpfErrorCode::{*, self}, retcode::*};
+use crate::{probe::{register_kprobe, register_kretprobe, unregister_kprobe, unregister_kretprobe, register_uprobe, unregister_uprobe, KProbeArgs, KRetProbeArgs, osutils::{symbol_to_addr, addr_to_symbol}}};
+use super::{BpfObject::*, *, consts::*, osutil::{os_current_thread, os_walk_stack, os_current_fp, os_user_regs, os_get_ticks, os_current_syscall_filters, os_add_syscall_filter}, retcode::BpfErrorCode::{*, self}, retcode::*};
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
@@ -28,16 +32,21 @@ pub enum TracepointType {
     KProbe,
     KRetProbeEntry,
     KRetProbeExit,
+    SysEnter,
+    SysExit,
+    PerfEvent,
+    UProbe,
 }
 
 use TracepointType::*;
 
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
-/// tracepoint abstraction, currently only for Kprobe
+/// tracepoint abstraction
 pub struct Tracepoint {
     pub tp_type: TracepointType,
-    /// Kprobe attach address
+    /// Kprobe attach address, sample period of perf events, key of `UPROBE_TARGETS` for uprobes,
+    /// 0 for static tracepoints
     pub token: usize,
 }
 
@@ -50,8 +59,12 @@ impl Tracepoint {
 lazy_static! {
     static ref ATTACHED_PROGS: Mutex<BTreeMap<Tracepoint, Vec<Arc<BpfProgram>>>> =
         Mutex::new(BTreeMap::new());
+    /// uprobe token -> (path, offset), a uprobe is not identified by an address
+    static ref UPROBE_TARGETS: Mutex<BTreeMap<usize, (String, usize)>> = Mutex::new(BTreeMap::new());
 }
 
+static NEXT_UPROBE_TOKEN: AtomicUsize = AtomicUsize::new(1);
+
 /// # run attached programs
 /// run all programs that attached to that tracepoint
 /// # arguments
@@ -60,17 +73,51 @@ lazy_static! {
 /// # prodecure
 /// * get the bpf program object by tracepoint.token
 /// * run them one by one, order is preserved
+/// * account the run count and run time of each program
 fn run_attached_programs(tracepoint: &Tracepoint, ctx: *const u8) {
     let map = ATTACHED_PROGS.lock();
-    let programs = map.get(tracepoint).unwrap();
+    let programs = match map.get(tracepoint) {
+        Some(programs) => programs,
+        None => return,
+    };
     for program in programs {
+        let start = os_get_ticks();
         let _result = program.run(ctx);
+        program.record_run(os_get_ticks() - start);
         // error!("run resultadr: {}", result);
     }
 }
 
+/// name of a tracepoint in the syntax of `bpf_program_attach`,
+/// kprobes outside the symbol table are shown by address
+fn tracepoint_name(tracepoint: &Tracepoint) -> String {
+    let symbol = || addr_to_symbol(tracepoint.token).map_or_else(|| format!("{:#x}", tracepoint.token), String::from);
+    match tracepoint.tp_type {
+        KProbe => format!("kprobe${}", symbol()),
+        KRetProbeEntry => format!("kretprobe@entry${}", symbol()),
+        KRetProbeExit => format!("kretprobe@exit${}", symbol()),
+        SysEnter => String::from("tracepoint$sys_enter"),
+        SysExit => String::from("tracepoint$sys_exit"),
+        PerfEvent => format!("perf_event${}", tracepoint.token),
+        UProbe => match UPROBE_TARGETS.lock().get(&tracepoint.token) {
+            Some((path, offset)) => format!("uprobe${}:{:#x}", path, offset),
+            None => format!("uprobe${}", tracepoint.token),
+        },
+    }
+}
+
+/// the tracepoints `program` is attached to, named like the targets of `bpf_program_attach`
+pub fn bpf_program_attach_points(program: &Arc<BpfProgram>) -> Vec<String> {
+    let map = ATTACHED_PROGS.lock();
+    map.iter()
+        .filter(|(_, programs)| programs.iter().any(|other| Arc::ptr_eq(other, program)))
+        .map(|(tracepoint, _)| tracepoint_name(tracepoint))
+        .collect()
+}
+
 #[repr(C)]
 /// kProbe context are just registers, or Trapframe
+/// `ptype` is 0 for kprobes, 1 and 2 for kretprobes, 6 for uprobes
 struct KProbeBPFContext {
     ptype: usize,
     paddr: usize,
@@ -91,6 +138,210 @@ impl KProbeBPFContext {
     }
 }
 
+#[repr(C)]
+/// syscall tracepoint context, `ptype` is 3 on entry and 4 on exit,
+/// 7 for syscall filters
+struct SyscallBPFContext {
+    ptype: usize,
+    id: usize,
+    args: [usize; 4],
+    /// 0 on entry
+    ret: isize,
+    pid: usize,
+    tid: usize,
+}
+
+impl SyscallBPFContext {
+    pub fn new(t: usize, id: usize, args: &[usize; 4], ret: isize) -> Self {
+        let thread = os_current_thread();
+        SyscallBPFContext {
+            ptype: t,
+            id,
+            args: *args,
+            ret,
+            pid: thread.get_pid() as usize,
+            tid: thread.get_tid() as usize,
+        }
+    }
+
+    pub fn as_ptr(&self) -> *const u8 {
+        self as *const Self as *const u8
+    }
+}
+
+/// # bpf_ctx_get_stack
+/// collect the stack of the probed code into `ips`, the innermost frame first
+/// * kprobe contexts start from the registers saved by the probe
+/// * other contexts start from the caller of the helper
+/// * user stacks start from the registers saved on the last trap into the kernel
+/// # return value
+/// * number of ips written
+pub fn bpf_ctx_get_stack(ctx: *const u8, user: bool, ips: &mut [u64]) -> usize {
+    if ips.len() < 2 {
+        return 0;
+    }
+    if user {
+        return match os_user_regs() {
+            Some((pc, fp)) => {
+                ips[0] = pc as u64;
+                1 + os_walk_stack(fp, true, &mut ips[1..])
+            }
+            None => 0,
+        };
+    }
+    // every context starts with its type, kprobe contexts are type 0 to 2
+    let ptype = unsafe { *(ctx as *const usize) };
+    match ptype {
+        0 | 1 | 2 => {
+            let tf = unsafe { &(*(ctx as *const KProbeBPFContext)).tf };
+            // probes hit function entries, ra is still in the register
+            ips[0] = tf.sepc as u64;
+            ips[1] = tf.x[1] as u64;
+            2 + os_walk_stack(tf.x[8], false, &mut ips[2..])
+        }
+        _ => os_walk_stack(os_current_fp(), false, ips),
+    }
+}
+
+#[repr(C)]
+/// perf event context, `ptype` is 5
+struct PerfEventBPFContext {
+    ptype: usize,
+    /// the interrupted user pc
+    sepc: usize,
+    pid: usize,
+}
+
+/// number of timer interrupts seen by `bpf_perf_event_tick`
+static PERF_TICKS: AtomicUsize = AtomicUsize::new(0);
+
+/// # bpf_perf_event_tick
+/// called by `trap_handler` on every timer interrupt,
+/// runs the perf event programs whose sample period divides the tick count
+pub fn bpf_perf_event_tick(sepc: usize) {
+    let ticks = PERF_TICKS.fetch_add(1, Ordering::Relaxed) + 1;
+    let periods: Vec<usize> = {
+        let map = ATTACHED_PROGS.lock();
+        let first = Tracepoint::new(PerfEvent, 0);
+        map.range(first..)
+            .filter(|(tp, programs)| tp.tp_type == PerfEvent && !programs.is_empty() && ticks % tp.token == 0)
+            .map(|(tp, _)| tp.token)
+            .collect()
+    };
+    if periods.is_empty() {
+        return;
+    }
+    let ctx = PerfEventBPFContext {
+        ptype: 5,
+        sepc,
+        pid: os_current_thread().get_pid() as usize,
+    };
+    for period in periods {
+        run_attached_programs(&Tracepoint::new(PerfEvent, period), &ctx as *const _ as *const u8);
+    }
+}
+
+/// size of the context passed to programs of `prog_type`, checked by the verifier
+pub fn bpf_prog_ctx_size(prog_type: u32) -> Option<usize> {
+    match prog_type {
+        BPF_PROG_TYPE_KPROBE => Some(core::mem::size_of::<KProbeBPFContext>()),
+        BPF_PROG_TYPE_TRACEPOINT | BPF_PROG_TYPE_SYSCALL_FILTER => Some(core::mem::size_of::<SyscallBPFContext>()),
+        BPF_PROG_TYPE_PERF_EVENT => Some(core::mem::size_of::<PerfEventBPFContext>()),
+        _ => None,
+    }
+}
+
+/// program type that can be attached to `tp_type`
+fn tracepoint_prog_type(tp_type: TracepointType) -> u32 {
+    match tp_type {
+        KProbe | KRetProbeEntry | KRetProbeExit | UProbe => BPF_PROG_TYPE_KPROBE,
+        SysEnter | SysExit => BPF_PROG_TYPE_TRACEPOINT,
+        PerfEvent => BPF_PROG_TYPE_PERF_EVENT,
+    }
+}
+
+/// called by `syscall::syscall` before dispatching
+pub fn bpf_syscall_enter(id: usize, args: &[usize; 4]) {
+    let tracepoint = Tracepoint::new(SysEnter, 0);
+    if ATTACHED_PROGS.lock().get(&tracepoint).map_or(true, |programs| programs.is_empty()) {
+        return;
+    }
+    let ctx = SyscallBPFContext::new(3, id, args, 0);
+    run_attached_programs(&tracepoint, ctx.as_ptr());
+}
+
+/// verdict of the syscall filters of a process
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyscallFilterAction {
+    Allow,
+    /// fail the syscall with this errno
+    Errno(u16),
+    Kill,
+}
+
+/// # bpf_syscall_filter
+/// called by `syscall::syscall` before dispatching and the `sys_enter` tracepoint
+/// # prodecure
+/// * run every filter of the current process, in the order they were installed
+/// * the most restrictive action wins, kill over errno over allow,
+///  the first errno is kept among errno actions
+/// * unknown actions kill the process, the linux way
+pub fn bpf_syscall_filter(id: usize, args: &[usize; 4]) -> SyscallFilterAction {
+    let filters = os_current_syscall_filters();
+    if filters.is_empty() {
+        return SyscallFilterAction::Allow;
+    }
+    let ctx = SyscallBPFContext::new(7, id, args, 0);
+    let mut verdict = SECCOMP_RET_ALLOW;
+    for program in filters {
+        let start = os_get_ticks();
+        let ret = program.run(ctx.as_ptr()) as u32;
+        program.record_run(os_get_ticks() - start);
+        let ret = match ret & SECCOMP_RET_ACTION_FULL {
+            SECCOMP_RET_ALLOW | SECCOMP_RET_ERRNO => ret,
+            _ => SECCOMP_RET_KILL,
+        };
+        if ret & SECCOMP_RET_ACTION_FULL < verdict & SECCOMP_RET_ACTION_FULL {
+            verdict = ret;
+        }
+    }
+    match verdict & SECCOMP_RET_ACTION_FULL {
+        SECCOMP_RET_ALLOW => SyscallFilterAction::Allow,
+        SECCOMP_RET_ERRNO => SyscallFilterAction::Errno((verdict & SECCOMP_RET_DATA) as u16),
+        _ => SyscallFilterAction::Kill,
+    }
+}
+
+/// # bpf_syscall_filter_install
+/// install a syscall filter on the current process, like seccomp filters
+/// # arguments
+/// * prog_fd - the fd of a `BPF_PROG_TYPE_SYSCALL_FILTER` program
+/// # prodecure
+/// * filters are inherited by `fork`, kept across `exec` and cannot be removed
+/// # return value
+/// * OK(0) on success
+/// * EINVAL if the program is of another type
+pub fn bpf_syscall_filter_install(prog_fd: u32) -> BpfResult {
+    let (_, program) = bpf_program_get_fd(prog_fd).ok_or(EBADF)?;
+    if program.prog_type != BPF_PROG_TYPE_SYSCALL_FILTER {
+        return Err(EINVAL);
+    }
+    if !os_add_syscall_filter(program) {
+        return Err(EAGAIN);
+    }
+    Ok(0)
+}
+
+/// called by `syscall::syscall` with the return value
+pub fn bpf_syscall_exit(id: usize, args: &[usize; 4], ret: isize) {
+    let tracepoint = Tracepoint::new(SysExit, 0);
+    if ATTACHED_PROGS.lock().get(&tracepoint).map_or(true, |programs| programs.is_empty()) {
+        return;
+    }
+    let ctx = SyscallBPFContext::new(4, id, args, ret);
+    run_attached_programs(&tracepoint, ctx.as_ptr());
+}
+
 /// the handler function that passed to register kprobe
 fn kprobe_handler(tf: &mut TrapFrame, probed_addr: usize) -> isize {
     let tracepoint = Tracepoint::new(KProbe, probed_addr);
@@ -118,13 +369,50 @@ fn kretprobe_exit_handler(tf: &mut TrapFrame, probed_addr: usize) -> isize {
     0
 }
 
-/// since rCore does not support symbol table
-/// we hardcode attached function to syscall::fs::sys_open
-fn resolve_symbol(symbol: &str) -> Option<usize> {
-    //addr = 0x0000000080207b4a
-    //panic!("resolve symbol need hardcoded symbol")
-    //symbol_to_addr(symbol)
-    Some(crate::syscall::fs::sys_open as usize)
+/// the handler passed to register uprobe, `token` is the key of `UPROBE_TARGETS`
+fn uprobe_handler(tf: &mut TrapFrame, token: usize) -> isize {
+    let tracepoint = Tracepoint::new(UProbe, token);
+    let ctx = KProbeBPFContext::new(tf, tf.sepc, 6);
+    run_attached_programs(&tracepoint, ctx.as_ptr());
+    0
+}
+
+/// split a uprobe target `path:offset`, the offset is hex with `0x` or decimal
+fn parse_uprobe_target(target: &str) -> Result<(&str, usize), BpfErrorCode> {
+    let pos = target.rfind(':').ok_or(EINVAL)?;
+    let path = target[..pos].trim_start_matches('/');
+    let offset_str = &target[(pos + 1)..];
+    let offset = match offset_str.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => offset_str.parse::<usize>(),
+    };
+    match offset {
+        Ok(offset) if !path.is_empty() => Ok((path, offset)),
+        _ => Err(EINVAL),
+    }
+}
+
+/// find the token of a uprobe target, a new one is allocated if `create` is set
+fn uprobe_token(target: &str, create: bool) -> Result<usize, BpfErrorCode> {
+    let (path, offset) = parse_uprobe_target(target)?;
+    let mut targets = UPROBE_TARGETS.lock();
+    if let Some((token, _)) = targets.iter().find(|(_, (p, o))| p == path && *o == offset) {
+        return Ok(*token);
+    }
+    if !create {
+        return Err(ENOENT);
+    }
+    let token = NEXT_UPROBE_TOKEN.fetch_add(1, Ordering::Relaxed);
+    targets.insert(token, (String::from(path), offset));
+    Ok(token)
+}
+
+/// look up the kernel symbol table, see `ksyms.rs`
+fn resolve_symbol(symbol: &str) -> Result<usize, BpfErrorCode> {
+    symbol_to_addr(symbol).ok_or_else(|| {
+        warn!("cannot resolve kernel symbol {}", symbol);
+        ENOENT
+    })
 }
 
 /// parse tracepoint types
@@ -141,6 +429,18 @@ fn parse_tracepoint<'a>(target: &'a str) -> Result<(TracepointType, &'a str), Bp
         tp_type = KRetProbeEntry;
     } else if type_str.eq_ignore_ascii_case("kretprobe@exit") {
         tp_type = KRetProbeExit;
+    } else if type_str.eq_ignore_ascii_case("tracepoint") {
+        tp_type = match fn_name {
+            "sys_enter" => SysEnter,
+            "sys_exit" => SysExit,
+            _ => return Err(ENOENT),
+        };
+    } else if type_str.eq_ignore_ascii_case("perf_event") {
+        // `perf_event$N` samples every N-th timer interrupt
+        tp_type = PerfEvent;
+    } else if type_str.eq_ignore_ascii_case("uprobe") {
+        // `uprobe$path:offset` probes the instruction at a file offset of an executable
+        tp_type = UProbe;
     } else {
         return Err(EINVAL);
     }
@@ -160,20 +460,21 @@ fn parse_tracepoint<'a>(target: &'a str) -> Result<(TracepointType, &'a str), Bp
 /// # return value
 /// * OK(0) on success
 pub fn bpf_program_attach(target: &str, prog_fd: u32) -> BpfResult {
-    // check program fd
-    let program = {
-        let objs = BPF_OBJECTS.lock();
-        
-        match objs.get(&prog_fd) {
-            Some(bpf_obj) => {
-                let shared_program = bpf_obj.is_program().unwrap();
-                Ok(shared_program.clone())
-            },
-            _ => Err(ENOENT),
-        }
-    }?;
+    // check program fd, the tracepoint keeps the program alive after the fd is closed
+    let (_, program) = bpf_program_get_fd(prog_fd).ok_or(ENOENT)?;
     let (tp_type, fn_name) = parse_tracepoint(target)?;
-    let addr = resolve_symbol(fn_name).ok_or(ENOENT)?;
+    if program.prog_type != tracepoint_prog_type(tp_type) {
+        return Err(EINVAL);
+    }
+    let addr = match tp_type {
+        SysEnter | SysExit => 0,
+        PerfEvent => match fn_name.parse::<usize>() {
+            Ok(period) if period > 0 => period,
+            _ => return Err(EINVAL),
+        },
+        UProbe => uprobe_token(fn_name, true)?,
+        _ => resolve_symbol(fn_name)?,
+    };
 
     let tracepoint = Tracepoint::new(tp_type, addr);
 
@@ -214,45 +515,150 @@ pub fn bpf_program_attach(target: &str, prog_fd: u32) -> BpfResult {
                 map.insert(tracepoint, vec![program]);
                 map.insert(dual_tp, vec![]);
             }
+            UProbe => {
+                let args = KProbeArgs {
+                    pre_handler: Arc::new(uprobe_handler),
+                    post_handler: None,
+                    user_data: addr,
+                };
+                let (path, offset) = UPROBE_TARGETS.lock().get(&addr).cloned().ok_or(ENOENT)?;
+                if register_uprobe(&path, offset, args).is_none() {
+                    UPROBE_TARGETS.lock().remove(&addr);
+                    return Err(EINVAL);
+                }
+                map.insert(tracepoint, vec![program]);
+            }
+            SysEnter | SysExit | PerfEvent => {
+                map.insert(tracepoint, vec![program]);
+            }
         }
     }
     trace!("bpf prog attached! tracepoint symbol:{} addr: {:x}", fn_name, addr);
     Ok(0)
 }
 
+/// # release tracepoint
+/// drop the tracepoint from `ATTACHED_PROGS` once no program is attached to it
+/// # prodecure
+/// * kprobes are unregistered when their vector is empty
+/// * kretprobes are unregistered when both the entry and exit vectors are empty,
+///  and both tracepoints are removed
+/// * if the probe is still active the empty vectors are kept, so that a later attach
+///  reuses the registered probe, the unregistration is retried once the probe is idle,
+///  see `bpf_release_idle_tracepoints`
+fn release_tracepoint(map: &mut BTreeMap<Tracepoint, Vec<Arc<BpfProgram>>>, tracepoint: Tracepoint) {
+    let is_empty = |map: &BTreeMap<Tracepoint, Vec<Arc<BpfProgram>>>, tp: &Tracepoint| {
+        map.get(tp).map_or(true, |programs| programs.is_empty())
+    };
+    if !is_empty(map, &tracepoint) {
+        return;
+    }
+    let addr = tracepoint.token;
+    match tracepoint.tp_type {
+        KProbe => {
+            if unregister_kprobe(addr).is_none() {
+                warn!("kprobe at {:#x} is still active, keep it registered", addr);
+                return;
+            }
+            map.remove(&tracepoint);
+        }
+        KRetProbeEntry | KRetProbeExit => {
+            let entry_tp = Tracepoint::new(KRetProbeEntry, addr);
+            let exit_tp = Tracepoint::new(KRetProbeExit, addr);
+            if !is_empty(map, &entry_tp) || !is_empty(map, &exit_tp) {
+                return;
+            }
+            if unregister_kretprobe(addr).is_none() {
+                warn!("kretprobe at {:#x} is still active, keep it registered", addr);
+                return;
+            }
+            map.remove(&entry_tp);
+            map.remove(&exit_tp);
+        }
+        UProbe => {
+            let target = UPROBE_TARGETS.lock().get(&addr).cloned();
+            if let Some((path, offset)) = target {
+                if unregister_uprobe(&path, offset).is_none() {
+                    warn!("uprobe at {}:{:#x} is still active, keep it registered", path, offset);
+                    return;
+                }
+            }
+            UPROBE_TARGETS.lock().remove(&addr);
+            map.remove(&tracepoint);
+        }
+        SysEnter | SysExit | PerfEvent => {
+            map.remove(&tracepoint);
+        }
+    }
+}
+
 /// # bpf_program_detach
-/// detach a program from hookpoint
+/// detach a program from hookpoints
 /// # arguments
+/// * target - the hookpoint to detach from, `None` detaches from every hookpoint
 /// * prog_fd - the fd of the bpf program
 /// # prodecure
 /// * get the bpf program object by prog_fd
-/// * remove program from tracepoint handlers
+/// * remove program from the handlers of each matching tracepoint
+/// * release the tracepoints that have no program left, see `release_tracepoint`
+/// * the fd stays valid, the program can be attached again
 /// # return value
 /// * OK(0) on success
-pub fn bpf_program_detach(prog_fd: u32) -> BpfResult {
-    if let Some(prog) = bpf_object_remove(prog_fd) {
-        let prog = prog.is_program().unwrap();
-        let mut map = ATTACHED_PROGS.lock();
-        let mut t = Tracepoint::new(TracepointType::KProbe, 0);
-        let mut id = 0;
-        for (k, v) in map.iter() {
-            let mut find = false;
-            for (i, p) in v.iter().enumerate() {
-                if Arc::ptr_eq(p, &prog) {
-                    t = k.clone();
-                    id = i;
-                    find = true;
-                }
-            }
-            if find {
-                break;
-            }
+/// * ENOENT if the program is not attached to target
+pub fn bpf_program_detach(target: Option<&str>, prog_fd: u32) -> BpfResult {
+    let (_, program) = bpf_program_get_fd(prog_fd).ok_or(EBADF)?;
+    let only = match target {
+        Some(target) => {
+            let (tp_type, fn_name) = parse_tracepoint(target)?;
+            let addr = match tp_type {
+                SysEnter | SysExit => 0,
+                PerfEvent => fn_name.parse::<usize>().map_err(|_| EINVAL)?,
+                UProbe => uprobe_token(fn_name, false)?,
+                _ => resolve_symbol(fn_name)?,
+            };
+            Some(Tracepoint::new(tp_type, addr))
         }
-        let v = map.get_mut(&t).unwrap();
-        v.remove(id);
-        //unregister_kprobe(t.token);
-        Ok(0)
-    } else {
-        Err(ENOENT)
+        None => None,
+    };
+
+    let mut map = ATTACHED_PROGS.lock();
+    let mut detached = Vec::new();
+    for (tracepoint, programs) in map.iter_mut() {
+        if only.map_or(false, |only| only != *tracepoint) {
+            continue;
+        }
+        let count = programs.len();
+        programs.retain(|other| !Arc::ptr_eq(other, &program));
+        if programs.len() != count {
+            detached.push(*tracepoint);
+        }
+    }
+    if detached.is_empty() {
+        return Err(ENOENT);
+    }
+    for tracepoint in detached {
+        release_tracepoint(&mut map, tracepoint);
+        trace!("bpf prog detached! tracepoint {:?}", tracepoint);
+    }
+    release_idle_tracepoints(&mut map);
+    Ok(0)
+}
+
+/// retry releasing the tracepoints whose probes were still active when their last program was detached
+fn release_idle_tracepoints(map: &mut BTreeMap<Tracepoint, Vec<Arc<BpfProgram>>>) {
+    let idle: Vec<Tracepoint> = map.iter()
+        .filter(|(_, programs)| programs.is_empty())
+        .map(|(tracepoint, _)| *tracepoint)
+        .collect();
+    for tracepoint in idle {
+        release_tracepoint(map, tracepoint);
+    }
+}
+
+/// called by the probes when one that failed to unregister becomes idle,
+/// skipped if `ATTACHED_PROGS` is held by the probed code, the next detach retries then
+pub fn bpf_release_idle_tracepoints() {
+    if let Some(mut map) = ATTACHED_PROGS.try_lock() {
+        release_idle_tracepoints(&mut map);
     }
 }
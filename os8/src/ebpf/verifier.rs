@@ -0,0 +1,1353 @@
+//! eBPF verifier
+//!
+//!
+//! statically checks a program before it is JITed or interpreted
+//!
+//! 1. the control flow graph must be a DAG and every jump stays inside the program
+//! 2. every path is simulated with abstract register and stack states, checking
+//!    register initialization, memory bounds, helper arguments and null checks
+//!
+//! BPF-to-BPF calls split the program into functions, the entry and every call target.
+//! a call edge is part of the control flow graph, so recursion is a back-edge.
+//! each call gets its own frame, and the stacks of all frames in a chain share
+//! `BPF_STACK_SIZE` bytes, like linux
+//!
+//! refer to <https://www.kernel.org/doc/html/latest/bpf/verifier.html>
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use alloc::vec;
+use core::fmt::Write;
+
+use super::{
+    insn::*,
+    interpreter::{alu32, alu64, condition, BPF_STACK_SIZE, MAX_CALL_FRAMES},
+    consts::{BPF_MAP_TYPE_ARRAY, BPF_MAP_TYPE_RINGBUF, BPF_MAP_TYPE_PROG_ARRAY, BPF_MAP_TYPE_STACK_TRACE,
+        BPF_MAP_TYPE_QUEUE, BPF_MAP_TYPE_STACK, BPF_F_RDONLY_PROG, BPF_PSEUDO_MAP_FD, BPF_PSEUDO_MAP_VALUE, BPF_PSEUDO_CALL},
+    helpers::*,
+    map::bpf_map_get_attr,
+    retcode::BpfErrorCode::{self, *},
+};
+
+/// maximum number of instructions in a program
+pub const BPF_MAXINSNS: usize = 4096;
+
+/// maximum number of instructions simulated over all paths
+const BPF_COMPLEXITY_LIMIT_INSNS: usize = 100_000;
+
+/// pointer offsets beyond this are rejected to avoid overflow in bound tracking
+const BPF_MAX_VAR_OFF: u64 = 1 << 29;
+
+/// verifier log, returned to the loader
+/// * level 0 - only the reason of rejection
+/// * level 1 - plus statistics
+/// * level 2 - plus every simulated instruction
+pub struct VerifierLog {
+    pub level: u32,
+    pub buf: String,
+}
+
+impl VerifierLog {
+    pub fn new(level: u32) -> Self {
+        Self { level, buf: String::new() }
+    }
+}
+
+/// abstract value of a register
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RegState {
+    NotInit,
+    /// a number within unsigned bounds
+    Scalar { umin: u64, umax: u64 },
+    /// a pointer with offset bounds
+    Ptr { kind: PtrKind, omin: i64, omax: i64 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PtrKind {
+    Ctx,
+    /// offsets are relative to the frame pointer of call frame `frame`, 0 is the entry
+    Stack { frame: usize },
+    MapValue { size: usize },
+    /// value of a map created with `BPF_F_RDONLY_PROG`, like the one of `.rodata`
+    RdonlyMapValue { size: usize },
+    /// returned by map lookup, copies of the same pointer share `id`
+    MapValueOrNull { size: usize, id: u32 },
+    /// slot of the map fd table, produced by a relocated LD_IMM64
+    MapFdSlot,
+    /// a map of the program, from `BPF_PSEUDO_MAP_FD` or a load from the map fd table,
+    /// the only valid map argument of helpers
+    ConstMap { id: u32 },
+    /// memory returned by an allocating helper, `id` is the reference to release
+    AllocMem { size: usize, id: u32 },
+    AllocMemOrNull { size: usize, id: u32 },
+}
+
+impl PtrKind {
+    fn is_or_null(&self) -> bool {
+        matches!(self, Self::MapValueOrNull { .. } | Self::AllocMemOrNull { .. })
+    }
+}
+
+impl RegState {
+    fn unknown() -> Self {
+        Self::Scalar { umin: 0, umax: u64::MAX }
+    }
+
+    fn known(value: u64) -> Self {
+        Self::Scalar { umin: value, umax: value }
+    }
+
+    /// an unknown value loaded from `size` bytes of memory
+    fn sized(size: usize) -> Self {
+        match size {
+            8 => Self::unknown(),
+            _ => Self::Scalar { umin: 0, umax: (1u64 << (size * 8)) - 1 },
+        }
+    }
+
+    fn ptr(kind: PtrKind, off: i64) -> Self {
+        Self::Ptr { kind, omin: off, omax: off }
+    }
+
+    fn is_init(&self) -> bool {
+        *self != Self::NotInit
+    }
+
+    fn is_ptr(&self) -> bool {
+        matches!(self, Self::Ptr { .. })
+    }
+
+    fn constant(&self) -> Option<u64> {
+        match *self {
+            Self::Scalar { umin, umax } if umin == umax => Some(umin),
+            _ => None,
+        }
+    }
+}
+
+/// 8 bytes of stack, either a spilled register or plain bytes
+#[derive(Clone, Copy, Debug)]
+struct StackSlot {
+    spilled: Option<RegState>,
+    /// bit i set if byte i is initialized
+    init: u8,
+}
+
+const STACK_SLOTS: usize = BPF_STACK_SIZE / 8;
+
+type Stack = [StackSlot; STACK_SLOTS];
+
+/// a function of a program, the entry or the target of a BPF-to-BPF call
+#[derive(Clone, Copy, Debug)]
+pub struct BpfSubprog {
+    /// index of the first instruction
+    pub start: usize,
+    /// bytes of stack used below the frame pointer, a multiple of 8
+    pub stack_depth: usize,
+}
+
+/// a caller suspended by a BPF-to-BPF call
+#[derive(Clone)]
+struct CallerFrame {
+    regs: [RegState; BPF_REG_COUNT],
+    stack: Stack,
+    subprog: usize,
+    /// where the caller continues once the callee exits
+    ret_pc: usize,
+}
+
+#[derive(Clone)]
+struct VerifierState {
+    regs: [RegState; BPF_REG_COUNT],
+    stack: Stack,
+    /// ids of acquired references, they must be released before exit
+    refs: Vec<u32>,
+    /// function of the current frame
+    subprog: usize,
+    /// frames of the callers, the entry first
+    callers: Vec<CallerFrame>,
+}
+
+impl VerifierState {
+    fn new() -> Self {
+        let mut regs = [RegState::NotInit; BPF_REG_COUNT];
+        regs[1] = RegState::ptr(PtrKind::Ctx, 0);
+        regs[BPF_REG_FP as usize] = RegState::ptr(PtrKind::Stack { frame: 0 }, 0);
+        Self {
+            regs,
+            stack: [StackSlot { spilled: None, init: 0 }; STACK_SLOTS],
+            refs: Vec::new(),
+            subprog: 0,
+            callers: Vec::new(),
+        }
+    }
+
+    /// index of the current frame
+    fn frame(&self) -> usize {
+        self.callers.len()
+    }
+
+    fn frame_stack(&self, frame: usize) -> &Stack {
+        self.callers.get(frame).map_or(&self.stack, |caller| &caller.stack)
+    }
+
+    fn frame_stack_mut(&mut self, frame: usize) -> &mut Stack {
+        match self.callers.get_mut(frame) {
+            Some(caller) => &mut caller.stack,
+            None => &mut self.stack,
+        }
+    }
+
+    fn frame_subprog(&self, frame: usize) -> usize {
+        self.callers.get(frame).map_or(self.subprog, |caller| caller.subprog)
+    }
+
+    /// leave the current function, r0 is its return value
+    /// # return value
+    /// * where the caller continues, None if the entry function exits
+    fn pop_frame(&mut self) -> Option<usize> {
+        let caller = self.callers.pop()?;
+        let r0 = self.regs[0];
+        self.regs = caller.regs;
+        self.stack = caller.stack;
+        self.subprog = caller.subprog;
+        self.regs[0] = r0;
+        for reg in 1..=5 {
+            self.regs[reg] = RegState::NotInit;
+        }
+        Some(caller.ret_pc)
+    }
+
+    /// apply `f` to every register and spilled register of every frame
+    fn for_each_reg(&mut self, f: impl Fn(&mut RegState)) {
+        let frames = self.callers.iter_mut()
+            .map(|caller| (&mut caller.regs, &mut caller.stack))
+            .chain(core::iter::once((&mut self.regs, &mut self.stack)));
+        for (regs, stack) in frames {
+            regs.iter_mut().for_each(&f);
+            stack.iter_mut().filter_map(|slot| slot.spilled.as_mut()).for_each(&f);
+        }
+    }
+
+    /// resolve a map lookup or allocation result after a null check,
+    /// a NULL allocation holds no reference
+    fn mark_ptr_or_null(&mut self, id: u32, is_null: bool) {
+        let resolve = |reg: &mut RegState| {
+            if let RegState::Ptr { kind, omin, omax } = *reg {
+                let kind = match kind {
+                    PtrKind::MapValueOrNull { size, id: reg_id } if reg_id == id => PtrKind::MapValue { size },
+                    PtrKind::AllocMemOrNull { size, id: reg_id } if reg_id == id => PtrKind::AllocMem { size, id },
+                    _ => return,
+                };
+                *reg = match is_null {
+                    true => RegState::known(0),
+                    false => RegState::Ptr { kind, omin, omax },
+                };
+            }
+        };
+        self.for_each_reg(resolve);
+        if is_null {
+            self.refs.retain(|&ref_id| ref_id != id);
+        }
+    }
+
+    /// # release_reference
+    /// drop reference `id`, every copy of the released pointer becomes unusable
+    /// # return value
+    /// * false if the reference is not held
+    fn release_reference(&mut self, id: u32) -> bool {
+        let pos = match self.refs.iter().position(|&ref_id| ref_id == id) {
+            Some(pos) => pos,
+            None => return false,
+        };
+        self.refs.remove(pos);
+        let invalidate = |reg: &mut RegState| {
+            if let RegState::Ptr { kind: PtrKind::AllocMem { id: reg_id, .. }, .. } = *reg {
+                if reg_id == id {
+                    *reg = RegState::unknown();
+                }
+            }
+        };
+        self.for_each_reg(invalidate);
+        true
+    }
+}
+
+/// byte index into the stack array for a frame pointer relative offset
+fn stack_byte(off: i64) -> usize {
+    (off + BPF_STACK_SIZE as i64) as usize
+}
+
+struct Verifier<'a> {
+    insns: Vec<BpfInsn>,
+    map_fd_table: &'a [u32],
+    /// ids of the maps the program holds, it can only refer to them
+    map_ids: &'a [u32],
+    ctx_size: usize,
+    log: &'a mut VerifierLog,
+    next_id: u32,
+    processed: usize,
+    /// functions of the program sorted by start, found by check_cfg
+    subprogs: Vec<BpfSubprog>,
+}
+
+/// # bpf_verify
+/// check a program before it is loaded
+/// # arguments
+/// * insns - the eBPF instructions, already relocated
+/// * map_fd_table - the table relocated LD_IMM64 point into
+/// * map_ids - ids of the maps kept alive by the program
+/// * ctx_size - size of the context passed in r1
+/// * log - receives the reason of rejection
+/// # return value
+/// * the functions of the program with their stack depth
+/// * EINVAL on malformed programs, E2BIG if the program is too large or complex,
+///   EACCES if the program may access memory or registers unsafely
+pub fn bpf_verify(insns: &[u64], map_fd_table: &[u32], map_ids: &[u32], ctx_size: usize, log: &mut VerifierLog) -> Result<Vec<BpfSubprog>, BpfErrorCode> {
+    let mut env = Verifier {
+        insns: insns.iter().map(|&raw| BpfInsn::decode(raw)).collect(),
+        map_fd_table,
+        map_ids,
+        ctx_size,
+        log,
+        next_id: 1,
+        processed: 0,
+        subprogs: Vec::new(),
+    };
+    env.check_cfg()?;
+    env.do_check()?;
+    env.check_max_stack_depth()?;
+    if env.log.level > 0 {
+        let _ = writeln!(env.log.buf, "processed {} insns", env.processed);
+    }
+    Ok(env.subprogs)
+}
+
+/// is `insn` a BPF-to-BPF call
+fn is_pseudo_call(insn: &BpfInsn) -> bool {
+    insn.code == BPF_JMP | BPF_CALL && insn.src as u32 == BPF_PSEUDO_CALL
+}
+
+impl<'a> Verifier<'a> {
+    /// record the reason of rejection
+    fn error(&mut self, pc: usize, code: BpfErrorCode, msg: core::fmt::Arguments) -> BpfErrorCode {
+        let _ = writeln!(self.log.buf, "{}: {}", pc, msg);
+        warn!("bpf verifier rejected insn {}: {}", pc, msg);
+        code
+    }
+
+    fn is_ld_imm64(&self, pc: usize) -> bool {
+        self.insns[pc].code == BPF_LD_IMM64
+    }
+
+    /// target of a jump or call at `pc`, `off` is relative to the next instruction
+    fn jump_target(&mut self, pc: usize, off: i64, second_half: &[bool]) -> Result<usize, BpfErrorCode> {
+        let target = pc as i64 + 1 + off;
+        if target < 0 || target as usize >= self.insns.len() || second_half[target as usize] {
+            return Err(self.error(pc, EINVAL, format_args!("jump out of range to {}", target)));
+        }
+        Ok(target as usize)
+    }
+
+    /// target of a jump at `pc`, which must stay in the same function
+    fn local_jump_target(&mut self, pc: usize, off: i64, second_half: &[bool]) -> Result<usize, BpfErrorCode> {
+        let target = self.jump_target(pc, off, second_half)?;
+        if self.subprog_of(target) != self.subprog_of(pc) {
+            return Err(self.error(pc, EINVAL, format_args!("jump out of the function to {}", target)));
+        }
+        Ok(target)
+    }
+
+    /// index of the function containing `pc`
+    fn subprog_of(&self, pc: usize) -> usize {
+        self.subprogs.partition_point(|subprog| subprog.start <= pc) - 1
+    }
+
+    /// successors of the instruction at `pc` in the control flow graph
+    fn successors(&mut self, pc: usize, second_half: &[bool]) -> Result<Vec<usize>, BpfErrorCode> {
+        let insn = self.insns[pc];
+        let next = match self.is_ld_imm64(pc) {
+            true => pc + 2,
+            false => pc + 1,
+        };
+        let fall_through = |env: &mut Self| match next < env.insns.len() {
+            true if env.subprog_of(next) != env.subprog_of(pc) => {
+                Err(env.error(pc, EINVAL, format_args!("falls through into the function at {}", next)))
+            }
+            true => Ok(next),
+            false => Err(env.error(pc, EINVAL, format_args!("falls off the end of the program"))),
+        };
+        if insn.class() != BPF_JMP && insn.class() != BPF_JMP32 {
+            return Ok(vec![fall_through(self)?]);
+        }
+        match insn.op() {
+            BPF_EXIT => Ok(vec![]),
+            // the callee returns to the next instruction
+            BPF_CALL if is_pseudo_call(&insn) => {
+                Ok(vec![fall_through(self)?, self.jump_target(pc, insn.imm as i64, second_half)?])
+            }
+            BPF_CALL => Ok(vec![fall_through(self)?]),
+            BPF_JA => {
+                let off = match insn.class() {
+                    BPF_JMP => insn.off as i64,
+                    _ => insn.imm as i64,
+                };
+                Ok(vec![self.local_jump_target(pc, off, second_half)?])
+            }
+            _ => Ok(vec![fall_through(self)?, self.local_jump_target(pc, insn.off as i64, second_half)?]),
+        }
+    }
+
+    /// # check_cfg
+    /// depth first search over the control flow graph
+    /// * every jump must land on an instruction inside the same function
+    /// * back-edges are rejected, so every path terminates and no function recurses
+    /// * every instruction must be reachable
+    fn check_cfg(&mut self) -> Result<(), BpfErrorCode> {
+        let len = self.insns.len();
+        if len == 0 {
+            return Err(self.error(0, EINVAL, format_args!("empty program")));
+        }
+        if len > BPF_MAXINSNS {
+            return Err(self.error(0, E2BIG, format_args!("program has {} insns, limit is {}", len, BPF_MAXINSNS)));
+        }
+
+        let mut second_half = vec![false; len];
+        let mut pc = 0;
+        while pc < len {
+            if self.is_ld_imm64(pc) {
+                let next = self.insns.get(pc + 1).copied();
+                match next {
+                    Some(next) if next.code == 0 && next.dst == 0 && next.src == 0 && next.off == 0 => {
+                        second_half[pc + 1] = true;
+                        pc += 1;
+                    }
+                    _ => return Err(self.error(pc, EINVAL, format_args!("invalid LD_IMM64 insn"))),
+                }
+            }
+            pc += 1;
+        }
+
+        // the entry and every call target start a function
+        let mut starts = vec![0];
+        for pc in 0..len {
+            let insn = self.insns[pc];
+            if is_pseudo_call(&insn) && !second_half[pc] {
+                starts.push(self.jump_target(pc, insn.imm as i64, &second_half)?);
+            }
+        }
+        starts.sort_unstable();
+        starts.dedup();
+        self.subprogs = starts.into_iter().map(|start| BpfSubprog { start, stack_depth: 0 }).collect();
+
+        // 0: not visited, 1: on the dfs stack, 2: finished
+        let mut color = vec![0u8; len];
+        let mut stack: Vec<(usize, Vec<usize>)> = Vec::new();
+        let succ = self.successors(0, &second_half)?;
+        color[0] = 1;
+        stack.push((0, succ));
+        while let Some((pc, succ)) = stack.last_mut() {
+            let pc = *pc;
+            match succ.pop() {
+                Some(target) => match color[target] {
+                    0 => {
+                        color[target] = 1;
+                        let succ = self.successors(target, &second_half)?;
+                        stack.push((target, succ));
+                    }
+                    1 => return Err(self.error(pc, EINVAL, format_args!("back-edge from insn {} to {}", pc, target))),
+                    _ => (),
+                },
+                None => {
+                    color[pc] = 2;
+                    stack.pop();
+                }
+            }
+        }
+
+        for pc in 0..len {
+            if color[pc] == 0 && !second_half[pc] {
+                return Err(self.error(pc, EINVAL, format_args!("unreachable insn")));
+            }
+        }
+        Ok(())
+    }
+
+    /// # do_check
+    /// walk every path from the entry, branches whose outcome is unknown
+    /// are explored with both states
+    fn do_check(&mut self) -> Result<(), BpfErrorCode> {
+        let mut pending = vec![(0usize, VerifierState::new())];
+        while let Some((mut pc, mut state)) = pending.pop() {
+            loop {
+                self.processed += 1;
+                if self.processed > BPF_COMPLEXITY_LIMIT_INSNS {
+                    let processed = self.processed;
+                    return Err(self.error(pc, E2BIG, format_args!("program is too complex, processed {} insns", processed)));
+                }
+                let insn = self.insns[pc];
+                if self.log.level > 1 {
+                    let _ = writeln!(
+                        self.log.buf, "{}: code={:#04x} dst=r{} src=r{} off={} imm={}",
+                        pc, insn.code, insn.dst, insn.src, insn.off, insn.imm
+                    );
+                }
+                if insn.dst as usize >= BPF_REG_COUNT || insn.src as usize >= BPF_REG_COUNT {
+                    return Err(self.error(pc, EINVAL, format_args!("invalid register")));
+                }
+
+                match insn.class() {
+                    BPF_ALU | BPF_ALU64 => self.check_alu(pc, &insn, &mut state)?,
+                    BPF_LD => {
+                        self.check_ld_imm64(pc, &insn, &mut state)?;
+                        pc += 1;
+                    }
+                    BPF_LDX => self.check_ldx(pc, &insn, &mut state)?,
+                    BPF_ST | BPF_STX => self.check_store(pc, &insn, &mut state)?,
+                    _ => match insn.op() {
+                        BPF_EXIT => {
+                            self.check_exit(pc, &insn, &state)?;
+                            match state.pop_frame() {
+                                Some(ret_pc) => {
+                                    pc = ret_pc;
+                                    continue;
+                                }
+                                None => break,
+                            }
+                        }
+                        BPF_CALL if is_pseudo_call(&insn) => {
+                            pc = self.check_pseudo_call(pc, &insn, &mut state)?;
+                            continue;
+                        }
+                        BPF_CALL => self.check_call(pc, &insn, &mut state)?,
+                        BPF_JA => {
+                            // targets are checked by check_cfg
+                            let off = match insn.class() {
+                                BPF_JMP => insn.off as i64,
+                                _ => insn.imm as i64,
+                            };
+                            pc = (pc as i64 + 1 + off) as usize;
+                            continue;
+                        }
+                        _ => {
+                            let target = (pc as i64 + 1 + insn.off as i64) as usize;
+                            let (fall, taken) = self.check_cond_jmp(pc, &insn, state)?;
+                            match (fall, taken) {
+                                (Some(fall), Some(taken)) => {
+                                    pending.push((target, taken));
+                                    state = fall;
+                                }
+                                (Some(fall), None) => state = fall,
+                                (None, Some(taken)) => {
+                                    state = taken;
+                                    pc = target;
+                                    continue;
+                                }
+                                (None, None) => unreachable!(),
+                            }
+                        }
+                    },
+                }
+                pc += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_reg(&mut self, pc: usize, state: &VerifierState, reg: u8) -> Result<RegState, BpfErrorCode> {
+        let value = state.regs[reg as usize];
+        if !value.is_init() {
+            return Err(self.error(pc, EACCES, format_args!("R{} !read_ok", reg)));
+        }
+        Ok(value)
+    }
+
+    fn check_reg_writable(&mut self, pc: usize, reg: u8) -> Result<(), BpfErrorCode> {
+        if reg == BPF_REG_FP {
+            return Err(self.error(pc, EACCES, format_args!("frame pointer is read only")));
+        }
+        Ok(())
+    }
+
+    fn check_alu(&mut self, pc: usize, insn: &BpfInsn, state: &mut VerifierState) -> Result<(), BpfErrorCode> {
+        let is64 = insn.class() == BPF_ALU64;
+        let op = insn.op();
+        match op {
+            BPF_ADD | BPF_SUB | BPF_MUL | BPF_DIV | BPF_MOD | BPF_OR | BPF_AND | BPF_XOR
+            | BPF_LSH | BPF_RSH | BPF_ARSH | BPF_NEG | BPF_MOV | BPF_END => (),
+            _ => return Err(self.error(pc, EINVAL, format_args!("invalid BPF_ALU opcode {:#x}", op))),
+        }
+        let uses_src_reg = insn.source() == BPF_X && op != BPF_END && op != BPF_NEG;
+        if !uses_src_reg && insn.src != 0 {
+            return Err(self.error(pc, EINVAL, format_args!("BPF_ALU uses reserved fields")));
+        }
+        self.check_reg_writable(pc, insn.dst)?;
+
+        let src = match uses_src_reg {
+            true => self.read_reg(pc, state, insn.src)?,
+            false if is64 => RegState::known(insn.imm as i64 as u64),
+            false => RegState::known(insn.imm as u32 as u64),
+        };
+
+        if op == BPF_MOV {
+            state.regs[insn.dst as usize] = match src {
+                RegState::Ptr { .. } if is64 && insn.off == 0 => src,
+                RegState::Ptr { .. } => {
+                    return Err(self.error(pc, EACCES, format_args!("R{} partial copy of pointer", insn.src)));
+                }
+                _ => self.scalar_alu(insn, RegState::known(0), src, is64),
+            };
+            return Ok(());
+        }
+
+        let dst = self.read_reg(pc, state, insn.dst)?;
+        state.regs[insn.dst as usize] = match (dst, src) {
+            (RegState::Scalar { .. }, RegState::Scalar { .. }) => self.scalar_alu(insn, dst, src, is64),
+            (RegState::Ptr { kind, omin, omax }, RegState::Scalar { umin, umax })
+            | (RegState::Scalar { umin, umax }, RegState::Ptr { kind, omin, omax })
+                if is64 && (op == BPF_ADD || (op == BPF_SUB && dst.is_ptr())) =>
+            {
+                if kind.is_or_null() {
+                    return Err(self.error(pc, EACCES, format_args!("pointer arithmetic on {:?} prohibited, null-check it first", kind)));
+                }
+                if let PtrKind::ConstMap { .. } = kind {
+                    return Err(self.error(pc, EACCES, format_args!("pointer arithmetic on map_ptr prohibited")));
+                }
+                if umax > BPF_MAX_VAR_OFF && umin != umax {
+                    return Err(self.error(pc, EACCES, format_args!("pointer offset is unbounded")));
+                }
+                // a known constant may be a negative offset
+                let (lo, hi) = match umin == umax {
+                    true => (umin as i64, umin as i64),
+                    false => (umin as i64, umax as i64),
+                };
+                if lo.unsigned_abs() > BPF_MAX_VAR_OFF {
+                    return Err(self.error(pc, EACCES, format_args!("pointer offset {} is too large", lo)));
+                }
+                let (omin, omax) = match op {
+                    BPF_ADD => (omin + lo, omax + hi),
+                    _ => (omin - hi, omax - lo),
+                };
+                RegState::Ptr { kind, omin, omax }
+            }
+            (RegState::Ptr { kind: k1, .. }, RegState::Ptr { kind: k2, .. })
+                if is64 && op == BPF_SUB && k1 == k2 && matches!(k1, PtrKind::Stack { .. }) =>
+            {
+                RegState::unknown()
+            }
+            _ => {
+                return Err(self.error(pc, EACCES, format_args!("pointer arithmetic with op {:#x} prohibited", op)));
+            }
+        };
+        Ok(())
+    }
+
+    /// bounds of a scalar ALU result, exact if both operands are known
+    fn scalar_alu(&self, insn: &BpfInsn, dst: RegState, src: RegState, is64: bool) -> RegState {
+        // byte order conversion works on the full 64-bit register, only 16 and 32-bit swaps truncate it
+        if insn.op() == BPF_END {
+            if let Some(a) = dst.constant() {
+                let result = match is64 {
+                    true => alu64(insn, a, 0),
+                    false => alu32(insn, a, 0),
+                };
+                return result.map_or(RegState::unknown(), RegState::known);
+            }
+            return match insn.imm {
+                16 => RegState::Scalar { umin: 0, umax: u16::MAX as u64 },
+                32 => RegState::Scalar { umin: 0, umax: u32::MAX as u64 },
+                64 if !is64 && insn.source() == BPF_TO_LE => dst,
+                _ => RegState::unknown(),
+            };
+        }
+        let (dst, src) = match is64 {
+            true => (dst, src),
+            false => (truncate32(dst), truncate32(src)),
+        };
+        if let (Some(a), Some(b)) = (dst.constant(), src.constant()) {
+            let result = match is64 {
+                true => alu64(insn, a, b),
+                false => alu32(insn, a, b as u32),
+            };
+            return result.map_or(RegState::unknown(), RegState::known);
+        }
+        let (amin, amax, bmin, bmax) = match (dst, src) {
+            (RegState::Scalar { umin: a0, umax: a1 }, RegState::Scalar { umin: b0, umax: b1 }) => (a0, a1, b0, b1),
+            _ => return RegState::unknown(),
+        };
+        let signed = insn.off == 1;
+        let bits = if is64 { 63 } else { 31 };
+        let (umin, umax) = match insn.op() {
+            BPF_ADD => match amax.checked_add(bmax) {
+                Some(max) => (amin + bmin, max),
+                None => (0, u64::MAX),
+            },
+            BPF_SUB if amin >= bmax => (amin - bmax, amax - bmin),
+            BPF_MUL if amax <= u32::MAX as u64 && bmax <= u32::MAX as u64 => (amin * bmin, amax * bmax),
+            BPF_DIV if !signed => match src.constant() {
+                Some(b) if b != 0 => (amin / b, amax / b),
+                _ => (0, amax),
+            },
+            BPF_MOD if !signed => match bmin {
+                0 => (0, amax),
+                _ => (0, amax.min(bmax - 1)),
+            },
+            BPF_AND => (0, amax.min(bmax)),
+            BPF_MOV if insn.off == 0 => (bmin, bmax),
+            BPF_LSH => match src.constant() {
+                Some(b) if (b & bits) < amax.leading_zeros() as u64 => (amin << (b & bits), amax << (b & bits)),
+                _ => (0, u64::MAX),
+            },
+            BPF_RSH => match src.constant() {
+                Some(b) => (amin >> (b & bits), amax >> (b & bits)),
+                None => (0, amax),
+            },
+            _ => (0, u64::MAX),
+        };
+        match is64 {
+            true => RegState::Scalar { umin, umax },
+            false => truncate32(RegState::Scalar { umin, umax }),
+        }
+    }
+
+    fn check_ld_imm64(&mut self, pc: usize, insn: &BpfInsn, state: &mut VerifierState) -> Result<(), BpfErrorCode> {
+        if insn.code != BPF_LD_IMM64 {
+            return Err(self.error(pc, EINVAL, format_args!("legacy packet access is not supported")));
+        }
+        let src = insn.src as u32;
+        if (src != 0 && src != BPF_PSEUDO_MAP_FD && src != BPF_PSEUDO_MAP_VALUE) || insn.off != 0 {
+            return Err(self.error(pc, EINVAL, format_args!("invalid LD_IMM64 insn")));
+        }
+        self.check_reg_writable(pc, insn.dst)?;
+        let next = self.insns[pc + 1];
+        if src != 0 && !self.map_ids.contains(&(insn.imm as u32)) {
+            return Err(self.error(pc, EINVAL, format_args!("map {} is not used by the program", insn.imm as u32)));
+        }
+        if src == BPF_PSEUDO_MAP_FD {
+            // the map id is loaded as a plain constant after verification
+            if next.imm != 0 {
+                return Err(self.error(pc, EINVAL, format_args!("invalid LD_IMM64 insn")));
+            }
+            // helpers could write a read-only map, it is only accessed by direct value
+            if bpf_map_get_attr(insn.imm as u32).map_or(false, |attr| attr.map_flags & BPF_F_RDONLY_PROG != 0) {
+                return Err(self.error(pc, EACCES, format_args!("map {} is read-only to programs", insn.imm as u32)));
+            }
+            state.regs[insn.dst as usize] = RegState::ptr(PtrKind::ConstMap { id: insn.imm as u32 }, 0);
+            return Ok(());
+        }
+        if src == BPF_PSEUDO_MAP_VALUE {
+            // global data, the address of the value is filled in after verification
+            let id = insn.imm as u32;
+            let off = next.imm as i64;
+            let attr = match bpf_map_get_attr(id) {
+                Some(attr) if attr.map_type == BPF_MAP_TYPE_ARRAY && attr.max_entries == 1 => attr,
+                _ => return Err(self.error(pc, EINVAL, format_args!("map {} has no direct value access", id))),
+            };
+            if off < 0 || off >= attr.value_size as i64 {
+                return Err(self.error(pc, EACCES, format_args!("direct value offset of {} is not allowed", off)));
+            }
+            let kind = match attr.map_flags & BPF_F_RDONLY_PROG {
+                0 => PtrKind::MapValue { size: attr.value_size },
+                _ => PtrKind::RdonlyMapValue { size: attr.value_size },
+            };
+            state.regs[insn.dst as usize] = RegState::ptr(kind, off);
+            return Ok(());
+        }
+        let value = (insn.imm as u32 as u64) | ((next.imm as u32 as u64) << 32);
+        let table = self.map_fd_table.as_ptr() as u64;
+        let table_end = table + (self.map_fd_table.len() * core::mem::size_of::<u32>()) as u64;
+        state.regs[insn.dst as usize] = match value >= table && value < table_end {
+            true => RegState::ptr(PtrKind::MapFdSlot, (value - table) as i64),
+            false => RegState::known(value),
+        };
+        Ok(())
+    }
+
+    fn check_ldx(&mut self, pc: usize, insn: &BpfInsn, state: &mut VerifierState) -> Result<(), BpfErrorCode> {
+        let signed = match insn.mode() {
+            BPF_MEM => false,
+            BPF_MEMSX if insn.size() != BPF_DW => true,
+            _ => return Err(self.error(pc, EINVAL, format_args!("invalid BPF_LDX mode"))),
+        };
+        if insn.imm != 0 {
+            return Err(self.error(pc, EINVAL, format_args!("BPF_LDX uses reserved fields")));
+        }
+        self.check_reg_writable(pc, insn.dst)?;
+        let ptr = self.read_reg(pc, state, insn.src)?;
+        let value = self.check_mem_access(pc, state, insn.src, ptr, insn.off, insn.size_bytes(), None)?;
+        state.regs[insn.dst as usize] = match (signed, value.constant()) {
+            (false, _) => value,
+            (true, Some(v)) => RegState::known(match insn.size_bytes() {
+                1 => v as i8 as i64 as u64,
+                2 => v as i16 as i64 as u64,
+                _ => v as i32 as i64 as u64,
+            }),
+            (true, None) => RegState::unknown(),
+        };
+        Ok(())
+    }
+
+    fn check_store(&mut self, pc: usize, insn: &BpfInsn, state: &mut VerifierState) -> Result<(), BpfErrorCode> {
+        let ptr = self.read_reg(pc, state, insn.dst)?;
+        let value = match insn.class() {
+            BPF_ST => {
+                if insn.mode() != BPF_MEM || insn.src != 0 {
+                    return Err(self.error(pc, EINVAL, format_args!("invalid BPF_ST insn")));
+                }
+                RegState::known(insn.imm as i64 as u64)
+            }
+            _ => {
+                let value = self.read_reg(pc, state, insn.src)?;
+                match insn.mode() {
+                    BPF_MEM if insn.imm == 0 => value,
+                    BPF_ATOMIC => return self.check_atomic(pc, insn, state, ptr, value),
+                    _ => return Err(self.error(pc, EINVAL, format_args!("invalid BPF_STX insn"))),
+                }
+            }
+        };
+        self.check_mem_access(pc, state, insn.dst, ptr, insn.off, insn.size_bytes(), Some(value))?;
+        Ok(())
+    }
+
+    fn check_atomic(&mut self, pc: usize, insn: &BpfInsn, state: &mut VerifierState, ptr: RegState, value: RegState) -> Result<(), BpfErrorCode> {
+        let valid_op = match insn.imm {
+            BPF_XCHG | BPF_CMPXCHG => true,
+            imm => matches!((imm & !BPF_FETCH) as u8, BPF_ADD | BPF_OR | BPF_AND | BPF_XOR) && (imm & !0xf1) == 0,
+        };
+        if !valid_op || (insn.size() != BPF_W && insn.size() != BPF_DW) {
+            return Err(self.error(pc, EINVAL, format_args!("invalid atomic operation")));
+        }
+        if value.is_ptr() {
+            return Err(self.error(pc, EACCES, format_args!("R{} leaks addr into mem", insn.src)));
+        }
+        if let RegState::Ptr { kind: PtrKind::Ctx, .. } = ptr {
+            return Err(self.error(pc, EACCES, format_args!("atomic operation on ctx prohibited")));
+        }
+        if insn.imm == BPF_CMPXCHG {
+            let r0 = self.read_reg(pc, state, 0)?;
+            if r0.is_ptr() {
+                return Err(self.error(pc, EACCES, format_args!("R0 leaks addr into mem")));
+            }
+        }
+        let size = insn.size_bytes();
+        self.check_mem_access(pc, state, insn.dst, ptr, insn.off, size, None)?;
+        self.check_mem_access(pc, state, insn.dst, ptr, insn.off, size, Some(RegState::sized(size)))?;
+        match insn.imm {
+            BPF_CMPXCHG => state.regs[0] = RegState::sized(size),
+            imm if imm & BPF_FETCH != 0 => {
+                self.check_reg_writable(pc, insn.src)?;
+                state.regs[insn.src as usize] = RegState::sized(size);
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// # check_mem_access
+    /// check a load (`value` is None) or store through the pointer in register `reg`
+    /// # return value
+    /// * the abstract value loaded
+    fn check_mem_access(
+        &mut self, pc: usize, state: &mut VerifierState, reg: u8, ptr: RegState,
+        off: i16, size: usize, value: Option<RegState>,
+    ) -> Result<RegState, BpfErrorCode> {
+        let (kind, omin, omax) = match ptr {
+            RegState::Ptr { kind, omin, omax } => (kind, omin, omax),
+            _ => return Err(self.error(pc, EACCES, format_args!("R{} invalid mem access 'scalar'", reg))),
+        };
+        let lo = omin + off as i64;
+        let hi = omax + off as i64 + size as i64;
+        let in_bounds = |limit_lo: i64, limit_hi: i64| lo >= limit_lo && hi <= limit_hi;
+        match kind {
+            PtrKind::Ctx => {
+                if value.is_some() {
+                    return Err(self.error(pc, EACCES, format_args!("cannot write into ctx")));
+                }
+                if !in_bounds(0, self.ctx_size as i64) {
+                    return Err(self.error(pc, EACCES, format_args!("invalid ctx access off={} size={}", lo, size)));
+                }
+                Ok(RegState::sized(size))
+            }
+            PtrKind::Stack { frame } => {
+                if !in_bounds(-(BPF_STACK_SIZE as i64), 0) {
+                    return Err(self.error(pc, EACCES, format_args!("invalid stack access off={} size={}", lo, size)));
+                }
+                self.update_stack_depth(state.frame_subprog(frame), lo);
+                match value {
+                    Some(value) => {
+                        if omin != omax {
+                            return Err(self.error(pc, EACCES, format_args!("variable stack write prohibited")));
+                        }
+                        self.stack_write(pc, state, frame, lo, size, value)?;
+                        Ok(value)
+                    }
+                    None => self.stack_read(pc, state, frame, lo, hi, size, omin == omax),
+                }
+            }
+            PtrKind::MapValue { size: value_size } => {
+                if !in_bounds(0, value_size as i64) {
+                    return Err(self.error(pc, EACCES, format_args!("invalid access to map value, value_size={} off={} size={}", value_size, lo, size)));
+                }
+                if let Some(value) = value {
+                    if value.is_ptr() {
+                        return Err(self.error(pc, EACCES, format_args!("leaking pointer into map value")));
+                    }
+                }
+                Ok(RegState::sized(size))
+            }
+            PtrKind::RdonlyMapValue { size: value_size } => {
+                if value.is_some() {
+                    return Err(self.error(pc, EACCES, format_args!("write into map forbidden, value_size={} off={} size={}", value_size, lo, size)));
+                }
+                if !in_bounds(0, value_size as i64) {
+                    return Err(self.error(pc, EACCES, format_args!("invalid access to map value, value_size={} off={} size={}", value_size, lo, size)));
+                }
+                Ok(RegState::sized(size))
+            }
+            PtrKind::AllocMem { size: mem_size, .. } => {
+                if !in_bounds(0, mem_size as i64) {
+                    return Err(self.error(pc, EACCES, format_args!("invalid access to alloc mem, mem_size={} off={} size={}", mem_size, lo, size)));
+                }
+                if let Some(value) = value {
+                    if value.is_ptr() {
+                        return Err(self.error(pc, EACCES, format_args!("leaking pointer into alloc mem")));
+                    }
+                }
+                Ok(RegState::sized(size))
+            }
+            PtrKind::MapValueOrNull { .. } | PtrKind::AllocMemOrNull { .. } | PtrKind::ConstMap { .. } => {
+                Err(self.error(pc, EACCES, format_args!("R{} invalid mem access '{:?}'", reg, kind)))
+            }
+            PtrKind::MapFdSlot => {
+                let entry = core::mem::size_of::<u32>() as i64;
+                if value.is_some() || omin != omax || size != entry as usize || lo % entry != 0 {
+                    return Err(self.error(pc, EACCES, format_args!("invalid access to map fd table")));
+                }
+                match self.map_fd_table.get((lo / entry) as usize) {
+                    Some(&id) if lo >= 0 => Ok(RegState::ptr(PtrKind::ConstMap { id }, 0)),
+                    _ => Err(self.error(pc, EACCES, format_args!("invalid access to map fd table"))),
+                }
+            }
+        }
+    }
+
+    /// record that the function of a frame uses the stack down to `off`
+    fn update_stack_depth(&mut self, subprog: usize, off: i64) {
+        let depth = (off.unsigned_abs() as usize + 7) & !7;
+        let subprog = &mut self.subprogs[subprog];
+        subprog.stack_depth = subprog.stack_depth.max(depth);
+    }
+
+    fn stack_write(&mut self, pc: usize, state: &mut VerifierState, frame: usize, off: i64, size: usize, value: RegState) -> Result<(), BpfErrorCode> {
+        // the frame of a callee is gone once it returns
+        if let RegState::Ptr { kind: PtrKind::Stack { frame: target }, .. } = value {
+            if target > frame {
+                return Err(self.error(pc, EACCES, format_args!("cannot spill a pointer to the stack of frame {} into frame {}", target, frame)));
+            }
+        }
+        let start = stack_byte(off);
+        let stack = state.frame_stack_mut(frame);
+        if size == 8 && start % 8 == 0 {
+            stack[start / 8] = StackSlot { spilled: Some(value), init: 0xff };
+            return Ok(());
+        }
+        if value.is_ptr() {
+            return Err(self.error(pc, EACCES, format_args!("invalid size of register spill")));
+        }
+        mark_stack_init(stack, start, size);
+        Ok(())
+    }
+
+    fn stack_read(&mut self, pc: usize, state: &VerifierState, frame: usize, lo: i64, hi: i64, size: usize, fixed: bool) -> Result<RegState, BpfErrorCode> {
+        let start = stack_byte(lo);
+        let stack = state.frame_stack(frame);
+        if fixed && size == 8 && start % 8 == 0 {
+            if let Some(spilled) = stack[start / 8].spilled {
+                return Ok(spilled);
+            }
+        }
+        for byte in start..stack_byte(hi) {
+            let slot = stack[byte / 8];
+            // the bytes of a spilled pointer would leak the address
+            if slot.spilled.map_or(false, |spilled| spilled.is_ptr()) {
+                return Err(self.error(pc, EACCES, format_args!("invalid read of spilled pointer from stack off {}", byte as i64 - BPF_STACK_SIZE as i64)));
+            }
+            if slot.init & (1 << (byte % 8)) == 0 {
+                return Err(self.error(pc, EACCES, format_args!("invalid read from stack off {}", byte as i64 - BPF_STACK_SIZE as i64)));
+            }
+        }
+        Ok(RegState::sized(size))
+    }
+
+    /// check that memory of `size` bytes pointed by a helper argument is accessible
+    fn check_helper_mem(&mut self, pc: usize, state: &mut VerifierState, reg: u8, size: u64, write: bool) -> Result<(), BpfErrorCode> {
+        let ptr = state.regs[reg as usize];
+        let (kind, omin, omax) = match ptr {
+            RegState::Ptr { kind, omin, omax } => (kind, omin, omax),
+            _ => return Err(self.error(pc, EACCES, format_args!("R{} type=scalar expected=fp, map_value", reg))),
+        };
+        if size == 0 {
+            return Ok(());
+        }
+        if size > BPF_MAX_VAR_OFF {
+            return Err(self.error(pc, EACCES, format_args!("R{} memory size is unbounded", reg + 1)));
+        }
+        let (lo, hi) = (omin, omax + size as i64);
+        match kind {
+            PtrKind::Stack { frame } => {
+                if lo < -(BPF_STACK_SIZE as i64) || hi > 0 {
+                    return Err(self.error(pc, EACCES, format_args!("invalid indirect access to stack off={} size={}", lo, size)));
+                }
+                self.update_stack_depth(state.frame_subprog(frame), lo);
+                match write {
+                    true => {
+                        if omin != omax {
+                            return Err(self.error(pc, EACCES, format_args!("variable stack write prohibited")));
+                        }
+                        mark_stack_init(state.frame_stack_mut(frame), stack_byte(lo), size as usize);
+                        Ok(())
+                    }
+                    false => self.stack_read(pc, state, frame, lo, hi, size as usize, false).map(|_| ()),
+                }
+            }
+            PtrKind::RdonlyMapValue { .. } if write => {
+                Err(self.error(pc, EACCES, format_args!("R{} points to read-only map value", reg)))
+            }
+            PtrKind::MapValue { size: value_size } | PtrKind::RdonlyMapValue { size: value_size }
+            | PtrKind::AllocMem { size: value_size, .. } => {
+                if lo < 0 || hi > value_size as i64 {
+                    return Err(self.error(pc, EACCES, format_args!("invalid access to {:?} off={} size={}", kind, lo, size)));
+                }
+                Ok(())
+            }
+            PtrKind::Ctx if !write => {
+                if lo < 0 || hi > self.ctx_size as i64 {
+                    return Err(self.error(pc, EACCES, format_args!("invalid ctx access off={} size={}", lo, size)));
+                }
+                Ok(())
+            }
+            _ => Err(self.error(pc, EACCES, format_args!("R{} type={:?} expected=fp, map_value", reg, kind))),
+        }
+    }
+
+    /// # check_call
+    /// check helper arguments against `HELPER_PROTO_TABLE`
+    fn check_call(&mut self, pc: usize, insn: &BpfInsn, state: &mut VerifierState) -> Result<(), BpfErrorCode> {
+        if insn.class() != BPF_JMP || insn.src != 0 || insn.dst != 0 || insn.off != 0 {
+            return Err(self.error(pc, EINVAL, format_args!("invalid BPF_CALL insn")));
+        }
+        let func_id = insn.imm as u32 as usize;
+        let proto = match HELPER_PROTO_TABLE.get(func_id) {
+            Some(Some(proto)) => *proto,
+            _ => return Err(self.error(pc, EINVAL, format_args!("invalid func unknown#{}", func_id))),
+        };
+
+        let mut map = None;
+        let mut alloc_size = 0;
+        let mut release = None;
+        for (i, &arg_type) in proto.args.iter().enumerate() {
+            let reg = i as u8 + 1;
+            if arg_type == BpfArgType::DontCare {
+                continue;
+            }
+            let arg = self.read_reg(pc, state, reg)?;
+            match arg_type {
+                BpfArgType::DontCare => (),
+                BpfArgType::Anything => {
+                    if arg.is_ptr() {
+                        return Err(self.error(pc, EACCES, format_args!("R{} leaks addr into helper function", reg)));
+                    }
+                }
+                BpfArgType::PtrToCtx => {
+                    if arg != RegState::ptr(PtrKind::Ctx, 0) {
+                        return Err(self.error(pc, EACCES, format_args!("R{} type={:?} expected=ctx", reg, arg)));
+                    }
+                }
+                BpfArgType::ConstMapFd => {
+                    // plain scalars could name maps of other processes
+                    let attr = match arg {
+                        RegState::Ptr { kind: PtrKind::ConstMap { id }, omin: 0, omax: 0 } => bpf_map_get_attr(id),
+                        _ => None,
+                    };
+                    match attr {
+                        Some(attr) => map = Some(attr),
+                        None => return Err(self.error(pc, EACCES, format_args!("R{} type={:?} expected=map_ptr", reg, arg))),
+                    }
+                    self.check_map_func_compatibility(pc, map.unwrap().map_type, func_id)?;
+                }
+                BpfArgType::PtrToMapKey | BpfArgType::PtrToMapValue | BpfArgType::PtrToUninitMapValue => {
+                    let attr = match map {
+                        Some(attr) => attr,
+                        None => return Err(self.error(pc, EACCES, format_args!("invalid map_ptr to access map key/value"))),
+                    };
+                    let size = match arg_type {
+                        BpfArgType::PtrToMapKey => attr.key_size,
+                        _ => attr.value_size,
+                    };
+                    self.check_helper_mem(pc, state, reg, size as u64, arg_type == BpfArgType::PtrToUninitMapValue)?;
+                }
+                BpfArgType::PtrToMem | BpfArgType::PtrToUninitMem => {
+                    let size_reg = reg + 1;
+                    let size = match state.regs[size_reg as usize] {
+                        RegState::Scalar { umax, .. } if proto.args.get(i + 1) == Some(&BpfArgType::ConstSize) => umax,
+                        _ => return Err(self.error(pc, EACCES, format_args!("R{} is not a bounded size", size_reg))),
+                    };
+                    self.check_helper_mem(pc, state, reg, size, arg_type == BpfArgType::PtrToUninitMem)?;
+                }
+                BpfArgType::ConstSize => {
+                    if arg.is_ptr() {
+                        return Err(self.error(pc, EACCES, format_args!("R{} leaks addr into helper function", reg)));
+                    }
+                }
+                BpfArgType::ConstAllocSize => match arg.constant() {
+                    Some(size) if size <= BPF_MAX_VAR_OFF => alloc_size = size as usize,
+                    _ => return Err(self.error(pc, EACCES, format_args!("R{} is not a known constant size", reg))),
+                },
+                BpfArgType::PtrToAllocMem => match arg {
+                    RegState::Ptr { kind: PtrKind::AllocMem { id, .. }, omin: 0, omax: 0 } => release = Some(id),
+                    _ => return Err(self.error(pc, EACCES, format_args!("R{} type={:?} expected=alloc_mem at offset 0", reg, arg))),
+                },
+            }
+        }
+        if func_id == BPF_FUNC_TAIL_CALL && !state.refs.is_empty() {
+            return Err(self.error(pc, EINVAL, format_args!("tail_call would lead to reference leak")));
+        }
+        if func_id == BPF_FUNC_TAIL_CALL && !state.callers.is_empty() {
+            return Err(self.error(pc, EINVAL, format_args!("tail_call is only allowed in the entry function")));
+        }
+        if let Some(id) = release {
+            if !state.release_reference(id) {
+                return Err(self.error(pc, EINVAL, format_args!("reference id={} has not been acquired before", id)));
+            }
+        }
+
+        // caller saved registers are clobbered
+        for reg in 1..=5 {
+            state.regs[reg] = RegState::NotInit;
+        }
+        state.regs[0] = match (proto.ret, map) {
+            (BpfRetType::MapValueOrNull, Some(attr)) => {
+                let id = self.next_id;
+                self.next_id += 1;
+                RegState::ptr(PtrKind::MapValueOrNull { size: attr.value_size, id }, 0)
+            }
+            (BpfRetType::AllocMemOrNull, _) => {
+                let id = self.next_id;
+                self.next_id += 1;
+                state.refs.push(id);
+                RegState::ptr(PtrKind::AllocMemOrNull { size: alloc_size, id }, 0)
+            }
+            _ => RegState::unknown(),
+        };
+        Ok(())
+    }
+
+    /// # check_pseudo_call
+    /// enter the function called at `pc` with a new frame, r1 - r5 are its arguments
+    /// # return value
+    /// * the first instruction of the callee
+    fn check_pseudo_call(&mut self, pc: usize, insn: &BpfInsn, state: &mut VerifierState) -> Result<usize, BpfErrorCode> {
+        if insn.dst != 0 || insn.off != 0 {
+            return Err(self.error(pc, EINVAL, format_args!("invalid BPF_CALL insn")));
+        }
+        let frame = state.frame() + 1;
+        if frame >= MAX_CALL_FRAMES {
+            return Err(self.error(pc, E2BIG, format_args!("the call stack of {} frames is too deep", frame + 1)));
+        }
+        // the target is checked by check_cfg
+        let target = (pc as i64 + 1 + insn.imm as i64) as usize;
+        let mut regs = [RegState::NotInit; BPF_REG_COUNT];
+        regs[1..=5].copy_from_slice(&state.regs[1..=5]);
+        regs[BPF_REG_FP as usize] = RegState::ptr(PtrKind::Stack { frame }, 0);
+        let caller = CallerFrame {
+            regs: core::mem::replace(&mut state.regs, regs),
+            stack: core::mem::replace(&mut state.stack, [StackSlot { spilled: None, init: 0 }; STACK_SLOTS]),
+            subprog: state.subprog,
+            ret_pc: pc + 1,
+        };
+        state.callers.push(caller);
+        state.subprog = self.subprog_of(target);
+        Ok(target)
+    }
+
+    /// # check_max_stack_depth
+    /// the frames of a call chain share the stack, the deepest chain must fit in `BPF_STACK_SIZE`
+    fn check_max_stack_depth(&mut self) -> Result<(), BpfErrorCode> {
+        let count = self.subprogs.len();
+        let mut callees = vec![Vec::new(); count];
+        let mut callers = vec![Vec::new(); count];
+        for pc in 0..self.insns.len() {
+            let insn = self.insns[pc];
+            if is_pseudo_call(&insn) {
+                let (caller, callee) = (self.subprog_of(pc), self.subprog_of((pc as i64 + 1 + insn.imm as i64) as usize));
+                callees[caller].push(callee);
+                callers[callee].push(caller);
+            }
+        }
+        // the call graph is a DAG, visit callees before their callers
+        let mut pending: Vec<usize> = callees.iter().map(|callees| callees.len()).collect();
+        let mut ready: Vec<usize> = (0..count).filter(|&i| pending[i] == 0).collect();
+        let mut chain = vec![0; count];
+        while let Some(i) = ready.pop() {
+            chain[i] = self.subprogs[i].stack_depth + callees[i].iter().map(|&callee| chain[callee]).max().unwrap_or(0);
+            for &caller in &callers[i] {
+                pending[caller] -= 1;
+                if pending[caller] == 0 {
+                    ready.push(caller);
+                }
+            }
+        }
+        if chain[0] > BPF_STACK_SIZE {
+            let depth = chain[0];
+            return Err(self.error(0, EACCES, format_args!("combined stack size of calls is {}, limit is {}", depth, BPF_STACK_SIZE)));
+        }
+        Ok(())
+    }
+
+    /// some helpers only work on some map types, and some maps only through some helpers
+    fn check_map_func_compatibility(&mut self, pc: usize, map_type: u32, func_id: usize) -> Result<(), BpfErrorCode> {
+        let ringbuf_func = matches!(func_id, BPF_FUNC_RINGBUF_OUTPUT | BPF_FUNC_RINGBUF_RESERVE | BPF_FUNC_RINGBUF_QUERY);
+        let tail_call_func = func_id == BPF_FUNC_TAIL_CALL;
+        let stackid_func = func_id == BPF_FUNC_GET_STACKID;
+        let queue_func = matches!(func_id, BPF_FUNC_MAP_PUSH_ELEM | BPF_FUNC_MAP_POP_ELEM | BPF_FUNC_MAP_PEEK_ELEM);
+        if ringbuf_func != (map_type == BPF_MAP_TYPE_RINGBUF)
+            || tail_call_func != (map_type == BPF_MAP_TYPE_PROG_ARRAY)
+            || stackid_func != (map_type == BPF_MAP_TYPE_STACK_TRACE)
+            || queue_func != matches!(map_type, BPF_MAP_TYPE_QUEUE | BPF_MAP_TYPE_STACK) {
+            return Err(self.error(pc, EINVAL, format_args!("cannot pass map_type {} into func #{}", map_type, func_id)));
+        }
+        Ok(())
+    }
+
+    fn check_exit(&mut self, pc: usize, insn: &BpfInsn, state: &VerifierState) -> Result<(), BpfErrorCode> {
+        if insn.class() != BPF_JMP || insn.src != 0 || insn.dst != 0 || insn.off != 0 || insn.imm != 0 {
+            return Err(self.error(pc, EINVAL, format_args!("invalid BPF_EXIT insn")));
+        }
+        let r0 = self.read_reg(pc, state, 0)?;
+        if r0.is_ptr() {
+            return Err(self.error(pc, EACCES, format_args!("R0 leaks addr as return value")));
+        }
+        // references may be passed between functions, only the entry must release them
+        if !state.callers.is_empty() {
+            return Ok(());
+        }
+        if let Some(&id) = state.refs.first() {
+            return Err(self.error(pc, EINVAL, format_args!("unreleased reference id={}", id)));
+        }
+        Ok(())
+    }
+
+    /// # check_cond_jmp
+    /// # return value
+    /// * states of the fall through and taken branch, None if the branch is impossible
+    fn check_cond_jmp(&mut self, pc: usize, insn: &BpfInsn, state: VerifierState) -> Result<(Option<VerifierState>, Option<VerifierState>), BpfErrorCode> {
+        let op = insn.op();
+        match op {
+            BPF_JEQ | BPF_JNE | BPF_JGT | BPF_JGE | BPF_JLT | BPF_JLE | BPF_JSET
+            | BPF_JSGT | BPF_JSGE | BPF_JSLT | BPF_JSLE => (),
+            _ => return Err(self.error(pc, EINVAL, format_args!("invalid BPF_JMP opcode {:#x}", op))),
+        }
+        if insn.source() == BPF_K && insn.src != 0 {
+            return Err(self.error(pc, EINVAL, format_args!("BPF_JMP uses reserved fields")));
+        }
+        let is64 = insn.class() == BPF_JMP;
+        let dst = self.read_reg(pc, &state, insn.dst)?;
+        let src = match insn.source() {
+            BPF_X => self.read_reg(pc, &state, insn.src)?,
+            _ => RegState::known(insn.imm as i64 as u64),
+        };
+
+        // null check of a map lookup or allocation result
+        if let RegState::Ptr { kind: PtrKind::MapValueOrNull { id, .. } | PtrKind::AllocMemOrNull { id, .. }, .. } = dst {
+            if is64 && src.constant() == Some(0) && (op == BPF_JEQ || op == BPF_JNE) {
+                let mut null = state.clone();
+                let mut non_null = state;
+                null.mark_ptr_or_null(id, true);
+                non_null.mark_ptr_or_null(id, false);
+                return Ok(match op {
+                    BPF_JEQ => (Some(non_null), Some(null)),
+                    _ => (Some(null), Some(non_null)),
+                });
+            }
+        }
+
+        let (dst, src) = match is64 {
+            true => (dst, src),
+            false => (truncate32(dst), truncate32(src)),
+        };
+        let (umin, umax, c) = match (dst, src.constant()) {
+            (RegState::Scalar { umin, umax }, Some(c)) => (umin, umax, c),
+            _ => return Ok((Some(state.clone()), Some(state))),
+        };
+        if umin == umax {
+            let taken = condition(op, umin, c, !is64).map_err(|e| self.error(pc, e, format_args!("invalid condition")))?;
+            return Ok(match taken {
+                true => (None, Some(state)),
+                false => (Some(state), None),
+            });
+        }
+
+        // signed comparisons behave like unsigned ones when both sides are non-negative
+        let sign_bit = if is64 { 1u64 << 63 } else { 1u64 << 31 };
+        let unsigned_op = match op {
+            BPF_JSGT | BPF_JSGE | BPF_JSLT | BPF_JSLE if umax >= sign_bit || c >= sign_bit => None,
+            BPF_JSGT => Some(BPF_JGT),
+            BPF_JSGE => Some(BPF_JGE),
+            BPF_JSLT => Some(BPF_JLT),
+            BPF_JSLE => Some(BPF_JLE),
+            BPF_JSET => None,
+            _ => Some(op),
+        };
+        // bounds of dst when the comparison holds and when it does not
+        let (umin, umax, c) = (umin as i128, umax as i128, c as i128);
+        let ranges = match unsigned_op {
+            Some(BPF_JEQ) => ((c, c), (umin, umax)),
+            Some(BPF_JNE) => ((umin, umax), (c, c)),
+            Some(BPF_JGT) => ((c + 1, umax), (umin, c)),
+            Some(BPF_JGE) => ((c, umax), (umin, c - 1)),
+            Some(BPF_JLT) => ((umin, c - 1), (c, umax)),
+            Some(BPF_JLE) => ((umin, c), (c + 1, umax)),
+            _ => return Ok((Some(state.clone()), Some(state))),
+        };
+        let refine = |(lo, hi): (i128, i128)| -> Option<VerifierState> {
+            let (lo, hi) = (lo.max(umin), hi.min(umax));
+            if lo > hi {
+                return None;
+            }
+            let mut refined = state.clone();
+            // only full width comparisons tell the upper 32 bits
+            if is64 {
+                refined.regs[insn.dst as usize] = RegState::Scalar { umin: lo as u64, umax: hi as u64 };
+            }
+            Some(refined)
+        };
+        // JEQ and JNE cannot narrow the unequal side
+        Ok(match unsigned_op {
+            Some(BPF_JEQ) => (Some(state.clone()), refine(ranges.0)),
+            Some(BPF_JNE) => (refine(ranges.1), Some(state.clone())),
+            _ => (refine(ranges.1), refine(ranges.0)),
+        })
+    }
+}
+
+/// the lower 32 bits of a value
+fn truncate32(reg: RegState) -> RegState {
+    match reg {
+        RegState::Scalar { umin, umax } if umin == umax => RegState::known(umin as u32 as u64),
+        RegState::Scalar { umax, .. } if umax > u32::MAX as u64 => RegState::Scalar { umin: 0, umax: u32::MAX as u64 },
+        _ => reg,
+    }
+}
+
+/// mark bytes written through a helper or a partial store, spilled scalars become plain bytes,
+/// the rest of a slot holding a spilled pointer becomes unreadable instead of bytes of the address
+fn mark_stack_init(stack: &mut Stack, start: usize, size: usize) {
+    for byte in start..start + size {
+        let slot = &mut stack[byte / 8];
+        if slot.spilled.take().map_or(false, |spilled| spilled.is_ptr()) {
+            slot.init = 0;
+        }
+        slot.init |= 1 << (byte % 8);
+    }
+}
@@ -1,8 +1,10 @@
 mod stdio;
 mod inode;
 mod pipe;
+mod urandom;
 
 use crate::mm::UserBuffer;
+use crate::ebpf::BpfObject;
 
 /// The common abstraction of all IO resources
 pub trait File : Send + Sync {
@@ -10,6 +12,10 @@ pub trait File : Send + Sync {
     fn writable(&self) -> bool;
     fn read(&self, buf: UserBuffer) -> usize;
     fn write(&self, buf: UserBuffer) -> usize;
+    /// BPF maps and programs are kept in the fd table as well, returns their id and object
+    fn bpf_object(&self) -> Option<(u32, BpfObject)> {
+        None
+    }
 }
 
 /// The stat of a inode
@@ -43,3 +49,4 @@ bitflags! {
 pub use stdio::{Stdin, Stdout};
 pub use inode::{OSInode, open_file, OpenFlags, list_apps};
 pub use pipe::{Pipe, make_pipe};
+pub use urandom::{Urandom, URANDOM_PATH};
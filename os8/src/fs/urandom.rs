@@ -0,0 +1,23 @@
+use super::File;
+use crate::mm::UserBuffer;
+use crate::random::fill_bytes;
+
+/// `/dev/urandom`, reads never block and return random bytes
+pub struct Urandom;
+
+/// path of the random device, it is not in the file system
+pub const URANDOM_PATH: &str = "/dev/urandom";
+
+impl File for Urandom {
+    fn readable(&self) -> bool { true }
+    fn writable(&self) -> bool { false }
+    fn read(&self, mut user_buf: UserBuffer) -> usize {
+        for buffer in user_buf.buffers.iter_mut() {
+            fill_bytes(buffer);
+        }
+        user_buf.len()
+    }
+    fn write(&self, _user_buf: UserBuffer) -> usize {
+        0
+    }
+}
@@ -0,0 +1,82 @@
+//! Kernel symbol table
+//!
+//! The build embeds the output of `nm -C` for the kernel's own functions
+//! into the `.ksyms` section, one `address type name` entry per line.
+//! The section is placed after `.data` and before `.bss`, so filling it in does not move any code,
+//! see `Makefile` and `build.rs` for how the table is generated.
+
+use alloc::vec::Vec;
+
+/// the table generated by the previous build, empty on the first build
+#[link_section = ".ksyms"]
+#[used]
+static KSYMS: [u8; include_bytes!(concat!(env!("OUT_DIR"), "/kernel.sym")).len()] =
+    *include_bytes!(concat!(env!("OUT_DIR"), "/kernel.sym"));
+
+/// raw table, bounded by linker symbols so that code does not depend on its size
+fn ksyms_data() -> &'static [u8] {
+    extern "C" {
+        fn sksyms();
+        fn eksyms();
+    }
+    unsafe {
+        core::slice::from_raw_parts(sksyms as usize as *const u8, eksyms as usize - sksyms as usize)
+    }
+}
+
+/// iterate over (address, name) of every function in the table
+fn ksyms_iter() -> impl Iterator<Item = (usize, &'static str)> {
+    ksyms_data()
+        .split(|&c| c == b'\n')
+        .filter_map(|line| core::str::from_utf8(line).ok())
+        .filter_map(|line| {
+            let mut fields = line.trim().splitn(3, ' ');
+            let addr = usize::from_str_radix(fields.next()?, 16).ok()?;
+            let _type = fields.next()?;
+            Some((addr, strip_hash(fields.next()?)))
+        })
+}
+
+/// remove the `::h0123456789abcdef` suffix of rust legacy mangling
+fn strip_hash(name: &str) -> &str {
+    match name.rsplit_once("::h") {
+        Some((path, hash)) if hash.len() == 16 && hash.bytes().all(|c| c.is_ascii_hexdigit()) => path,
+        _ => name,
+    }
+}
+
+/// Warn if the table is empty, symbols can not be resolved until the kernel is rebuilt
+pub fn init() {
+    if ksyms_iter().next().is_none() {
+        warn!("kernel symbol table is empty, rebuild the kernel to probe functions by name");
+    }
+}
+
+/// Find the address of a kernel function
+///
+/// `symbol` is either the full path like `os::syscall::fs::sys_write`,
+/// or the last component like `sys_write` if it is unique.
+pub fn lookup_symbol(symbol: &str) -> Option<usize> {
+    let mut candidates = Vec::new();
+    for (addr, name) in ksyms_iter() {
+        if name == symbol {
+            return Some(addr);
+        }
+        if name.rsplit("::").next() == Some(symbol) && !candidates.contains(&addr) {
+            candidates.push(addr);
+        }
+    }
+    match candidates.len() {
+        1 => Some(candidates[0]),
+        0 => None,
+        _ => {
+            warn!("ambiguous kernel symbol {}, use the full path", symbol);
+            None
+        }
+    }
+}
+
+/// Find the full path of the kernel function starting at `addr`
+pub fn lookup_address(addr: usize) -> Option<&'static str> {
+    ksyms_iter().find(|&(sym_addr, _)| sym_addr == addr).map(|(_, name)| name)
+}
@@ -33,6 +33,7 @@ mod console;
 mod config;
 mod drivers;
 mod fs;
+mod ksyms;
 mod lang_items;
 mod logging;
 mod mm;
@@ -43,6 +44,7 @@ mod task;
 mod timer;
 mod trap;
 mod probe;
+mod random;
 mod ebpf;
 
 core::arch::global_asm!(include_str!("entry.asm"));
@@ -70,10 +72,13 @@ pub fn rust_main() -> ! {
     trap::init();
     trap::enable_timer_interrupt();
     timer::set_next_trigger();
+    random::init();
+    ksyms::init();
     // Uncomment following lines and see what happens!
     // task::kernel_stackless_coroutine_test();
     // task::kernel_stackful_coroutine_test();
     probe::run_tests();
+    ebpf::run_tests();
     fs::list_apps();
     task::add_initproc();
     task::run_tasks();
@@ -40,6 +40,7 @@ impl Drop for FrameTracker {
 trait FrameAllocator {
     fn new() -> Self;
     fn alloc(&mut self) -> Option<PhysPageNum>;
+    fn alloc_contiguous(&mut self, count: usize) -> Option<PhysPageNum>;
     fn dealloc(&mut self, ppn: PhysPageNum);
 }
 
@@ -75,6 +76,15 @@ impl FrameAllocator for StackFrameAllocator {
             Some((self.current - 1).into())
         }
     }
+    /// recycled frames are scattered, so take frames never allocated
+    fn alloc_contiguous(&mut self, count: usize) -> Option<PhysPageNum> {
+        if self.end - self.current < count {
+            None
+        } else {
+            self.current += count;
+            Some((self.current - count).into())
+        }
+    }
     fn dealloc(&mut self, ppn: PhysPageNum) {
         let ppn = ppn.0;
         // validity check
@@ -112,6 +122,12 @@ pub fn frame_alloc() -> Option<FrameTracker> {
         .map(FrameTracker::new)
 }
 
+/// allocate `count` frames with consecutive numbers, the kernel sees them as one buffer
+pub fn frame_alloc_contiguous(count: usize) -> Option<Vec<FrameTracker>> {
+    let start = FRAME_ALLOCATOR.exclusive_access().alloc_contiguous(count)?;
+    Some((start.0..start.0 + count).map(|ppn| FrameTracker::new(ppn.into())).collect())
+}
+
 pub fn raw_frame_alloc() -> Option<PhysAddr> {
     FRAME_ALLOCATOR.exclusive_access().alloc().map(|ppn| ppn.into())
 }
@@ -4,7 +4,7 @@ use super::{frame_alloc, FrameTracker};
 use super::{PTEFlags, PageTable, PageTableEntry};
 use super::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
 use super::{StepByOne, VPNRange};
-use crate::config::{MEMORY_END, MMIO, PAGE_SIZE, TRAMPOLINE};
+use crate::config::{MEMORY_END, MMIO, PAGE_SIZE, TRAMPOLINE, USER_SPACE_END};
 use crate::sync::UPSafeCell;
 use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
@@ -19,6 +19,8 @@ extern "C" {
     fn erodata();
     fn sdata();
     fn edata();
+    fn sksyms();
+    fn eksyms();
     fn sbss_with_stack();
     fn ebss();
     fn ekernel();
@@ -36,6 +38,9 @@ pub fn kernel_token() -> usize {
     KERNEL_SPACE.exclusive_access().token()
 }
 
+/// frames owned by something else, like a BPF map, that processes can map
+pub type SharedFrames = Arc<Vec<FrameTracker>>;
+
 /// memory set structure, controls virtual-memory space
 pub struct MemorySet {
     page_table: PageTable,
@@ -64,6 +69,54 @@ impl MemorySet {
             None,
         );
     }
+    /// Map the first frames of `frames` to `[start_va, end_va)`, they are shared with
+    /// their owner and other mappings. Fails if the range is not below `USER_SPACE_END`,
+    /// or overlaps an existing area or a page mapped outside of areas, like the trampoline.
+    pub fn insert_shared_area(
+        &mut self,
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        frames: SharedFrames,
+        permission: MapPermission,
+    ) -> bool {
+        if start_va >= end_va || end_va.0 > USER_SPACE_END {
+            return false;
+        }
+        let (start_vpn, end_vpn) = (start_va.floor(), end_va.ceil());
+        if end_vpn.0 - start_vpn.0 > frames.len() || self.overlaps(start_vpn, end_vpn) {
+            return false;
+        }
+        let mapped = (start_vpn.0..end_vpn.0)
+            .any(|vpn| self.page_table.translate(vpn.into()).map_or(false, |pte| pte.is_valid()));
+        if mapped {
+            return false;
+        }
+        let mut map_area = MapArea::new(start_va, end_va, MapType::Shared, permission);
+        map_area.shared_frames = Some(frames);
+        self.push(map_area, None);
+        true
+    }
+    /// Unmap the shared area that is exactly `[start_vpn, end_vpn)`.
+    pub fn remove_shared_area(&mut self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) -> bool {
+        let found = self.areas.iter().position(|area| {
+            area.map_type == MapType::Shared
+                && area.vpn_range.get_start() == start_vpn
+                && area.vpn_range.get_end() == end_vpn
+        });
+        match found {
+            Some(idx) => {
+                self.areas[idx].unmap(&mut self.page_table);
+                self.areas.remove(idx);
+                true
+            }
+            None => false,
+        }
+    }
+    fn overlaps(&self, start_vpn: VirtPageNum, end_vpn: VirtPageNum) -> bool {
+        self.areas.iter().any(|area| {
+            area.vpn_range.get_start() < end_vpn && start_vpn < area.vpn_range.get_end()
+        })
+    }
     pub fn remove_area_with_start_vpn(&mut self, start_vpn: VirtPageNum) {
         if let Some((idx, area)) = self
             .areas
@@ -99,6 +152,7 @@ impl MemorySet {
         info!(".text [{:#x}, {:#x})", stext as usize, etext as usize);
         info!(".rodata [{:#x}, {:#x})", srodata as usize, erodata as usize);
         info!(".data [{:#x}, {:#x})", sdata as usize, edata as usize);
+        info!(".ksyms [{:#x}, {:#x})", sksyms as usize, eksyms as usize);
         info!(
             ".bss [{:#x}, {:#x})",
             sbss_with_stack as usize, ebss as usize
@@ -133,6 +187,16 @@ impl MemorySet {
             ),
             None,
         );
+        info!("mapping .ksyms section");
+        memory_set.push(
+            MapArea::new(
+                (sksyms as usize).into(),
+                (eksyms as usize).into(),
+                MapType::Identical,
+                MapPermission::R,
+            ),
+            None,
+        );
         info!("mapping .bss section");
         memory_set.push(
             MapArea::new(
@@ -224,6 +288,10 @@ impl MemorySet {
         for area in user_space.areas.iter() {
             let new_area = MapArea::from_another(area);
             memory_set.push(new_area, None);
+            // shared frames are mapped again, not copied
+            if area.map_type == MapType::Shared {
+                continue;
+            }
             // copy data from another space
             for vpn in area.vpn_range {
                 let src_ppn = user_space.translate(vpn).unwrap().ppn();
@@ -265,6 +333,8 @@ pub struct MapArea {
     data_frames: BTreeMap<VirtPageNum, FrameTracker>,
     map_type: MapType,
     map_perm: MapPermission,
+    /// frames of a `MapType::Shared` area, in page order
+    shared_frames: Option<SharedFrames>,
 }
 
 impl MapArea {
@@ -281,6 +351,7 @@ impl MapArea {
             data_frames: BTreeMap::new(),
             map_type,
             map_perm,
+            shared_frames: None,
         }
     }
     pub fn from_another(another: &MapArea) -> Self {
@@ -289,6 +360,7 @@ impl MapArea {
             data_frames: BTreeMap::new(),
             map_type: another.map_type,
             map_perm: another.map_perm,
+            shared_frames: another.shared_frames.clone(),
         }
     }
     pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
@@ -302,6 +374,10 @@ impl MapArea {
                 ppn = frame.ppn;
                 self.data_frames.insert(vpn, frame);
             }
+            MapType::Shared => {
+                let frames = self.shared_frames.as_ref().unwrap();
+                ppn = frames[vpn.0 - self.vpn_range.get_start().0].ppn;
+            }
         }
         let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
         page_table.map(vpn, ppn, pte_flags);
@@ -352,10 +428,12 @@ impl MapArea {
 }
 
 #[derive(Copy, Clone, PartialEq, Debug)]
-/// map type for memory set: identical or framed
+/// map type for memory set: identical, framed or shared
 pub enum MapType {
     Identical,
     Framed,
+    /// frames of the area are owned with others, see `SharedFrames`
+    Shared,
 }
 
 bitflags! {
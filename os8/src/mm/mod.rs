@@ -15,9 +15,9 @@ mod page_table;
 
 pub use address::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
 pub use address::{StepByOne, VPNRange};
-pub use frame_allocator::{frame_alloc, frame_dealloc, raw_frame_alloc, raw_frame_dealloc, FrameTracker};
+pub use frame_allocator::{frame_alloc, frame_alloc_contiguous, frame_dealloc, raw_frame_alloc, raw_frame_dealloc, FrameTracker};
 pub use memory_set::{remap_test, kernel_token};
-pub use memory_set::{MapPermission, MemorySet, KERNEL_SPACE};
+pub use memory_set::{MapPermission, MemorySet, SharedFrames, KERNEL_SPACE};
 pub use page_table::{translated_byte_buffer, translated_refmut, translated_ref, translated_str, PageTableEntry};
 pub use page_table::{PTEFlags, PageTable, UserBuffer};
 
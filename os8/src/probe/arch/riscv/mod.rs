@@ -79,7 +79,9 @@ pub fn get_insn_type(addr: usize) -> SingleStepType {
                 Auipc(_) | Jal(_) | Jalr(_) | Beq(_) | Bne(_) | Blt(_) | Bge(_) | Bltu(_)
                 | Bgeu(_) => Emulate,
                 Compressed(c_insn) => match c_insn {
-                    CJ(_) | CJr(_) | CJalr(_) | CBeqz(_) | CBnez(_) => Emulate,
+                    CJ(_) | CJr(_) | CJalr(_) => Emulate,
+                    // pc-relative, but not handled by `emulate_execution` yet
+                    CBeqz(_) | CBnez(_) => Unsupported,
                     _ => Execute,
                 },
                 _ => Execute, // TODO: handle priviledged instructions
@@ -128,6 +130,27 @@ pub fn emulate_execution(tf: &mut TrapFrame, insn_addr: usize, pc: usize) {
                 set_trapframe_pc(tf, pc + 4);
             }
         }
+        Blt(b_type) | Bge(b_type) | Bltu(b_type) | Bgeu(b_type) => {
+            let offset = b_type.imm() as isize;
+            let rs1 = get_reg(tf, b_type.rs1());
+            let rs2 = get_reg(tf, b_type.rs2());
+            let taken = match insn {
+                Blt(_) => (rs1 as isize) < (rs2 as isize),
+                Bge(_) => (rs1 as isize) >= (rs2 as isize),
+                Bltu(_) => rs1 < rs2,
+                _ => rs1 >= rs2,
+            };
+            if taken {
+                set_trapframe_pc(tf, pc + offset as usize);
+            } else {
+                set_trapframe_pc(tf, pc + 4);
+            }
+        }
+        Auipc(u_type) => {
+            let offset = u_type.imm() as i32 as isize;
+            set_reg(tf, u_type.rd(), pc + offset as usize);
+            set_trapframe_pc(tf, pc + 4);
+        }
         Compressed(c_insn) => match c_insn {
             CJ(cj_type) => {
                 let offset = cj_type.imm() as isize;
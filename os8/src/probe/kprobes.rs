@@ -5,6 +5,7 @@ use core::ops::Fn;
 use lazy_static::*;
 
 use super::arch::*;
+use super::osutils::probe_idle;
 use super::{KProbeArgs, TrapFrame};
 
 pub type Handler = dyn Fn(&mut TrapFrame, usize) -> isize + Sync + Send;
@@ -18,6 +19,8 @@ struct KProbe {
     insn_buf: InstructionBuffer,
     insn_len: usize,
     active_count: usize,
+    /// an unregistration failed while active, `probe_idle` is called once it is not
+    unregister_pending: bool,
     emulate: bool,
 }
 
@@ -51,6 +54,7 @@ impl KProbe {
             insn_buf: InstructionBuffer::new(),
             insn_len: get_insn_length(addr),
             active_count: 0,
+            unregister_pending: false,
             emulate,
         }
     }
@@ -64,6 +68,11 @@ impl KProbe {
         invalidate_icache();
     }
 
+    /// whether the probe just became idle with an unregistration pending
+    fn finish(&mut self) -> bool {
+        self.active_count == 0 && core::mem::take(&mut self.unregister_pending)
+    }
+
     pub fn disarm(&self) {
         // change to original instruction
         self.insn_buf.copy_out(0, self.addr, self.insn_len);
@@ -88,6 +97,11 @@ pub fn kprobe_trap_handler(tf: &mut TrapFrame) -> bool {
                 let _ = handler(tf, probe.user_data);
             }
             probe.active_count -= 1;
+            let idle = probe.finish();
+            drop(map);
+            if idle {
+                probe_idle();
+            }
             // finished probing, back to kernel handler
             return true;
         }
@@ -99,13 +113,19 @@ pub fn kprobe_trap_handler(tf: &mut TrapFrame) -> bool {
     }
 
     // post_handler stage
-    if let Some(orig_addr) = ADDR_MAP.lock().get(&pc) {
-        let probe = map.get_mut(orig_addr).unwrap();
+    let orig_addr = ADDR_MAP.lock().get(&pc).copied();
+    if let Some(orig_addr) = orig_addr {
+        let probe = map.get_mut(&orig_addr).unwrap();
         if let Some(handler) = &probe.post_handler {
             let _ = handler(tf, probe.user_data);
         }
         probe.active_count -= 1;
-        set_trapframe_pc(tf, *orig_addr + probe.insn_len);
+        set_trapframe_pc(tf, orig_addr + probe.insn_len);
+        let idle = probe.finish();
+        drop(map);
+        if idle {
+            probe_idle();
+        }
         return true;
     }
     false
@@ -145,8 +165,9 @@ pub fn register_kprobe(addr: usize, args: KProbeArgs) -> bool {
 /// possible errors: kprobe not exist at given addr, kprobe is still active(post handler not executed)
 pub fn unregister_kprobe(addr: usize) -> bool {
     let mut map = KPROBES.lock();
-    if let Some(probe) = map.get(&addr) {
+    if let Some(probe) = map.get_mut(&addr) {
         if probe.active_count > 0 {
+            probe.unregister_pending = true;
             false
         } else {
             probe.disarm();
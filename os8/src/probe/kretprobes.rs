@@ -8,6 +8,7 @@ use super::arch::{
     set_trapframe_ra,
 };
 use super::kprobes::{register_kprobe, unregister_kprobe, Handler};
+use super::osutils::probe_idle;
 use super::{KProbeArgs, KRetProbeArgs, TrapFrame};
 
 /// instances: the function is entered but has not returned, leaving the probe hanging
@@ -20,6 +21,8 @@ struct KRetProbe {
     user_data: usize,
     nr_instances: usize,
     nr_misses: usize,
+    /// an unregistration failed while instances were hanging, `probe_idle` is called once none are
+    unregister_pending: bool,
 }
 
 struct KRetProbeInstance {
@@ -52,6 +55,7 @@ impl KRetProbe {
             user_data,
             nr_instances: 0,
             nr_misses: 0,
+            unregister_pending: false,
         }
     }
 }
@@ -104,12 +108,18 @@ pub fn kretprobe_trap_handler(tf: &mut TrapFrame) -> bool {
     let probe = kretprobes.get_mut(&instance.entry_addr).unwrap();
     let _ = (probe.exit_handler)(tf, probe.user_data);
     probe.nr_instances -= 1;
+    let idle = probe.nr_instances == 0 && core::mem::take(&mut probe.unregister_pending);
 
     let ra = instance.ret_addr;
     set_trapframe_pc(tf, ra);
     set_trapframe_ra(tf, ra);
     free_breakpoint(pc);
     instance_map.remove(&pc).unwrap();
+    drop(instance_map);
+    drop(kretprobes);
+    if idle {
+        probe_idle();
+    }
     true
 }
 
@@ -131,8 +141,9 @@ pub fn register_kretprobe(entry_addr: usize, args: KRetProbeArgs) -> bool {
 
 pub fn unregister_kretprobe(entry_addr: usize) -> bool {
     let mut kretprobes = KRETPROBES.lock();
-    if let Some(probe) = kretprobes.get(&entry_addr) {
+    if let Some(probe) = kretprobes.get_mut(&entry_addr) {
         if probe.nr_instances > 0 {
+            probe.unregister_pending = true;
             false
         } else {
             let ok = unregister_kprobe(entry_addr);
@@ -1,5 +1,6 @@
 pub mod kprobes;
 pub mod kretprobes;
+pub mod uprobes;
 pub mod osutils;
 pub use osutils::init_osutils;
 
@@ -74,6 +75,26 @@ pub fn unregister_kretprobe(addr: usize) -> Option<()> {
     }
 }
 
+pub fn register_uprobe(path: &str, offset: usize, args: KProbeArgs) -> Option<()> {
+    match uprobes::register_uprobe(path, offset, args) {
+        true => Some(()),
+        false => None,
+    }
+}
+
+pub fn unregister_uprobe(path: &str, offset: usize) -> Option<()> {
+    match uprobes::unregister_uprobe(path, offset) {
+        true => Some(()),
+        false => None,
+    }
+}
+
+/// This function should be called from the user trap handler when a breakpoint is hit,
+/// returns false if the breakpoint is not a uprobe
+pub fn uprobes_breakpoint_handler(tf: &mut TrapFrame) -> bool {
+    uprobes::uprobe_trap_handler(tf)
+}
+
 /// This function should be called from the trap handler when a breakpoint is hit.
 #[no_mangle]
 pub fn kprobes_breakpoint_handler(tf: &mut TrapFrame) {
@@ -1,6 +1,13 @@
-use crate::mm::{raw_frame_alloc, raw_frame_dealloc};
+use crate::mm::{raw_frame_alloc, raw_frame_dealloc, MapPermission, VirtAddr, VirtPageNum};
+use crate::task::{current_process, current_task, ProcessControlBlock};
+use crate::fs::{open_file, OpenFlags};
+use alloc::sync::{Arc, Weak};
 
 pub const PAGE_SIZE: usize = crate::config::PAGE_SIZE;
+pub const UPROBE_XOL: usize = crate::config::UPROBE_XOL;
+
+/// a user address space that uprobes are armed in, held weakly so that exited processes are dropped
+pub type UserSpace = Weak<ProcessControlBlock>;
 
 /// optional function to initialize anything needed
 pub fn init_osutils() {
@@ -29,7 +36,89 @@ pub fn byte_copy(dst_addr: usize, src_addr: usize, len: usize) {
     }
 }
 
-/// Convert symbol to address for kprobe registering, not required
+/// Convert symbol to address for kprobe registering, uses the embedded kernel symbol table
 pub fn symbol_to_addr(symbol: &str) -> Option<usize> {
+    crate::ksyms::lookup_symbol(symbol)
+}
+
+/// Convert the address of a function back to its symbol, for reporting probes
+pub fn addr_to_symbol(addr: usize) -> Option<&'static str> {
+    crate::ksyms::lookup_address(addr)
+}
+
+/// The address space of the current process
+pub fn current_user_space() -> UserSpace {
+    Arc::downgrade(&current_process())
+}
+
+/// The pid and tid of the current thread
+pub fn current_thread_id() -> (usize, usize) {
+    let task = current_task().unwrap();
+    let tid = task.inner_exclusive_access().res.as_ref().unwrap().tid;
+    (current_process().getpid(), tid)
+}
+
+/// Find the instruction at `offset` of the executable `path`
+/// returns its virtual address and the (up to) 4 bytes at that offset,
+/// the offset must be inside an executable loadable segment
+pub fn user_offset_to_addr(path: &str, offset: usize) -> Option<(usize, u32)> {
+    let data = open_file(path, OpenFlags::RDONLY)?.read_all();
+    let elf = xmas_elf::ElfFile::new(data.as_slice()).ok()?;
+    for ph in elf.program_iter() {
+        if ph.get_type() != Ok(xmas_elf::program::Type::Load) || !ph.flags().is_execute() {
+            continue;
+        }
+        let start = ph.offset() as usize;
+        let end = start + ph.file_size() as usize;
+        if offset < start || offset >= end || offset % 2 != 0 {
+            continue;
+        }
+        let mut insn = [0u8; 4];
+        let len = (end - offset).min(4);
+        insn[..len].copy_from_slice(&data[offset..offset + len]);
+        return Some((ph.virtual_addr() as usize + offset - start, u32::from_le_bytes(insn)));
+    }
     None
-}
\ No newline at end of file
+}
+
+/// Write `src` into a user address space, ignoring the page permissions
+/// the page of `UPROBE_XOL` is mapped on first use, readable and executable by user
+/// fails if the process has exited or the address is not mapped
+pub fn user_space_write(space: &UserSpace, addr: usize, src: &[u8]) -> bool {
+    let process = match space.upgrade() {
+        Some(process) => process,
+        None => return false,
+    };
+    let mut inner = match process.try_inner_exclusive_access() {
+        Some(inner) => inner,
+        None => return false,
+    };
+    if inner.is_zombie {
+        return false;
+    }
+    let memory_set = &mut inner.memory_set;
+    let xol_vpn: VirtPageNum = VirtAddr::from(UPROBE_XOL).floor();
+    if VirtAddr::from(addr).floor() == xol_vpn
+        && !memory_set.translate(xol_vpn).map_or(false, |pte| pte.is_valid())
+    {
+        memory_set.insert_framed_area(
+            UPROBE_XOL.into(),
+            (UPROBE_XOL + PAGE_SIZE).into(),
+            MapPermission::R | MapPermission::X | MapPermission::U,
+        );
+    }
+    for (i, byte) in src.iter().enumerate() {
+        let va = VirtAddr::from(addr + i);
+        match memory_set.translate(va.floor()) {
+            Some(pte) if pte.is_valid() => pte.ppn().get_bytes_array()[va.page_offset()] = *byte,
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// Called when a probe that failed to unregister because it was active becomes idle,
+/// so that its owner can retry
+pub fn probe_idle() {
+    crate::ebpf::tracepoints::bpf_release_idle_tracepoints();
+}
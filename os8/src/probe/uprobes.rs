@@ -0,0 +1,305 @@
+//! uprobes, probes on the instructions of user programs
+//!
+//! a uprobe is identified by the path of an executable and a file offset in it,
+//! and is armed in every process running that executable by writing breakpoints
+//! over the instruction in the process's own copy of the code.
+//! when it is hit, the pre handler runs, then the displaced instruction is emulated
+//! if it is pc-relative, or executed out of line from a slot of the page at `UPROBE_XOL`.
+//! the slot ends with another breakpoint, which runs the post handler and resumes
+//! after the probed instruction.
+//!
+//! processes are tracked by `uprobe_exec` and `uprobe_fork`, a forked child
+//! gets the breakpoints of its parent with the copy of its memory.
+//! threads stepping out of line are tracked until they hit the breakpoint of the slot
+//! or exit, see `uprobe_exit`.
+use lock::Mutex;
+use alloc::collections::btree_map::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+use super::arch::*;
+use super::kprobes::{Handler, SingleStepType};
+use super::osutils::{current_thread_id, current_user_space, probe_idle, user_offset_to_addr, user_space_write, UserSpace, UPROBE_XOL};
+use super::{KProbeArgs, TrapFrame};
+
+const C_EBREAK: [u8; 2] = [0x02, 0x90];
+/// displaced instruction followed by `c.ebreak`
+const XOL_SLOT_SIZE: usize = 8;
+const XOL_SLOTS: usize = PAGE_SIZE / XOL_SLOT_SIZE;
+
+struct UProbe {
+    /// virtual address of the probed instruction
+    addr: usize,
+    insn: u32,
+    insn_len: usize,
+    emulate: bool,
+    /// index of the out of line slot in `UPROBE_XOL`
+    slot: usize,
+    pre_handler: Arc<Handler>,
+    post_handler: Option<Arc<Handler>>,
+    user_data: usize,
+    active_count: usize,
+    /// an unregistration failed while active, `probe_idle` is called once it is not
+    unregister_pending: bool,
+}
+
+lazy_static! {
+    /// (path, offset) -> probe instance
+    static ref UPROBES: Mutex<BTreeMap<(String, usize), UProbe>> = Mutex::new(BTreeMap::new());
+    /// processes that uprobes are armed in, with the path of their executable
+    static ref UPROBE_SPACES: Mutex<Vec<(String, UserSpace)>> = Mutex::new(Vec::new());
+    /// (pid, tid) -> probe whose instruction the thread is executing out of line
+    static ref UPROBE_STEPS: Mutex<BTreeMap<(usize, usize), (String, usize)>> = Mutex::new(BTreeMap::new());
+}
+
+/// paths are matched without the leading `/`, the file system is flat
+fn normalize_path(path: &str) -> &str {
+    path.trim_start_matches('/')
+}
+
+fn slot_addr(slot: usize) -> usize {
+    UPROBE_XOL + slot * XOL_SLOT_SIZE
+}
+
+impl UProbe {
+    fn insn_bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(&self.insn as *const u32 as *const u8, self.insn_len) }
+    }
+
+    /// whether the probe just became idle with an unregistration pending
+    fn finish(&mut self) -> bool {
+        self.active_count == 0 && core::mem::take(&mut self.unregister_pending)
+    }
+
+    pub fn arm(&self, space: &UserSpace) -> bool {
+        if !self.emulate {
+            // fill the out of line slot first, the breakpoint may be hit as soon as it is written
+            let mut slot = [0u8; XOL_SLOT_SIZE];
+            slot[..self.insn_len].copy_from_slice(self.insn_bytes());
+            slot[self.insn_len..self.insn_len + 2].copy_from_slice(&C_EBREAK);
+            if !user_space_write(space, slot_addr(self.slot), &slot) {
+                return false;
+            }
+        }
+        let mut breakpoints = [0u8; 4];
+        for bp in breakpoints.chunks_mut(2) {
+            bp.copy_from_slice(&C_EBREAK);
+        }
+        user_space_write(space, self.addr, &breakpoints[..self.insn_len])
+    }
+
+    pub fn disarm(&self, space: &UserSpace) {
+        // change to original instruction
+        user_space_write(space, self.addr, self.insn_bytes());
+    }
+}
+
+/// drop the spaces of exited processes
+fn live_spaces(spaces: &mut Vec<(String, UserSpace)>) {
+    spaces.retain(|(_, space)| space.strong_count() > 0);
+}
+
+/// called after a process loads the executable `path`, arms the uprobes of `path` in it
+pub fn uprobe_exec(space: UserSpace, path: &str) {
+    let path = normalize_path(path);
+    // lock UPROBES first like the others
+    let map = UPROBES.lock();
+    let mut spaces = UPROBE_SPACES.lock();
+    live_spaces(&mut spaces);
+    spaces.retain(|(_, other)| !other.ptr_eq(&space));
+    for ((probe_path, _), probe) in map.iter() {
+        if probe_path == path && !probe.arm(&space) {
+            warn!("failed to arm uprobe at {:#x} of {}", probe.addr, path);
+        }
+    }
+    spaces.push((String::from(path), space));
+}
+
+/// called after `child` is forked from `parent`, the armed breakpoints are copied with the memory
+pub fn uprobe_fork(parent: &UserSpace, child: UserSpace) {
+    let mut spaces = UPROBE_SPACES.lock();
+    if let Some((path, _)) = spaces.iter().find(|(_, space)| space.ptr_eq(parent)) {
+        let path = path.clone();
+        spaces.push((path, child));
+    }
+}
+
+/// entry of ebreak trap from user, returns whether this event is handled
+/// returning false means the ebreak dosen't belong to uprobes
+pub fn uprobe_trap_handler(tf: &mut TrapFrame) -> bool {
+    let pc = get_trapframe_pc(tf);
+    let mut map = UPROBES.lock();
+
+    // post_handler stage, the breakpoint after the out of line instruction,
+    // only for threads sent to the slot by the probe, not those jumping there by themselves
+    if pc >= UPROBE_XOL && pc < UPROBE_XOL + PAGE_SIZE {
+        let thread = current_thread_id();
+        let mut steps = UPROBE_STEPS.lock();
+        let key = match steps.get(&thread) {
+            Some(key) => key.clone(),
+            None => return false,
+        };
+        let probe = match map.get_mut(&key) {
+            Some(probe) if pc == slot_addr(probe.slot) + probe.insn_len => probe,
+            _ => return false,
+        };
+        steps.remove(&thread);
+        drop(steps);
+        probe.active_count -= 1;
+        let idle = probe.finish();
+        let post_handler = probe.post_handler.clone();
+        let user_data = probe.user_data;
+        set_trapframe_pc(tf, probe.addr + probe.insn_len);
+        drop(map);
+        if let Some(handler) = post_handler {
+            let _ = handler(tf, user_data);
+        }
+        if idle {
+            probe_idle();
+        }
+        return true;
+    }
+
+    let space = current_user_space();
+    let path = match UPROBE_SPACES.lock().iter().find(|(_, other)| other.ptr_eq(&space)) {
+        Some((path, _)) => path.clone(),
+        None => return false,
+    };
+    let (key, probe) = match map.iter_mut().find(|((probe_path, _), probe)| *probe_path == path && probe.addr == pc) {
+        Some((key, probe)) => (key.clone(), probe),
+        None => return false,
+    };
+    // breakpoint hit for the first time
+    let pre_handler = probe.pre_handler.clone();
+    let post_handler = probe.post_handler.clone();
+    let user_data = probe.user_data;
+    let insn = probe.insn;
+    let emulate = probe.emulate;
+    let slot = probe.slot;
+    let mut idle = false;
+    if !emulate {
+        probe.active_count += 1;
+        // a thread leaving a slot without hitting its breakpoint has abandoned that step
+        if let Some(abandoned) = UPROBE_STEPS.lock().insert(current_thread_id(), key) {
+            if let Some(probe) = map.get_mut(&abandoned) {
+                probe.active_count -= 1;
+                idle = probe.finish();
+            }
+        }
+    }
+    drop(map);
+    if idle {
+        probe_idle();
+    }
+    let _ = pre_handler(tf, user_data);
+    // emulate and return if instruction is emulated
+    if emulate {
+        emulate_execution(tf, &insn as *const u32 as usize, pc);
+        if let Some(handler) = post_handler {
+            let _ = handler(tf, user_data);
+        }
+        return true;
+    }
+    // redirect to the out of line slot
+    // return to slot to execute -> ebreak in slot -> post_handler
+    set_trapframe_pc(tf, slot_addr(slot));
+    true
+}
+
+/// called when a thread exits, ends its step out of line if it has one,
+/// the exit of the main thread (tid 0) ends the steps of all threads of the process
+pub fn uprobe_exit(pid: usize, tid: usize) {
+    let mut map = UPROBES.lock();
+    let mut steps = UPROBE_STEPS.lock();
+    let mut idle = false;
+    steps.retain(|&(step_pid, step_tid), key| {
+        if step_pid != pid || (tid != 0 && step_tid != tid) {
+            return true;
+        }
+        if let Some(probe) = map.get_mut(key) {
+            probe.active_count -= 1;
+            idle |= probe.finish();
+        }
+        false
+    });
+    drop(steps);
+    drop(map);
+    if idle {
+        probe_idle();
+    }
+}
+
+/// register uprobe with args at the instruction at `offset` of the executable `path`
+/// multiple uprobes at the same instruction is not supported for now
+/// possible errors: uprobe already exist, `offset` is not an instruction in an executable segment,
+/// target instruction is not supported, out of XOL slots
+pub fn register_uprobe(path: &str, offset: usize, args: KProbeArgs) -> bool {
+    let path = normalize_path(path);
+    let mut map = UPROBES.lock();
+    let key = (String::from(path), offset);
+    if map.contains_key(&key) {
+        return false;
+    }
+    let (addr, insn) = match user_offset_to_addr(path, offset) {
+        Some(found) => found,
+        None => return false,
+    };
+    let insn_len = get_insn_length(&insn as *const u32 as usize);
+    let insn_type = get_insn_type(&insn as *const u32 as usize);
+    if (insn_len != 2 && insn_len != 4) || insn_type == SingleStepType::Unsupported {
+        return false;
+    }
+    let slot = match (0..XOL_SLOTS).find(|slot| map.values().all(|probe| probe.slot != *slot)) {
+        Some(slot) => slot,
+        None => return false,
+    };
+
+    let probe = UProbe {
+        addr,
+        insn: if insn_len == 2 { insn & 0xffff } else { insn },
+        insn_len,
+        emulate: insn_type == SingleStepType::Emulate,
+        slot,
+        pre_handler: args.pre_handler,
+        post_handler: args.post_handler,
+        user_data: args.user_data,
+        active_count: 0,
+        unregister_pending: false,
+    };
+    let mut spaces = UPROBE_SPACES.lock();
+    live_spaces(&mut spaces);
+    for (space_path, space) in spaces.iter() {
+        if space_path == path && !probe.arm(space) {
+            warn!("failed to arm uprobe at {:#x} of {}", addr, path);
+        }
+    }
+    map.insert(key, probe);
+    true
+}
+
+/// unregister uprobe at `offset` of the executable `path`
+/// possible errors: uprobe not exist, uprobe is still active(post handler not executed)
+pub fn unregister_uprobe(path: &str, offset: usize) -> bool {
+    let key = (String::from(normalize_path(path)), offset);
+    let mut map = UPROBES.lock();
+    if let Some(probe) = map.get_mut(&key) {
+        if probe.active_count > 0 {
+            probe.unregister_pending = true;
+            false
+        } else {
+            let mut spaces = UPROBE_SPACES.lock();
+            live_spaces(&mut spaces);
+            for (space_path, space) in spaces.iter() {
+                if *space_path == key.0 {
+                    probe.disarm(space);
+                }
+            }
+            map.remove(&key).unwrap();
+            true
+        }
+    } else {
+        false
+    }
+}
@@ -0,0 +1,38 @@
+//! Kernel pseudo-random number generator
+//!
+//! A SplitMix64 stream seeded from `mtime` at boot. The state is a single
+//! atomic counter, so it can be used anywhere, including eBPF programs run
+//! by probes. It is not cryptographically secure.
+
+use crate::timer::get_time;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+const GOLDEN_GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+static STATE: AtomicU64 = AtomicU64::new(GOLDEN_GAMMA);
+
+/// seed the generator with the current time
+pub fn init() {
+    STATE.store(get_time() as u64 ^ GOLDEN_GAMMA, Ordering::Relaxed);
+}
+
+/// the next random u64
+pub fn next_u64() -> u64 {
+    let mut z = STATE.fetch_add(GOLDEN_GAMMA, Ordering::Relaxed).wrapping_add(GOLDEN_GAMMA);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// the next random u32
+pub fn next_u32() -> u32 {
+    (next_u64() >> 32) as u32
+}
+
+/// fill `buf` with random bytes
+pub fn fill_bytes(buf: &mut [u8]) {
+    for chunk in buf.chunks_mut(8) {
+        let bytes = next_u64().to_ne_bytes();
+        chunk.copy_from_slice(&bytes[..chunk.len()]);
+    }
+}
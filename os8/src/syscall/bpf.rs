@@ -10,7 +10,7 @@
 use super::*;
 
 use crate::ebpf::{
-    consts::BpfCommand,
+    consts::{BpfCommand, SECCOMP_SET_MODE_FILTER},
     osutil::*,
 };
 use core::convert::TryFrom;
@@ -27,10 +27,23 @@ pub fn sys_bpf(cmd: isize, bpf_attr: usize , size: usize) -> isize {
             BPF_MAP_UPDATE_ELEM => sys_bpf_map_update_elem(ptr, size),
             BPF_MAP_DELETE_ELEM => sys_bpf_map_delete_elem(ptr, size),
             BPF_MAP_GET_NEXT_KEY => sys_bpf_map_get_next_key(ptr, size),
-            BPF_PROG_LOAD => todo!(),
+            BPF_PROG_LOAD => sys_bpf_program_load(ptr, size),
+            BPF_OBJ_PIN => sys_bpf_obj_pin(ptr, size),
+            BPF_OBJ_GET => sys_bpf_obj_get(ptr, size),
             BPF_PROG_ATTACH => sys_bpf_program_attach(ptr, size),
             BPF_PROG_DETACH => sys_bpf_program_detach(ptr, size),
+            BPF_PROG_GET_NEXT_ID => sys_bpf_obj_get_next_id(ptr, size, false),
+            BPF_MAP_GET_NEXT_ID => sys_bpf_obj_get_next_id(ptr, size, true),
+            BPF_PROG_GET_FD_BY_ID => sys_bpf_obj_get_fd_by_id(ptr, size, false),
+            BPF_MAP_GET_FD_BY_ID => sys_bpf_obj_get_fd_by_id(ptr, size, true),
+            BPF_OBJ_GET_INFO_BY_FD => sys_bpf_obj_get_info_by_fd(ptr, size),
+            BPF_MAP_LOOKUP_AND_DELETE_ELEM => sys_bpf_map_lookup_and_delete_elem(ptr, size),
+            BPF_MAP_FREEZE => sys_bpf_map_freeze(ptr, size),
+            BPF_MAP_LOOKUP_BATCH => sys_bpf_map_lookup_batch(ptr, size),
+            BPF_MAP_UPDATE_BATCH => sys_bpf_map_update_batch(ptr, size),
+            BPF_MAP_DELETE_BATCH => sys_bpf_map_delete_batch(ptr, size),
             BPF_PROG_LOAD_EX => sys_preprocess_bpf_program_load_ex(ptr, size),
+            BPF_RINGBUF_READ => sys_bpf_ringbuf_read(ptr, size),
         };
         if ret < 0 {
             -1
@@ -41,3 +54,18 @@ pub fn sys_bpf(cmd: isize, bpf_attr: usize , size: usize) -> isize {
         -1
     }
 }
+
+/// seccomp(2), installs a syscall filter on the calling process
+///
+/// only `SECCOMP_SET_MODE_FILTER` without flags is supported,
+/// `args` is the fd of a `BPF_PROG_TYPE_SYSCALL_FILTER` program instead of a cBPF program
+pub fn sys_seccomp(op: usize, flags: usize, args: usize) -> isize {
+    if op != SECCOMP_SET_MODE_FILTER || flags != 0 {
+        return -1;
+    }
+    if sys_bpf_syscall_filter_install(args as u32) < 0 {
+        -1
+    } else {
+        0
+    }
+}
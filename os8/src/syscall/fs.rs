@@ -1,9 +1,11 @@
 //! File and filesystem-related syscalls
 
+use crate::ebpf::{bpf_obj_unpin, BPF_FS_ROOT};
 use crate::fs::make_pipe;
 use crate::fs::open_file;
 use crate::fs::OpenFlags;
 use crate::fs::Stat;
+use crate::fs::{Urandom, URANDOM_PATH};
 use crate::mm::translated_byte_buffer;
 use crate::mm::translated_refmut;
 use crate::mm::translated_str;
@@ -50,6 +52,12 @@ pub fn sys_open(path: *const u8, flags: u32) -> isize {
     let process = current_process();
     let token = current_user_token();
     let path = translated_str(token, path);
+    if path == URANDOM_PATH {
+        let mut inner = process.inner_exclusive_access();
+        let fd = inner.alloc_fd();
+        inner.fd_table[fd] = Some(Arc::new(Urandom));
+        return fd as isize;
+    }
     if let Some(inode) = open_file(path.as_str(), OpenFlags::from_bits(flags).unwrap()) {
         let mut inner = process.inner_exclusive_access();
         let fd = inner.alloc_fd();
@@ -109,6 +117,24 @@ pub fn sys_linkat(_old_name: *const u8, _new_name: *const u8) -> isize {
     -1
 }
 
-pub fn sys_unlinkat(_name: *const u8) -> isize {
+pub fn sys_unlinkat(name: *const u8) -> isize {
+    let token = current_user_token();
+    let path = translated_str(token, name);
+    // pinned BPF objects are removed like files
+    if path.starts_with(BPF_FS_ROOT) {
+        return match bpf_obj_unpin(&path) {
+            Ok(_) => 0,
+            Err(_) => -1,
+        };
+    }
     -1
 }
+
+/// fill `len` bytes of `buf` with random bytes, `flags` are ignored since it never blocks
+pub fn sys_getrandom(buf: *mut u8, len: usize, _flags: u32) -> isize {
+    let token = current_user_token();
+    for buffer in translated_byte_buffer(token, buf, len) {
+        crate::random::fill_bytes(buffer);
+    }
+    len as isize
+}
@@ -45,6 +45,8 @@ const SYSCALL_SEMAPHORE_DOWN: usize = 470;
 const SYSCALL_CONDVAR_CREATE: usize = 471;
 const SYSCALL_CONDVAR_SIGNAL: usize = 472;
 const SYSCALL_CONDVAR_WAIT: usize = 473;
+const SYSCALL_SECCOMP: usize = 277;
+const SYSCALL_GETRANDOM: usize = 278;
 const SYSCALL_BPF: usize = 280;
 
 pub mod fs;
@@ -58,10 +60,32 @@ use fs::*;
 use process::*;
 use sync::*;
 use thread::*;
-use bpf::sys_bpf;
+use bpf::{sys_bpf, sys_seccomp};
+use crate::ebpf::tracepoints::{bpf_syscall_enter, bpf_syscall_exit, bpf_syscall_filter, SyscallFilterAction};
+use crate::task::exit_current_and_run_next;
 
 /// handle syscall exception with `syscall_id` and other arguments
+///
+/// runs eBPF programs attached to `tracepoint$sys_enter` and `tracepoint$sys_exit` around the call,
+/// after the syscall filters of the process allow it
 pub fn syscall(syscall_id: usize, args: [usize; 4]) -> isize {
+    match bpf_syscall_filter(syscall_id, &args) {
+        SyscallFilterAction::Allow => (),
+        SyscallFilterAction::Errno(errno) => return -(errno as isize),
+        SyscallFilterAction::Kill => {
+            println!("[kernel] Syscall {} denied by filter, core dumped.", syscall_id);
+            // SIGSYS exit code
+            exit_current_and_run_next(-31);
+            panic!("Unreachable in syscall!");
+        }
+    }
+    bpf_syscall_enter(syscall_id, &args);
+    let ret = syscall_dispatch(syscall_id, args);
+    bpf_syscall_exit(syscall_id, &args, ret);
+    ret
+}
+
+fn syscall_dispatch(syscall_id: usize, args: [usize; 4]) -> isize {
     match syscall_id {
         SYSCALL_DUP => sys_dup(args[0]),
         SYSCALL_LINKAT => sys_linkat(args[1] as *const u8, args[3] as *const u8),
@@ -81,7 +105,7 @@ pub fn syscall(syscall_id: usize, args: [usize; 4]) -> isize {
         SYSCALL_EXEC => sys_exec(args[0] as *const u8, args[1] as *const usize),
         SYSCALL_WAITPID => sys_waitpid(args[0] as isize, args[1] as *mut i32),
         SYSCALL_GET_TIME => sys_get_time(args[0] as *mut TimeVal, args[1]),
-        SYSCALL_MMAP => sys_mmap(args[0], args[1], args[2]),
+        SYSCALL_MMAP => sys_mmap(args[0], args[1], args[2], args[3]),
         SYSCALL_MUNMAP => sys_munmap(args[0], args[1]),
         SYSCALL_SET_PRIORITY => sys_set_priority(args[0] as isize),
         SYSCALL_TASK_INFO => sys_task_info(args[0] as *mut TaskInfo),
@@ -98,6 +122,8 @@ pub fn syscall(syscall_id: usize, args: [usize; 4]) -> isize {
         SYSCALL_CONDVAR_CREATE => sys_condvar_create(args[0]),
         SYSCALL_CONDVAR_SIGNAL => sys_condvar_signal(args[0]),
         SYSCALL_CONDVAR_WAIT => sys_condvar_wait(args[0], args[1]),
+        SYSCALL_SECCOMP => sys_seccomp(args[0], args[1], args[2]),
+        SYSCALL_GETRANDOM => sys_getrandom(args[0] as *mut u8, args[1], args[2] as u32),
         SYSCALL_BPF => sys_bpf(args[0] as isize, args[1] as usize, args[2] as usize),
         _ => panic!("Unsupported syscall_id: {}", syscall_id),
     }
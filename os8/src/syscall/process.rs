@@ -1,8 +1,9 @@
 //! Process management syscalls
 
-use crate::config::MAX_SYSCALL_NUM;
+use crate::config::{MAX_SYSCALL_NUM, PAGE_SIZE};
+use crate::ebpf::map::bpf_map_mmap;
 use crate::fs::{open_file, OpenFlags};
-use crate::mm::{translated_ref, translated_refmut, translated_str};
+use crate::mm::{translated_ref, translated_refmut, translated_str, VirtAddr};
 use crate::task::{
     current_process, current_task, current_user_token, exit_current_and_run_next,
     suspend_current_and_run_next, TaskStatus,
@@ -76,7 +77,7 @@ pub fn sys_exec(path: *const u8, mut args: *const usize) -> isize {
         let all_data = app_inode.read_all();
         let process = current_process();
         let argc = args_vec.len();
-        process.exec(all_data.as_slice(), args_vec);
+        process.exec(path.as_str(), all_data.as_slice(), args_vec);
         argc as isize
     } else {
         -1
@@ -143,12 +144,28 @@ pub fn sys_set_priority(_prio: isize) -> isize {
     -1
 }
 
-pub fn sys_mmap(_start: usize, _len: usize, _port: usize) -> isize {
-    -1
+/// only BPF arrays created with `BPF_F_MMAPABLE` can be mapped for now, `fd` is the map
+pub fn sys_mmap(start: usize, len: usize, port: usize, fd: usize) -> isize {
+    match bpf_map_mmap(fd as u32, start, len, port) {
+        Ok(_) => 0,
+        Err(_) => -1,
+    }
 }
 
-pub fn sys_munmap(_start: usize, _len: usize) -> isize {
-    -1
+/// unmap an area mapped by `sys_mmap`, the range must be the whole area
+pub fn sys_munmap(start: usize, len: usize) -> isize {
+    let end = match start.checked_add(len) {
+        Some(end) if start % PAGE_SIZE == 0 && len != 0 => end,
+        _ => return -1,
+    };
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let start_vpn = VirtAddr::from(start).floor();
+    let end_vpn = VirtAddr::from(end).ceil();
+    match inner.memory_set.remove_shared_area(start_vpn, end_vpn) {
+        true => 0,
+        false => -1,
+    }
 }
 
 //
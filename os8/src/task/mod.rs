@@ -23,6 +23,7 @@ mod task;
 pub use crate::syscall::process::TaskInfo;
 use crate::{
     fs::{open_file, OpenFlags},
+    probe::uprobes::uprobe_exit,
     task::id::TaskUserRes,
 };
 use alloc::{sync::Arc, vec::Vec};
@@ -32,7 +33,7 @@ pub use kthread::kernel_stackful_coroutine_test;
 use lazy_static::*;
 pub use manager::add_task;
 use manager::fetch_task;
-use process::ProcessControlBlock;
+pub use process::ProcessControlBlock;
 pub use processor::{
     current_process, current_task, current_trap_cx, current_trap_cx_user_va, current_user_token,
     run_tasks, schedule, take_current_task,
@@ -87,6 +88,7 @@ pub fn exit_current_and_run_next(exit_code: i32) {
     drop(task_inner);
     drop(task);
     // debug!("task {} dropped", tid);
+    uprobe_exit(process.getpid(), tid);
 
     if tid == 0 {
         let mut process_inner = process.inner_exclusive_access();
@@ -147,7 +149,7 @@ lazy_static! {
     pub static ref INITPROC: Arc<ProcessControlBlock> = {
         let inode = open_file("ch8b_initproc", OpenFlags::RDONLY).unwrap();
         let v = inode.read_all();
-        ProcessControlBlock::new(v.as_slice())
+        ProcessControlBlock::new("ch8b_initproc", v.as_slice())
     };
 }
 
@@ -2,6 +2,8 @@ use super::id::RecycleAllocator;
 use super::{add_task, pid_alloc, PidHandle, TaskControlBlock};
 use crate::fs::{File, Stdin, Stdout};
 use crate::mm::{translated_refmut, MemorySet, KERNEL_SPACE};
+use crate::probe::uprobes::{uprobe_exec, uprobe_fork};
+use crate::ebpf::program::BpfProgram;
 use crate::sync::{Condvar, Mutex, Semaphore, UPSafeCell};
 use crate::trap::{trap_handler, TrapContext};
 use alloc::string::String;
@@ -14,9 +16,16 @@ pub struct ProcessControlBlock {
     // immutable
     pub pid: PidHandle,
     // mutable
+    /// name of the executable, kept out of `inner` so that probes can read it
+    pub name: UPSafeCell<String>,
     inner: UPSafeCell<ProcessControlBlockInner>,
 }
 
+/// the executable name is the last component of its path
+fn exe_name(path: &str) -> String {
+    String::from(path.rsplit('/').next().unwrap_or(path))
+}
+
 // LAB5 HINT: you may add data structures for deadlock detection here
 pub struct ProcessControlBlockInner {
     pub is_zombie: bool,
@@ -30,6 +39,8 @@ pub struct ProcessControlBlockInner {
     pub mutex_list: Vec<Option<Arc<dyn Mutex>>>,
     pub semaphore_list: Vec<Option<Arc<Semaphore>>>,
     pub condvar_list: Vec<Option<Arc<Condvar>>>,
+    /// eBPF programs deciding whether each syscall may run, see `bpf_syscall_filter`
+    pub syscall_filters: Vec<Arc<BpfProgram>>,
 }
 
 impl ProcessControlBlockInner {
@@ -69,14 +80,20 @@ impl ProcessControlBlock {
         self.inner.exclusive_access()
     }
 
+    /// Used by probes, which may run while the inner data is borrowed.
+    pub fn try_inner_exclusive_access(&self) -> Option<RefMut<'_, ProcessControlBlockInner>> {
+        self.inner.try_exclusive_access()
+    }
+
     // LAB5 HINT: How to initialize deadlock data structures?
-    pub fn new(elf_data: &[u8]) -> Arc<Self> {
+    pub fn new(path: &str, elf_data: &[u8]) -> Arc<Self> {
         // memory_set with elf program headers/trampoline/trap context/user stack
         let (memory_set, ustack_base, entry_point) = MemorySet::from_elf(elf_data);
         // allocate a pid
         let pid_handle = pid_alloc();
         let process = Arc::new(Self {
             pid: pid_handle,
+            name: unsafe { UPSafeCell::new(exe_name(path)) },
             inner: unsafe {
                 UPSafeCell::new(ProcessControlBlockInner {
                     is_zombie: false,
@@ -97,6 +114,7 @@ impl ProcessControlBlock {
                     mutex_list: Vec::new(),
                     semaphore_list: Vec::new(),
                     condvar_list: Vec::new(),
+                    syscall_filters: Vec::new(),
                 })
             },
         });
@@ -123,6 +141,8 @@ impl ProcessControlBlock {
         let mut process_inner = process.inner_exclusive_access();
         process_inner.tasks.push(Some(Arc::clone(&task)));
         drop(process_inner);
+        // arm the uprobes of this executable
+        uprobe_exec(Arc::downgrade(&process), path);
         // add main thread to scheduler
         add_task(task);
         process
@@ -131,11 +151,12 @@ impl ProcessControlBlock {
     // LAB5 HINT: How to initialize deadlock data structures?
     /// Load a new elf to replace the original application address space and start execution
     /// Only support processes with a single thread.
-    pub fn exec(self: &Arc<Self>, elf_data: &[u8], args: Vec<String>) {
+    pub fn exec(self: &Arc<Self>, path: &str, elf_data: &[u8], args: Vec<String>) {
         assert_eq!(self.inner_exclusive_access().thread_count(), 1);
         // memory_set with elf program headers/trampoline/trap context/user stack
         let (memory_set, ustack_base, entry_point) = MemorySet::from_elf(elf_data);
         let new_token = memory_set.token();
+        *self.name.exclusive_access() = exe_name(path);
         // substitute memory_set
         self.inner_exclusive_access().memory_set = memory_set;
         // then we alloc user resource for main thread again
@@ -181,6 +202,8 @@ impl ProcessControlBlock {
         trap_cx.x[10] = args.len();
         trap_cx.x[11] = argv_base;
         *task_inner.get_trap_cx() = trap_cx;
+        // arm the uprobes of the new executable
+        uprobe_exec(Arc::downgrade(self), path);
     }
 
     // LAB5 HINT: How to initialize deadlock data structures?
@@ -205,6 +228,7 @@ impl ProcessControlBlock {
         // create child process pcb
         let child = Arc::new(Self {
             pid,
+            name: unsafe { UPSafeCell::new(self.name.exclusive_access().clone()) },
             inner: unsafe {
                 UPSafeCell::new(ProcessControlBlockInner {
                     is_zombie: false,
@@ -218,11 +242,15 @@ impl ProcessControlBlock {
                     mutex_list: Vec::new(),
                     semaphore_list: Vec::new(),
                     condvar_list: Vec::new(),
+                    // filters are inherited
+                    syscall_filters: parent.syscall_filters.clone(),
                 })
             },
         });
         // add child
         parent.children.push(Arc::clone(&child));
+        // the breakpoints of uprobes are copied with the memory_set
+        uprobe_fork(&Arc::downgrade(self), Arc::downgrade(&child));
         // create main thread of child process
         let task = Arc::new(TaskControlBlock::new(
             Arc::clone(&child),
@@ -259,6 +287,7 @@ impl ProcessControlBlock {
         let memory_set = MemorySet::kernel_copy();
         let process = Arc::new(ProcessControlBlock {
             pid: super::pid_alloc(),
+            name: unsafe { UPSafeCell::new(String::from("kernel")) },
             inner: unsafe {
                 UPSafeCell::new(ProcessControlBlockInner {
                     is_zombie: false,
@@ -272,6 +301,7 @@ impl ProcessControlBlock {
                     mutex_list: Vec::new(),
                     semaphore_list: Vec::new(),
                     condvar_list: Vec::new(),
+                    syscall_filters: Vec::new(),
                 })
             },
         });
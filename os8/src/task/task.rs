@@ -89,6 +89,11 @@ impl TaskControlBlock {
         inner
     }
 
+    /// Used by probes, which may run while the inner data is borrowed.
+    pub fn try_inner_exclusive_access(&self) -> Option<RefMut<'_, TaskControlBlockInner>> {
+        self.inner.try_exclusive_access()
+    }
+
     pub fn get_user_token(&self) -> usize {
         let process = self.process.upgrade().unwrap();
         let inner = process.inner_exclusive_access();
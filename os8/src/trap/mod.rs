@@ -21,6 +21,8 @@ use crate::task::{
     suspend_current_and_run_next,
 };
 use crate::timer::{check_timer, set_next_trigger};
+use crate::ebpf::tracepoints::bpf_perf_event_tick;
+use crate::probe::uprobes_breakpoint_handler;
 use riscv::register::{
     mtvec::TrapMode,
     scause::{self, Exception, Interrupt, Trap},
@@ -88,12 +90,20 @@ pub fn trap_handler() -> ! {
             // page fault exit code
             exit_current_and_run_next(-2);
         }
+        Trap::Exception(Exception::Breakpoint) => {
+            // uprobes resume the application at another pc
+            if !uprobes_breakpoint_handler(current_trap_cx()) {
+                println!("[kernel] Breakpoint in application, core dumped.");
+                exit_current_and_run_next(-3);
+            }
+        }
         Trap::Exception(Exception::IllegalInstruction) => {
             println!("[kernel] IllegalInstruction in application, core dumped.");
             // illegal instruction exit code
             exit_current_and_run_next(-3);
         }
         Trap::Interrupt(Interrupt::SupervisorTimer) => {
+            bpf_perf_event_tick(current_trap_cx().sepc);
             set_next_trigger();
             check_timer();
             suspend_current_and_run_next();